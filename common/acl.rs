@@ -0,0 +1,608 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, RwLock, atomic::{AtomicU64, Ordering}},
+    time::{Duration, Instant},
+};
+use anyhow::Error;
+use volo_gen::volo::example::{
+    GetItemRequest, GetItemResponse, ItemServiceGetItemResultSend, ItemServiceRequestRecv, ItemServiceResponseSend,
+};
+
+use crate::{glob_match, OPCode};
+
+// a session not used for so long has to AUTH again, so that the sessions of the gone clients do not pile up
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+const NOAUTH: &str = "NOAUTH Authentication required.";
+const WRONGPASS: &str = "WRONGPASS invalid username-password pair or user is disabled.";
+
+// the categories of the commands, a user is granted the commands one by one or by the categories, @all stands for all of them
+pub const CATEGORIES: &[&str] = &[
+    "read", "write", "keyspace", "string", "pubsub", "scripting", "transaction", "connection", "admin", "dangerous",
+];
+
+// the name and the categories of each command, the requests with an unknown opcode are refused by the server itself
+pub(crate) const COMMANDS: &[(i32, &str, &[&str])] = &[
+    (OPCode::GET as i32, "get", &["read", "string"]),
+    (OPCode::SET as i32, "set", &["write", "string"]),
+    (OPCode::DEL as i32, "del", &["write", "keyspace"]),
+    (OPCode::PING as i32, "ping", &["connection"]),
+    (OPCode::SUBSCRIBE as i32, "subscribe", &["pubsub"]),
+    (OPCode::PUBLISH as i32, "publish", &["pubsub"]),
+    (OPCode::SCAN as i32, "scan", &["read", "keyspace"]),
+    (OPCode::KEYS as i32, "keys", &["read", "keyspace", "dangerous"]),
+    (OPCode::DBSIZE as i32, "dbsize", &["read", "keyspace"]),
+    (OPCode::FLUSHALL as i32, "flushall", &["write", "keyspace", "dangerous"]),
+    (OPCode::RANDOMKEY as i32, "randomkey", &["read", "keyspace"]),
+    (OPCode::PSUBSCRIBE as i32, "psubscribe", &["pubsub"]),
+    (OPCode::PUNSUBSCRIBE as i32, "punsubscribe", &["pubsub"]),
+    (OPCode::UNSUBSCRIBE as i32, "unsubscribe", &["pubsub"]),
+    (OPCode::POLL as i32, "poll", &["pubsub"]),
+    (OPCode::PUBSUB as i32, "pubsub", &["pubsub"]),
+    (OPCode::CONFIG as i32, "config", &["admin", "dangerous"]),
+    (OPCode::EVAL as i32, "eval", &["scripting"]),
+    (OPCode::EVALSHA as i32, "evalsha", &["scripting"]),
+    (OPCode::SCRIPT as i32, "script", &["scripting"]),
+    (OPCode::FUNCTION as i32, "function", &["write", "scripting"]),
+    (OPCode::FCALL as i32, "fcall", &["scripting"]),
+    (OPCode::FCALLRO as i32, "fcall_ro", &["read", "scripting"]),
+    (OPCode::EXPIRE as i32, "expire", &["write", "keyspace"]),
+    (OPCode::TTL as i32, "ttl", &["read", "keyspace"]),
+    (OPCode::PERSIST as i32, "persist", &["write", "keyspace"]),
+    (OPCode::MEMORY as i32, "memory", &["read"]),
+    (OPCode::SELECT as i32, "select", &["connection"]),
+    (OPCode::MOVE as i32, "move", &["write", "keyspace"]),
+    (OPCode::SWAPDB as i32, "swapdb", &["write", "keyspace", "dangerous"]),
+    (OPCode::FLUSHDB as i32, "flushdb", &["write", "keyspace", "dangerous"]),
+    (OPCode::AUTH as i32, "auth", &["connection"]),
+    (OPCode::ACL as i32, "acl", &["admin", "dangerous"]),
+    (OPCode::FILTER as i32, "filter", &["admin"]),
+    (OPCode::INFO as i32, "info", &["admin"]),
+    (OPCode::SLOWLOG as i32, "slowlog", &["admin", "dangerous"]),
+    // the requests replicated by the master node, the master sends them with its auth-user
+    (OPCode::SETMASTER as i32, "setmaster", &["admin"]),
+    (OPCode::DELMASTER as i32, "delmaster", &["admin"]),
+    (OPCode::FLUSHMASTER as i32, "flushmaster", &["admin"]),
+    (OPCode::EXECMASTER as i32, "execmaster", &["admin"]),
+    (OPCode::FUNCTIONMASTER as i32, "functionmaster", &["admin"]),
+    (OPCode::EXPIREMASTER as i32, "expiremaster", &["admin"]),
+    (OPCode::MULTI as i32, "multi", &["transaction"]),
+    (OPCode::EXEC as i32, "exec", &["transaction"]),
+    (OPCode::WATCH as i32, "watch", &["transaction"]),
+    (OPCode::DISCARD as i32, "discard", &["transaction"]),
+    (OPCode::UNWATCH as i32, "unwatch", &["transaction"]),
+    (OPCode::TOPOLOGY as i32, "topology", &["admin"]),
+    (OPCode::ADDSLAVE as i32, "addslave", &["admin", "dangerous"]),
+    (OPCode::DELSLAVE as i32, "delslave", &["admin", "dangerous"]),
+    (OPCode::REPLACEMASTER as i32, "replacemaster", &["admin", "dangerous"]),
+];
+
+// the keys a request accesses, which must match the key patterns of the user
+fn request_keys(req: &GetItemRequest) -> Vec<&str> {
+    match OPCode::from(req.opcode) {
+        OPCode::GET | OPCode::SET | OPCode::DEL | OPCode::EXPIRE | OPCode::TTL | OPCode::PERSIST | OPCode::MOVE => {
+            vec![req.key_channal.as_str()]
+        },
+        OPCode::WATCH => req.key_channal.split_whitespace().collect(),
+        OPCode::MEMORY if req.key_channal.eq_ignore_ascii_case("usage") => req.value_message.split_whitespace().take(1).collect(),
+        // the value_message is `numkeys [key ...] [arg ...]`
+        OPCode::EVAL | OPCode::EVALSHA | OPCode::FCALL | OPCode::FCALLRO => {
+            let mut args = req.value_message.split_whitespace();
+            let numkeys = args.next().and_then(|numkeys| numkeys.parse::<usize>().ok()).unwrap_or(0);
+            args.take(numkeys).collect()
+        },
+        _ => Vec::new(),
+    }
+}
+
+fn hash_password(password: &str) -> String {
+    sha1_smol::Sha1::from(password).digest().to_string()
+}
+
+// a user is created off, without any password, command or key, and is changed by the rules of ACL SETUSER
+#[derive(Clone, Debug, Default)]
+struct User {
+    enabled: bool,
+    nopass: bool,                       // any password is accepted
+    passwords: BTreeSet<String>,        // the sha1 of the passwords
+    commands: Vec<(bool, String)>,      // the rules such as +get or -@write in order, the last matching one decides
+    patterns: Vec<String>,              // the glob patterns of the keys the user may access
+}
+
+impl User {
+    fn apply(&mut self, rule: &str) -> Result<(), Error> {
+        let invalid = |reason: &str| Error::msg(format!("ERR Error in ACL SETUSER modifier '{}': {}", rule, reason));
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            },
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            },
+            "allkeys" => self.patterns = vec!["*".to_string()],
+            "resetkeys" => self.patterns.clear(),
+            "allcommands" => self.commands = vec![(true, "@all".to_string())],
+            "nocommands" => self.commands = vec![(false, "@all".to_string())],
+            "reset" => *self = User::default(),
+            _ => {
+                let mut chars = rule.chars();
+                let (Some(prefix), arg) = (chars.next(), chars.as_str()) else {
+                    return Err(invalid("Syntax error"));
+                };
+                match prefix {
+                    '>' => {
+                        self.nopass = false;
+                        self.passwords.insert(hash_password(arg));
+                    },
+                    '<' => {
+                        if !self.passwords.remove(&hash_password(arg)) {
+                            return Err(invalid("no such password"));
+                        }
+                    },
+                    '#' => {
+                        if arg.len() != 40 || !arg.chars().all(|c| c.is_ascii_hexdigit()) {
+                            return Err(invalid("The password hash must be exactly 40 characters and contain only lowercase hexadecimal characters"));
+                        }
+                        self.nopass = false;
+                        self.passwords.insert(arg.to_lowercase());
+                    },
+                    '~' if arg == "*" => self.patterns = vec!["*".to_string()],
+                    '~' => self.patterns.push(arg.to_string()),
+                    '+' | '-' => {
+                        let target = arg.to_lowercase();
+                        let known = match target.strip_prefix('@') {
+                            Some(category) => category == "all" || CATEGORIES.contains(&category),
+                            None => COMMANDS.iter().any(|(_, name, _)| *name == target),
+                        };
+                        if !known {
+                            return Err(invalid("Unknown command or category name in ACL"));
+                        }
+                        // +@all and -@all override all the rules before them, and a rule overrides the former one on the same target
+                        match target == "@all" {
+                            true => self.commands.clear(),
+                            false => self.commands.retain(|(_, former)| *former != target),
+                        }
+                        self.commands.push((prefix == '+', target));
+                    },
+                    _ => return Err(invalid("Syntax error")),
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn check_password(&self, password: &str) -> bool {
+        self.enabled && (self.nopass || self.passwords.contains(&hash_password(password)))
+    }
+
+    fn can_run(&self, command: &str, categories: &[&str]) -> bool {
+        self.commands
+            .iter()
+            .rev()
+            .find(|(_, target)| match target.strip_prefix('@') {
+                Some("all") => true,
+                Some(category) => categories.contains(&category),
+                None => target == command,
+            })
+            .is_some_and(|(allowed, _)| *allowed)
+    }
+
+    fn can_access(&self, key: &str) -> bool {
+        self.patterns.iter().any(|pattern| glob_match(pattern, key))
+    }
+
+    // the rules that create the user, in the same form as ACL LIST
+    fn describe(&self) -> String {
+        let mut rules = vec![match self.enabled {
+            true => "on".to_string(),
+            false => "off".to_string(),
+        }];
+        if self.nopass {
+            rules.push("nopass".to_string());
+        }
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        rules.extend(self.patterns.iter().map(|pattern| format!("~{}", pattern)));
+        rules.push(self.describe_commands());
+        rules.join(" ")
+    }
+
+    fn describe_commands(&self) -> String {
+        if self.commands.is_empty() {
+            return "-@all".to_string();
+        }
+        self.commands
+            .iter()
+            .map(|(allowed, target)| match allowed {
+                true => format!("+{}", target),
+                false => format!("-{}", target),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+struct Session {
+    user: String,
+    last_active: AtomicU64,     // the milliseconds since the Acl is created
+}
+
+impl Session {
+    // another thread may have stored a later time than now
+    fn is_idle(&self, now: u64) -> bool {
+        now.saturating_sub(self.last_active.load(Ordering::Relaxed)) >= SESSION_IDLE_TIMEOUT.as_millis() as u64
+    }
+}
+
+// Acl keeps the users and the sessions authenticated by AUTH
+// the users are local to the node, the same aclfile is expected to be given to all the nodes of the cluster,
+// while the users of the proxy are its own, and the proxy runs the requests on the nodes as its auth-user
+pub struct Acl {
+    users: RwLock<HashMap<String, User>>,
+    sessions: RwLock<HashMap<String, Session>>,
+    created: Instant,
+}
+
+impl Default for Acl {
+    fn default() -> Acl {
+        Acl::new(None, None).unwrap()
+    }
+}
+
+impl Acl {
+    // the default user runs any command on any key without a password, unless requirepass is given
+    // each line of the aclfile is `user <name> [rule ...]`, lines starting with # are ignored
+    pub fn new(requirepass: Option<&str>, aclfile: Option<&str>) -> Result<Acl, Error> {
+        let mut default = User::default();
+        for rule in ["on", "nopass", "~*", "+@all"] {
+            default.apply(rule)?;
+        }
+        if let Some(password) = requirepass {
+            default.nopass = false;
+            default.passwords.insert(hash_password(password));
+        }
+        let acl = Acl {
+            users: RwLock::new(HashMap::from([("default".to_string(), default)])),
+            sessions: RwLock::new(HashMap::new()),
+            created: Instant::now(),
+        };
+        if let Some(path) = aclfile {
+            let content = std::fs::read_to_string(path).map_err(|e| Error::msg(format!("Failed to read the aclfile {}: {}", path, e)))?;
+            for (number, line) in content.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let mut words = line.split_whitespace();
+                let result = match (words.next(), words.next()) {
+                    (Some("user"), Some(name)) => acl.set_user(name, words),
+                    _ => Err(Error::msg("the line must be `user <name> [rule ...]`")),
+                };
+                result.map_err(|e| Error::msg(format!("Invalid aclfile {} at line {}: {}", path, number + 1, e)))?;
+            }
+        }
+        Ok(acl)
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.created.elapsed().as_millis() as u64
+    }
+
+    // the rules are applied to a copy of the user, so that an invalid rule leaves the user unchanged
+    fn set_user<'a>(&self, name: &str, rules: impl IntoIterator<Item = &'a str>) -> Result<(), Error> {
+        let mut users = self.users.write().unwrap();
+        let mut user = users.get(name).cloned().unwrap_or_default();
+        for rule in rules {
+            user.apply(rule)?;
+        }
+        users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    fn authenticate(&self, user: &str, password: &str) -> bool {
+        self.users.read().unwrap().get(user).is_some_and(|user| user.check_password(password))
+    }
+
+    // the user a request runs as: the credentials `<user> <password>` carried by the request, such as those of the other nodes,
+    // then the user the session has authenticated as, then the default user if it needs no password
+    pub(crate) fn user_of(&self, req: &GetItemRequest) -> Result<String, Error> {
+        if let Some(auth) = &req.auth {
+            let (user, password) = auth.split_once(' ').unwrap_or(("default", auth.as_str()));
+            return match self.authenticate(user, password) {
+                true => Ok(user.to_string()),
+                false => Err(Error::msg(WRONGPASS)),
+            };
+        }
+        if let Some(session_id) = &req.session_id {
+            if let Some(session) = self.sessions.read().unwrap().get(session_id.as_str()) {
+                let now = self.now_ms();
+                // a session idle for too long has to AUTH again, even before AUTH of another session drops it
+                if session.is_idle(now) {
+                    return Err(Error::msg(NOAUTH));
+                }
+                session.last_active.fetch_max(now, Ordering::Relaxed);
+                return Ok(session.user.clone());
+            }
+        }
+        match self.users.read().unwrap().get("default").is_some_and(|user| user.enabled && user.nopass) {
+            true => Ok("default".to_string()),
+            false => Err(Error::msg(NOAUTH)),
+        }
+    }
+
+    pub(crate) fn check(&self, name: &str, req: &GetItemRequest) -> Result<(), Error> {
+        let users = self.users.read().unwrap();
+        // the user may have been deleted or disabled since the session authenticated
+        let Some(user) = users.get(name).filter(|user| user.enabled) else {
+            return Err(Error::msg(NOAUTH));
+        };
+        let Some((_, command, categories)) = COMMANDS.iter().find(|(opcode, _, _)| *opcode == req.opcode) else {
+            return Ok(());
+        };
+        // any user may ask who it is and which categories there are
+        if req.opcode == OPCode::ACL as i32 && matches!(req.key_channal.to_lowercase().as_str(), "whoami" | "cat") {
+            return Ok(());
+        }
+        if !user.can_run(command, categories) {
+            return Err(Error::msg(format!("NOPERM User {} has no permissions to run the '{}' command", name, command)));
+        }
+        if request_keys(req).iter().any(|key| !user.can_access(key)) {
+            return Err(Error::msg("NOPERM No permissions to access a key"));
+        }
+        Ok(())
+    }
+
+    // AUTH [username] password, the username is in the key_channal and defaults to the default user
+    fn auth(&self, req: &GetItemRequest) -> Result<String, Error> {
+        let session_id = req.session_id.as_ref().ok_or(Error::msg("ERR AUTH needs a session"))?.to_string();
+        let user = match req.key_channal.trim() {
+            "" => "default",
+            user => user,
+        };
+        if !self.authenticate(user, &req.value_message) {
+            return Err(Error::msg(WRONGPASS));
+        }
+        let mut sessions = self.sessions.write().unwrap();
+        // the time is read under the write lock, so that no user_of stores a later one meanwhile
+        let now = self.now_ms();
+        sessions.retain(|_, session| !session.is_idle(now));
+        sessions.insert(session_id, Session { user: user.to_string(), last_active: AtomicU64::new(now) });
+        Ok("OK".to_string())
+    }
+
+    // ACL SETUSER/GETUSER/DELUSER/LIST/WHOAMI/CAT, the subcommand is in the key_channal and its args in the value_message
+    fn command(&self, user: &str, req: &GetItemRequest) -> Result<String, Error> {
+        let args: Vec<&str> = req.value_message.split_whitespace().collect();
+        match (req.key_channal.to_lowercase().as_str(), args.as_slice()) {
+            ("whoami", []) => Ok(user.to_string()),
+            ("cat", []) => Ok(CATEGORIES.join("\n")),
+            ("cat", [category]) => match CATEGORIES.contains(category) {
+                true => Ok(COMMANDS
+                    .iter()
+                    .filter(|(_, _, categories)| categories.contains(category))
+                    .map(|(_, name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join("\n")),
+                false => Err(Error::msg(format!("ERR Unknown category '{}'", category))),
+            },
+            ("setuser", [name, rules @ ..]) => {
+                self.set_user(name, rules.iter().copied())?;
+                Ok("OK".to_string())
+            },
+            // the fields and their values in turn, like redis
+            ("getuser", [name]) => {
+                let users = self.users.read().unwrap();
+                let user = users.get(*name).ok_or(Error::msg("(nil)"))?;
+                let mut flags = vec![match user.enabled {
+                    true => "on",
+                    false => "off",
+                }];
+                if user.nopass {
+                    flags.push("nopass");
+                }
+                let fields = [
+                    ("flags", flags.join(" ")),
+                    ("passwords", user.passwords.iter().cloned().collect::<Vec<_>>().join(" ")),
+                    ("commands", user.describe_commands()),
+                    ("keys", user.patterns.iter().map(|pattern| format!("~{}", pattern)).collect::<Vec<_>>().join(" ")),
+                ];
+                Ok(fields.iter().map(|(name, value)| format!("{}\n{}", name, value)).collect::<Vec<_>>().join("\n"))
+            },
+            ("deluser", names) if !names.is_empty() => {
+                if names.contains(&"default") {
+                    return Err(Error::msg("ERR The 'default' user cannot be removed"));
+                }
+                let mut users = self.users.write().unwrap();
+                Ok(names.iter().filter(|name| users.remove(**name).is_some()).count().to_string())
+            },
+            ("list", []) => {
+                let users = self.users.read().unwrap();
+                let mut names: Vec<&String> = users.keys().collect();
+                names.sort();
+                Ok(names.iter().map(|name| format!("user {} {}", name, users[*name].describe())).collect::<Vec<_>>().join("\n"))
+            },
+            _ => Err(Error::msg("ERR Unknown subcommand or wrong number of arguments for ACL")),
+        }
+    }
+}
+
+// AclLayer authenticates the requests and checks the permissions of the user before they reach the server
+// AUTH and ACL are answered by the layer itself
+pub struct AclLayer(pub Arc<Acl>);
+
+impl<S> volo::Layer<S> for AclLayer {
+    type Service = AclService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        AclService { inner, acl: self.0 }
+    }
+}
+
+#[derive(Clone)]
+pub struct AclService<S> {
+    inner: S,
+    acl: Arc<Acl>,
+}
+
+#[volo::service]
+impl<S> volo::Service<volo_thrift::context::ServerContext, ItemServiceRequestRecv> for AclService<S>
+where
+    S: volo::Service<volo_thrift::context::ServerContext, ItemServiceRequestRecv, Response = ItemServiceResponseSend, Error = Error>
+        + Send
+        + Sync
+        + 'static,
+{
+    async fn call(
+        &self,
+        cx: &mut volo_thrift::context::ServerContext,
+        req: ItemServiceRequestRecv,
+    ) -> Result<ItemServiceResponseSend, Error> {
+        let ItemServiceRequestRecv::GetItem(args) = &req;
+        let result = match OPCode::from(args.req.opcode) {
+            OPCode::AUTH => self.acl.auth(&args.req),
+            opcode => match self.acl.user_of(&args.req).and_then(|user| self.acl.check(&user, &args.req).map(|_| user)) {
+                Ok(user) if opcode == OPCode::ACL => self.acl.command(&user, &args.req),
+                Ok(_) => return self.inner.call(cx, req).await,
+                Err(e) => Err(e),
+            },
+        };
+        let mut resp = GetItemResponse {
+            opcode: args.req.opcode,
+            key_channal: args.req.key_channal.clone(),
+            value_message: " ".into(),
+            success: false,
+        };
+        match result {
+            Ok(message) => {
+                resp.value_message = message.into();
+                resp.success = true;
+            },
+            Err(e) => resp.value_message = e.to_string().into(),
+        }
+        Ok(ItemServiceResponseSend::GetItem(ItemServiceGetItemResultSend::Ok(resp)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::request;
+
+    fn user(rules: &[&str]) -> User {
+        let mut user = User::default();
+        for rule in rules {
+            user.apply(rule).unwrap();
+        }
+        user
+    }
+
+    #[test]
+    fn commands_and_categories() {
+        let reader = user(&["+@read", "-ttl"]);
+        assert!(reader.can_run("get", &["read", "string"]));
+        assert!(!reader.can_run("ttl", &["read", "keyspace"]));
+        assert!(!reader.can_run("set", &["write", "string"]));
+
+        let admin = user(&["+@all", "-@dangerous"]);
+        assert!(admin.can_run("set", &["write", "string"]));
+        assert!(!admin.can_run("keys", &["read", "keyspace", "dangerous"]));
+
+        // +@all overrides the rules before it, and the last rule on a command wins
+        assert!(user(&["-get", "+@all"]).can_run("get", &["read", "string"]));
+        assert!(user(&["-get", "+get"]).can_run("get", &["read", "string"]));
+        assert!(!user(&[]).can_run("get", &["read", "string"]));
+
+        assert!(User::default().apply("+nosuch").is_err());
+        assert!(User::default().apply("-@nosuch").is_err());
+    }
+
+    #[test]
+    fn key_patterns() {
+        let user = user(&["~user:*", "~order:[0-9]"]);
+        assert!(user.can_access("user:1"));
+        assert!(user.can_access("order:7"));
+        assert!(!user.can_access("order:x"));
+        assert!(!user.can_access("session:1"));
+
+        let mut all = self::user(&["~user:*", "~*"]);
+        assert_eq!(all.patterns, vec!["*".to_string()]);
+        all.apply("resetkeys").unwrap();
+        assert!(!all.can_access("user:1"));
+    }
+
+    #[test]
+    fn passwords() {
+        let mut user = user(&["on", ">secret", ">Other"]);
+        assert!(user.check_password("secret"));
+        assert!(user.check_password("Other"));
+        assert!(!user.check_password("other"));
+
+        user.apply("<secret").unwrap();
+        assert!(!user.check_password("secret"));
+        assert!(user.apply("<secret").is_err());
+
+        user.apply("off").unwrap();
+        assert!(!user.check_password("Other"));
+
+        assert!(User::default().apply("#1234").is_err());
+        let hashed = self::user(&["on", &format!("#{}", hash_password("secret").to_uppercase())]);
+        assert!(hashed.check_password("secret"));
+    }
+
+    #[test]
+    fn nopass() {
+        let mut user = user(&["on", ">secret", "nopass"]);
+        assert!(user.passwords.is_empty());
+        assert!(user.check_password("anything"));
+        // a new password turns nopass off again
+        user.apply(">other").unwrap();
+        assert!(!user.check_password("anything"));
+        assert!(user.check_password("other"));
+    }
+
+    #[test]
+    fn reset() {
+        let user = user(&["on", "nopass", "~*", "+@all", "reset"]);
+        assert!(!user.enabled);
+        assert!(!user.nopass);
+        assert!(user.patterns.is_empty());
+        assert!(!user.can_run("get", &["read", "string"]));
+        assert_eq!(user.describe(), "off -@all");
+    }
+
+    #[test]
+    fn invalid_aclfile_reports_the_line() {
+        let path = std::env::temp_dir().join(format!("redis-acl-{}.acl", std::process::id()));
+        std::fs::write(&path, "# users\nuser alice on >secret ~* +@all\nuser bob +nosuch\n").unwrap();
+        let path = path.to_str().unwrap();
+        let error = Acl::new(None, Some(path)).err().unwrap().to_string();
+        std::fs::remove_file(path).unwrap();
+        assert!(error.contains(path), "{}", error);
+        assert!(error.contains("line 3"), "{}", error);
+    }
+
+    #[test]
+    fn auth_alongside_the_sessions_in_use() {
+        let acl = Acl::new(Some("secret"), None).unwrap();
+        let session = |id: &str, opcode: OPCode, value: &str| GetItemRequest { session_id: Some(id.to_string().into()), ..request(opcode, " ", value) };
+        acl.auth(&session("used", OPCode::AUTH, "secret")).unwrap();
+        // the session in use keeps storing the time it is used while the other sessions authenticate
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..10000 {
+                    assert_eq!(acl.user_of(&session("used", OPCode::GET, " ")).unwrap(), "default");
+                }
+            });
+            scope.spawn(|| {
+                for id in 0..10000 {
+                    acl.auth(&session(&id.to_string(), OPCode::AUTH, "secret")).unwrap();
+                }
+            });
+        });
+        assert_eq!(acl.sessions.read().unwrap().len(), 10001);
+        assert_eq!(acl.user_of(&session("unknown", OPCode::GET, " ")).unwrap_err().to_string(), NOAUTH);
+    }
+}
@@ -0,0 +1,333 @@
+use std::sync::{Arc, RwLock};
+use aho_corasick::{AhoCorasick, MatchKind};
+use anyhow::Error;
+use volo_gen::volo::example::{
+    GetItemRequest, GetItemResponse, ItemServiceGetItemResultSend, ItemServiceRequestRecv, ItemServiceResponseSend,
+};
+
+use crate::OPCode;
+
+// the words filtered when no filter-file is given
+const DEFAULT_WORDS: &[&str] = &["傻逼"];
+
+// what the FilterLayer does with a request containing a filtered word
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FilterMode {
+    #[default]
+    Reject,     // refuse the request
+    Mask,       // replace each character of the words with *, and let the request through
+    Log,        // only log the word
+}
+
+const MODES: &[(FilterMode, &str)] = &[
+    (FilterMode::Reject, "reject"),
+    (FilterMode::Mask, "mask"),
+    (FilterMode::Log, "log"),
+];
+
+impl FilterMode {
+    pub fn parse(value: &str) -> Result<FilterMode, Error> {
+        MODES
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(value))
+            .map(|(mode, _)| *mode)
+            .ok_or(Error::msg(format!("Invalid value for filter-mode: {}", value)))
+    }
+}
+
+impl std::fmt::Display for FilterMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = MODES.iter().find(|(mode, _)| mode == self).map(|(_, name)| *name).unwrap_or_default();
+        write!(f, "{}", name)
+    }
+}
+
+// the words along with the automaton matching all of them in a single pass
+struct Words {
+    words: Vec<String>,
+    automaton: AhoCorasick,
+}
+
+impl Words {
+    fn new(words: Vec<String>) -> Result<Words, Error> {
+        let automaton = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostLongest)
+            .ascii_case_insensitive(true)
+            .build(&words)
+            .map_err(|e| Error::msg(format!("Failed to build the filter: {}", e)))?;
+        Ok(Words { words, automaton })
+    }
+
+    // one word per line, blank lines and lines starting with # are ignored
+    fn load(path: Option<&str>) -> Result<Words, Error> {
+        let words = match path {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|e| Error::msg(format!("Failed to read the filter-file {}: {}", path, e)))?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string)
+                .collect(),
+            None => DEFAULT_WORDS.iter().map(|word| word.to_string()).collect(),
+        };
+        Words::new(words).map_err(|e| match path {
+            Some(path) => Error::msg(format!("Invalid filter-file {}: {}", path, e)),
+            None => e,
+        })
+    }
+
+    fn find<'a>(&self, text: &'a str) -> Option<&'a str> {
+        self.automaton.find(text).map(|m| &text[m.range()])
+    }
+
+    fn mask(&self, text: &str) -> String {
+        let mut masked = String::with_capacity(text.len());
+        self.automaton.replace_all_with(text, &mut masked, |_, word, dst| {
+            dst.extend(std::iter::repeat('*').take(word.chars().count()));
+            true
+        });
+        masked
+    }
+}
+
+// ContentFilter keeps the filtered words, which are matched against the key_channal and the value_message of the requests
+// FILTER RELOAD reads the filter-file again, and FILTER MODE changes the mode at runtime
+pub struct ContentFilter {
+    path: Option<String>,
+    words: RwLock<Arc<Words>>,
+    mode: RwLock<FilterMode>,
+}
+
+impl Default for ContentFilter {
+    fn default() -> ContentFilter {
+        ContentFilter::new(None, FilterMode::default()).unwrap()
+    }
+}
+
+impl ContentFilter {
+    pub fn new(path: Option<&str>, mode: FilterMode) -> Result<ContentFilter, Error> {
+        Ok(ContentFilter {
+            path: path.map(str::to_string),
+            words: RwLock::new(Arc::new(Words::load(path)?)),
+            mode: RwLock::new(mode),
+        })
+    }
+
+    pub fn mode(&self) -> FilterMode {
+        *self.mode.read().unwrap()
+    }
+
+    // the old words are kept if the file can not be read
+    pub fn reload(&self) -> Result<usize, Error> {
+        let words = Words::load(self.path.as_deref())?;
+        let count = words.words.len();
+        *self.words.write().unwrap() = Arc::new(words);
+        Ok(count)
+    }
+
+    // the replicated requests were filtered by the master already, and AUTH carries a password that must not be logged
+    // the proxy refuses the replicated requests of the clients, so they are never exempt there
+    fn is_exempt(req: &GetItemRequest) -> bool {
+        matches!(
+            OPCode::from(req.opcode),
+            OPCode::AUTH | OPCode::FILTER | OPCode::SETMASTER | OPCode::DELMASTER | OPCode::FLUSHMASTER | OPCode::EXECMASTER
+                | OPCode::FUNCTIONMASTER | OPCode::EXPIREMASTER
+        )
+    }
+
+    // apply the mode to the request, the error is the reply to send instead
+    pub fn filter(&self, req: &mut GetItemRequest) -> Result<(), Error> {
+        if ContentFilter::is_exempt(req) {
+            return Ok(());
+        }
+        let words = self.words.read().unwrap().clone();
+        let word = match words.find(&req.key_channal).or_else(|| words.find(&req.value_message)) {
+            Some(word) => word.to_string(),
+            None => return Ok(()),
+        };
+        match self.mode() {
+            FilterMode::Reject => Err(Error::msg(format!("ERR the command contains the filtered word '{}'", word))),
+            FilterMode::Mask => {
+                req.key_channal = words.mask(&req.key_channal).into();
+                req.value_message = words.mask(&req.value_message).into();
+                Ok(())
+            },
+            FilterMode::Log => {
+                tracing::warn!("The command contains the filtered word '{}'", word);
+                Ok(())
+            },
+        }
+    }
+
+    // FILTER RELOAD/MODE/WORDS, the subcommand is in the key_channal and its args in the value_message
+    fn command(&self, req: &GetItemRequest) -> Result<String, Error> {
+        let args: Vec<&str> = req.value_message.split_whitespace().collect();
+        match (req.key_channal.to_lowercase().as_str(), args.as_slice()) {
+            ("reload", []) => Ok(self.reload()?.to_string()),
+            ("mode", []) => Ok(self.mode().to_string()),
+            ("mode", [mode]) => {
+                *self.mode.write().unwrap() = FilterMode::parse(mode)?;
+                Ok("OK".to_string())
+            },
+            ("words", []) => Ok(self.words.read().unwrap().words.join("\n")),
+            _ => Err(Error::msg("ERR Unknown subcommand or wrong number of arguments for FILTER")),
+        }
+    }
+}
+
+// FilterLayer applies the ContentFilter to the requests before they reach the server, FILTER is answered by the layer itself
+// it is put behind the AclLayer, so that only the users granted FILTER may change the filter
+pub struct FilterLayer(pub Arc<ContentFilter>);
+
+impl<S> volo::Layer<S> for FilterLayer {
+    type Service = FilterService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        FilterService { inner, filter: self.0 }
+    }
+}
+
+#[derive(Clone)]
+pub struct FilterService<S> {
+    inner: S,
+    filter: Arc<ContentFilter>,
+}
+
+#[volo::service]
+impl<S> volo::Service<volo_thrift::context::ServerContext, ItemServiceRequestRecv> for FilterService<S>
+where
+    S: volo::Service<volo_thrift::context::ServerContext, ItemServiceRequestRecv, Response = ItemServiceResponseSend, Error = Error>
+        + Send
+        + Sync
+        + 'static,
+{
+    async fn call(
+        &self,
+        cx: &mut volo_thrift::context::ServerContext,
+        mut req: ItemServiceRequestRecv,
+    ) -> Result<ItemServiceResponseSend, Error> {
+        let ItemServiceRequestRecv::GetItem(args) = &mut req;
+        let result = match OPCode::from(args.req.opcode) {
+            OPCode::FILTER => self.filter.command(&args.req),
+            _ => match self.filter.filter(&mut args.req) {
+                Ok(()) => return self.inner.call(cx, req).await,
+                Err(e) => Err(e),
+            },
+        };
+        let mut resp = GetItemResponse {
+            opcode: args.req.opcode,
+            key_channal: args.req.key_channal.clone(),
+            value_message: " ".into(),
+            success: false,
+        };
+        match result {
+            Ok(message) => {
+                resp.value_message = message.into();
+                resp.success = true;
+            },
+            Err(e) => resp.value_message = e.to_string().into(),
+        }
+        Ok(ItemServiceResponseSend::GetItem(ItemServiceGetItemResultSend::Ok(resp)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::request;
+
+    // a filter-file in the temporary directory, removed when it is dropped
+    struct WordsFile(std::path::PathBuf);
+
+    impl WordsFile {
+        fn new(name: &str, content: &str) -> WordsFile {
+            let path = std::env::temp_dir().join(format!("redis-filter-{}-{}.txt", name, std::process::id()));
+            std::fs::write(&path, content).unwrap();
+            WordsFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for WordsFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn reject() {
+        let filter = ContentFilter::new(None, FilterMode::Reject).unwrap();
+        let mut req = request(OPCode::SET, "greeting", "你是傻逼吗");
+        let error = filter.filter(&mut req).unwrap_err().to_string();
+        assert!(error.contains("傻逼"), "{}", error);
+        assert_eq!(req.value_message.as_str(), "你是傻逼吗");
+
+        let mut req = request(OPCode::SET, "傻逼", "value");
+        assert!(filter.filter(&mut req).is_err());
+        let mut req = request(OPCode::SET, "greeting", "hello");
+        assert!(filter.filter(&mut req).is_ok());
+    }
+
+    #[test]
+    fn mask() {
+        let file = WordsFile::new("mask", "# the words\nbad\n\n  Worse  \nbadder\n");
+        let filter = ContentFilter::new(Some(file.path()), FilterMode::Mask).unwrap();
+        let mut req = request(OPCode::SET, "a-bad-key", "BAD or worse, badder");
+        filter.filter(&mut req).unwrap();
+        assert_eq!(req.key_channal.as_str(), "a-***-key");
+        // the words match regardless of the ascii case, and the longest one wins
+        assert_eq!(req.value_message.as_str(), "*** or *****, ******");
+
+        let filter = ContentFilter::new(None, FilterMode::Mask).unwrap();
+        let mut req = request(OPCode::SET, "greeting", "你是傻逼吗");
+        filter.filter(&mut req).unwrap();
+        assert_eq!(req.value_message.as_str(), "你是**吗");
+    }
+
+    #[test]
+    fn log() {
+        let filter = ContentFilter::new(None, FilterMode::Log).unwrap();
+        let mut req = request(OPCode::SET, "greeting", "你是傻逼吗");
+        filter.filter(&mut req).unwrap();
+        assert_eq!(req.value_message.as_str(), "你是傻逼吗");
+    }
+
+    #[test]
+    fn exempt_requests() {
+        let filter = ContentFilter::new(None, FilterMode::Reject).unwrap();
+        for opcode in [OPCode::AUTH, OPCode::SETMASTER, OPCode::EXECMASTER] {
+            let mut req = request(opcode, "傻逼", "傻逼");
+            assert!(filter.filter(&mut req).is_ok());
+        }
+    }
+
+    #[test]
+    fn change_mode() {
+        assert_eq!(FilterMode::parse("MASK").unwrap(), FilterMode::Mask);
+        assert!(FilterMode::parse("drop").is_err());
+
+        let filter = ContentFilter::new(None, FilterMode::Reject).unwrap();
+        assert_eq!(filter.command(&request(OPCode::FILTER, "mode", "log")).unwrap(), "OK");
+        assert_eq!(filter.mode(), FilterMode::Log);
+        assert_eq!(filter.command(&request(OPCode::FILTER, "mode", "")).unwrap(), "log");
+        assert!(filter.command(&request(OPCode::FILTER, "mode", "drop")).is_err());
+        assert_eq!(filter.mode(), FilterMode::Log);
+    }
+
+    #[test]
+    fn reload_keeps_the_words_if_the_file_is_gone() {
+        let file = WordsFile::new("reload", "bad\n");
+        let filter = ContentFilter::new(Some(file.path()), FilterMode::Reject).unwrap();
+        std::fs::write(&file.0, "bad\nworse\n").unwrap();
+        assert_eq!(filter.reload().unwrap(), 2);
+
+        let path = file.path().to_string();
+        drop(file);
+        let error = filter.reload().unwrap_err().to_string();
+        assert!(error.contains(&path), "{}", error);
+        assert!(filter.filter(&mut request(OPCode::SET, "key", "worse")).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+// glob-style pattern matching with the same semantics as redis
+// *      matches any sequence of characters, including an empty one
+// ?      matches any single character
+// [abc]  matches one character in the brackets, [^abc] matches one character not in the brackets
+// [a-z]  matches one character in the range
+// \x     matches the character x literally, both outside and inside the brackets
+
+// match the character class starting at p[i] (right after '['),
+// return whether c is matched and the index right after the class
+fn match_class(p: &[char], mut i: usize, c: char) -> (bool, usize) {
+    let negate = i < p.len() && p[i] == '^';
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    while i < p.len() && p[i] != ']' {
+        if p[i] == '\\' && i + 1 < p.len() {
+            matched |= p[i + 1] == c;
+            i += 2;
+        } else if i + 2 < p.len() && p[i + 1] == '-' && p[i + 2] != ']' {
+            let (start, end) = match p[i] <= p[i + 2] {
+                true => (p[i], p[i + 2]),
+                false => (p[i + 2], p[i]),
+            };
+            matched |= start <= c && c <= end;
+            i += 3;
+        } else {
+            matched |= p[i] == c;
+            i += 1;
+        }
+    }
+    // skip the closing bracket, an unterminated class ends at the end of the pattern
+    if i < p.len() {
+        i += 1;
+    }
+    (matched != negate, i)
+}
+
+// match a single token (anything but '*') starting at p[i] against c,
+// return whether c is matched and the index of the next token
+fn match_token(p: &[char], i: usize, c: char) -> (bool, usize) {
+    match p[i] {
+        '?' => (true, i + 1),
+        '\\' if i + 1 < p.len() => (p[i + 1] == c, i + 2),
+        '[' => match_class(p, i + 1, c),
+        x => (x == c, i + 1),
+    }
+}
+
+pub fn glob_match(pattern: &str, string: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = string.chars().collect();
+    let (mut pi, mut si) = (0, 0);
+    // the position after the last '*' and the position in the string it has consumed up to,
+    // used to backtrack when the following tokens fail to match
+    let mut star: Option<(usize, usize)> = None;
+    while si < s.len() {
+        if pi < p.len() && p[pi] == '*' {
+            while pi < p.len() && p[pi] == '*' {
+                pi += 1;
+            }
+            star = Some((pi, si));
+            continue;
+        }
+        if pi < p.len() {
+            let (matched, next) = match_token(&p, pi, s[si]);
+            if matched {
+                pi = next;
+                si += 1;
+                continue;
+            }
+        }
+        match star {
+            // let the last '*' consume one more character and try again
+            Some((star_pi, star_si)) => {
+                pi = star_pi;
+                si = star_si + 1;
+                star = Some((star_pi, star_si + 1));
+            },
+            None => return false,
+        }
+    }
+    while pi < p.len() && p[pi] == '*' {
+        pi += 1;
+    }
+    pi == p.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn star_and_question_mark() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("h?llo", "hello"));
+        assert!(!glob_match("h?llo", "hllo"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(glob_match("*llo", "hello"));
+        assert!(!glob_match("a*", "ba"));
+        assert!(glob_match("a**b", "ab"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(glob_match("\\*", "*"));
+        assert!(!glob_match("\\*", "a"));
+        assert!(glob_match("\\?", "?"));
+        assert!(!glob_match("\\?", "a"));
+        assert!(glob_match("a\\[b", "a[b"));
+        // an escaped closing bracket does not end the class
+        assert!(glob_match("[\\]]", "]"));
+        assert!(!glob_match("[\\]]", "\\"));
+    }
+
+    #[test]
+    fn trailing_backslash_is_literal() {
+        assert!(glob_match("abc\\", "abc\\"));
+        assert!(!glob_match("abc\\", "abc"));
+        assert!(glob_match("[a\\", "\\"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(glob_match("h[ae]llo", "hallo"));
+        assert!(!glob_match("h[ae]llo", "hillo"));
+        assert!(glob_match("h[^e]llo", "hallo"));
+        assert!(!glob_match("h[^e]llo", "hello"));
+        // an unterminated class ends at the end of the pattern
+        assert!(glob_match("[abc", "b"));
+        assert!(!glob_match("[abc", "d"));
+    }
+
+    #[test]
+    fn ranges() {
+        assert!(glob_match("[a-c]", "b"));
+        assert!(!glob_match("[a-c]", "d"));
+        // a reversed range is the same as the ordered one
+        assert!(glob_match("[c-a]", "b"));
+        assert!(glob_match("[^a-c]", "d"));
+        assert!(!glob_match("[^a-c]", "b"));
+        // a '-' before the closing bracket is literal
+        assert!(glob_match("[a-]", "-"));
+        assert!(!glob_match("[a-]", "b"));
+        assert!(glob_match("key:[0-9][0-9]", "key:42"));
+    }
+
+    #[test]
+    fn multibyte() {
+        assert!(glob_match("你*", "你好"));
+        assert!(glob_match("?好", "你好"));
+        assert!(!glob_match("?", "你好"));
+        assert!(glob_match("[你我]", "我"));
+        assert!(glob_match("[一-龥]", "中"));
+        assert!(!glob_match("[^一-龥]", "中"));
+        assert!(glob_match("\\你", "你"));
+    }
+
+    #[test]
+    fn channel_patterns() {
+        assert!(glob_match("news.*", "news.sport"));
+        assert!(glob_match("news.*", "news."));
+        assert!(!glob_match("news.*", "weather.today"));
+        assert!(glob_match("*.*.log", "a.b.log"));
+        assert!(!glob_match("*.*.log", "a.log"));
+        assert!(glob_match("新闻.*", "新闻.体育"));
+    }
+}
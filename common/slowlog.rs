@@ -0,0 +1,295 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering}},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use anyhow::Error;
+use volo::context::Context;
+use volo_gen::volo::example::{
+    GetItemRequest, GetItemResponse, ItemServiceGetItemResultSend, ItemServiceRequestRecv, ItemServiceResponseSend,
+};
+
+use crate::{acl::COMMANDS, Config, OPCode};
+
+// the arguments kept for an entry, the rest are counted in the last one, like redis
+const SLOWLOG_MAX_ARGC: usize = 32;
+// the bytes kept of each argument
+const SLOWLOG_MAX_ARGV_LEN: usize = 128;
+// the entries returned by SLOWLOG GET without a count
+const SLOWLOG_DEFAULT_COUNT: usize = 10;
+
+// a command that took longer than slowlog-log-slower-than
+#[derive(Clone, Debug)]
+pub struct SlowLogEntry {
+    pub id: u64,
+    pub timestamp: u64,         // the unix time the command was received, in seconds
+    pub duration: Duration,
+    pub args: Vec<String>,      // the command name followed by its truncated arguments
+    pub client: String,         // the address of the client, empty if it is unknown
+}
+
+impl SlowLogEntry {
+    fn render(&self) -> String {
+        format!(
+            "id:{}\ntime:{}\nduration_us:{}\nclient:{}\nargs:{}",
+            self.id,
+            self.timestamp,
+            self.duration.as_micros(),
+            self.client,
+            self.args.join(" ")
+        )
+    }
+}
+
+// SlowLog keeps the latest slowlog-max-len commands slower than slowlog-log-slower-than, the newest first
+// the limits of a node are changed by CONFIG SET, then the oldest entries beyond the new length are dropped
+pub struct SlowLog {
+    next_id: AtomicU64,
+    slower_than: AtomicI64,     // in microseconds, a negative value turns the log off and 0 logs every command
+    max_len: AtomicUsize,
+    entries: Mutex<VecDeque<SlowLogEntry>>,
+}
+
+impl Default for SlowLog {
+    fn default() -> SlowLog {
+        let config = Config::default();
+        SlowLog::new(config.slowlog_log_slower_than, config.slowlog_max_len)
+    }
+}
+
+impl SlowLog {
+    pub fn new(slower_than: i64, max_len: usize) -> SlowLog {
+        SlowLog {
+            next_id: AtomicU64::new(0),
+            slower_than: AtomicI64::new(slower_than),
+            max_len: AtomicUsize::new(max_len),
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn set_limits(&self, slower_than: i64, max_len: usize) {
+        self.slower_than.store(slower_than, Ordering::Relaxed);
+        self.max_len.store(max_len, Ordering::Relaxed);
+        self.entries.lock().unwrap().truncate(max_len);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.slower_than.load(Ordering::Relaxed) >= 0
+    }
+
+    // the arguments are truncated by SlowLog::args before the request is run, since the request is moved into the server
+    pub fn record(&self, args: Vec<String>, started: SystemTime, duration: Duration, client: String) {
+        let slower_than = self.slower_than.load(Ordering::Relaxed);
+        if slower_than < 0 || duration.as_micros() < slower_than as u128 {
+            return;
+        }
+        let max_len = self.max_len.load(Ordering::Relaxed);
+        let entry = SlowLogEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            timestamp: started.duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or_default(),
+            duration,
+            args,
+            client,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_front(entry);
+        entries.truncate(max_len);
+    }
+
+    pub fn get(&self, count: usize) -> Vec<SlowLogEntry> {
+        self.entries.lock().unwrap().iter().take(count).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn reset(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    // the name of the command, then the key_channal and the words of the value_message, without the blank placeholders
+    fn args(req: &GetItemRequest) -> Vec<String> {
+        let name = COMMANDS
+            .iter()
+            .find(|(opcode, _, _)| *opcode == req.opcode)
+            .map(|(_, name, _)| name.to_string())
+            .unwrap_or_else(|| req.opcode.to_string());
+        let mut argv = vec![name];
+        argv.extend(
+            std::iter::once(req.key_channal.trim())
+                .filter(|key| !key.is_empty())
+                .chain(req.value_message.split_whitespace())
+                .map(|arg| match arg.len() > SLOWLOG_MAX_ARGV_LEN {
+                    true => {
+                        let mut end = SLOWLOG_MAX_ARGV_LEN;
+                        while !arg.is_char_boundary(end) {
+                            end -= 1;
+                        }
+                        format!("{}... ({} more bytes)", &arg[..end], arg.len() - end)
+                    },
+                    false => arg.to_string(),
+                }),
+        );
+        if argv.len() > SLOWLOG_MAX_ARGC {
+            let more = argv.len() - (SLOWLOG_MAX_ARGC - 1);
+            argv.truncate(SLOWLOG_MAX_ARGC - 1);
+            argv.push(format!("... ({} more arguments)", more));
+        }
+        argv
+    }
+
+    // SLOWLOG GET/LEN/RESET, the subcommand is in the key_channal and the count of GET in the value_message
+    // GET returns the entries separated by blank lines, a negative count returns all of them
+    fn command(&self, req: &GetItemRequest) -> Result<String, Error> {
+        let args: Vec<&str> = req.value_message.split_whitespace().collect();
+        match (req.key_channal.to_lowercase().as_str(), args.as_slice()) {
+            ("get", []) => Ok(self.render(SLOWLOG_DEFAULT_COUNT)),
+            ("get", [count]) => match count.parse::<i64>() {
+                Ok(count) if count < 0 => Ok(self.render(usize::MAX)),
+                Ok(count) => Ok(self.render(count as usize)),
+                Err(_) => Err(Error::msg("ERR value is not an integer or out of range")),
+            },
+            ("len", []) => Ok(self.len().to_string()),
+            ("reset", []) => {
+                self.reset();
+                Ok("OK".to_string())
+            },
+            _ => Err(Error::msg("ERR Unknown subcommand or wrong number of arguments for SLOWLOG")),
+        }
+    }
+
+    fn render(&self, count: usize) -> String {
+        self.get(count).iter().map(SlowLogEntry::render).collect::<Vec<_>>().join("\n\n")
+    }
+}
+
+// SlowLogLayer times each request reaching the server and records the slow ones, SLOWLOG is answered by the layer itself
+// it is put behind the FilterLayer, so that the masked words are not kept in the log
+pub struct SlowLogLayer(pub Arc<SlowLog>);
+
+impl<S> volo::Layer<S> for SlowLogLayer {
+    type Service = SlowLogService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        SlowLogService { inner, slowlog: self.0 }
+    }
+}
+
+#[derive(Clone)]
+pub struct SlowLogService<S> {
+    inner: S,
+    slowlog: Arc<SlowLog>,
+}
+
+#[volo::service]
+impl<S> volo::Service<volo_thrift::context::ServerContext, ItemServiceRequestRecv> for SlowLogService<S>
+where
+    S: volo::Service<volo_thrift::context::ServerContext, ItemServiceRequestRecv, Response = ItemServiceResponseSend, Error = Error>
+        + Send
+        + Sync
+        + 'static,
+{
+    async fn call(
+        &self,
+        cx: &mut volo_thrift::context::ServerContext,
+        req: ItemServiceRequestRecv,
+    ) -> Result<ItemServiceResponseSend, Error> {
+        let ItemServiceRequestRecv::GetItem(args) = &req;
+        if OPCode::from(args.req.opcode) != OPCode::SLOWLOG {
+            // nothing is kept while the log is off, otherwise the truncated arguments are kept instead of the whole request
+            if !self.slowlog.is_enabled() {
+                return self.inner.call(cx, req).await;
+            }
+            let argv = SlowLog::args(&args.req);
+            let client = cx.rpc_info().caller().address().map(|addr| addr.to_string()).unwrap_or_default();
+            let started = SystemTime::now();
+            let now = Instant::now();
+            let result = self.inner.call(cx, req).await;
+            self.slowlog.record(argv, started, now.elapsed(), client);
+            return result;
+        }
+        let mut resp = GetItemResponse {
+            opcode: args.req.opcode,
+            key_channal: args.req.key_channal.clone(),
+            value_message: " ".into(),
+            success: false,
+        };
+        match self.slowlog.command(&args.req) {
+            Ok(message) => {
+                resp.value_message = message.into();
+                resp.success = true;
+            },
+            Err(e) => resp.value_message = e.to_string().into(),
+        }
+        Ok(ItemServiceResponseSend::GetItem(ItemServiceGetItemResultSend::Ok(resp)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::request;
+
+    #[test]
+    fn args_skip_the_placeholders() {
+        assert_eq!(SlowLog::args(&request(OPCode::GET, "key", " ")), vec!["get", "key"]);
+        assert_eq!(SlowLog::args(&request(OPCode::SET, "key", "a  b")), vec!["set", "key", "a", "b"]);
+        assert_eq!(SlowLog::args(&request(OPCode::PING, " ", " ")), vec!["ping"]);
+        let unknown = GetItemRequest { opcode: 999, ..request(OPCode::GET, "key", " ") };
+        assert_eq!(SlowLog::args(&unknown), vec!["999", "key"]);
+    }
+
+    #[test]
+    fn args_truncate_long_arguments() {
+        let value = "a".repeat(SLOWLOG_MAX_ARGV_LEN + 72);
+        let args = SlowLog::args(&request(OPCode::SET, "key", &value));
+        assert_eq!(args[2], format!("{}... (72 more bytes)", "a".repeat(SLOWLOG_MAX_ARGV_LEN)));
+
+        let value = "a".repeat(SLOWLOG_MAX_ARGV_LEN);
+        let args = SlowLog::args(&request(OPCode::SET, "key", &value));
+        assert_eq!(args[2], value);
+    }
+
+    #[test]
+    fn args_truncate_at_a_char_boundary() {
+        // 128 is not a multiple of 3, so the argument is cut after the 42nd character
+        let value = "你".repeat(50);
+        let args = SlowLog::args(&request(OPCode::SET, "key", &value));
+        assert_eq!(args[2], format!("{}... (24 more bytes)", "你".repeat(42)));
+
+        let key = "é".repeat(100);
+        let args = SlowLog::args(&request(OPCode::GET, &key, " "));
+        assert_eq!(args[1], format!("{}... (72 more bytes)", "é".repeat(64)));
+    }
+
+    #[test]
+    fn args_truncate_many_arguments() {
+        let value = (0..40).map(|i| i.to_string()).collect::<Vec<_>>().join(" ");
+        let args = SlowLog::args(&request(OPCode::EVAL, "return 1", &value));
+        assert_eq!(args.len(), SLOWLOG_MAX_ARGC);
+        assert_eq!(args[SLOWLOG_MAX_ARGC - 2], "28");
+        assert_eq!(args[SLOWLOG_MAX_ARGC - 1], "... (11 more arguments)");
+    }
+
+    #[test]
+    fn record_the_slow_commands() {
+        let slowlog = SlowLog::new(1000, 2);
+        slowlog.record(SlowLog::args(&request(OPCode::GET, "key", " ")), SystemTime::now(), Duration::from_micros(999), String::new());
+        assert!(slowlog.is_empty());
+        for id in 0..3 {
+            slowlog.record(SlowLog::args(&request(OPCode::GET, &id.to_string(), " ")), SystemTime::now(), Duration::from_millis(1), String::new());
+        }
+        let entries = slowlog.get(10);
+        assert_eq!(entries.iter().map(|entry| entry.id).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(entries[0].args, vec!["get", "2"]);
+
+        slowlog.set_limits(-1, 1);
+        assert!(!slowlog.is_enabled());
+        assert_eq!(slowlog.len(), 1);
+    }
+}
@@ -0,0 +1,17 @@
+// the helpers shared by the unit tests of the modules
+use volo_gen::volo::example::GetItemRequest;
+
+use crate::OPCode;
+
+// a request outside of any transaction, session or database, sent without credentials
+pub fn request(opcode: OPCode, key: &str, value: &str) -> GetItemRequest {
+    GetItemRequest {
+        opcode: opcode as i32,
+        key_channal: key.to_string().into(),
+        value_message: value.to_string().into(),
+        txn_id: None,
+        session_id: None,
+        db: None,
+        auth: None,
+    }
+}
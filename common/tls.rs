@@ -0,0 +1,313 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+use anyhow::Error;
+use tokio::{
+    io::{ReadHalf, WriteHalf},
+    net::{TcpListener, TcpStream, UnixStream},
+    sync::mpsc,
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        self,
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+        server::WebPkiClientVerifier,
+        RootCertStore,
+    },
+    TlsAcceptor,
+    TlsConnector,
+};
+use volo::net::{
+    conn::{Conn, ConnInfo, ConnStream},
+    dial::MakeTransport,
+    incoming::{Incoming, MakeIncoming},
+    Address,
+};
+
+// a connection not finishing the handshake in time is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// whether the listener asks the clients for a certificate signed by tls-ca-cert-file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClientAuth {
+    #[default]
+    No,
+    Optional,   // the certificate is verified if the client sends one
+    Yes,
+}
+
+const CLIENT_AUTHS: &[(ClientAuth, &str)] = &[
+    (ClientAuth::No, "no"),
+    (ClientAuth::Optional, "optional"),
+    (ClientAuth::Yes, "yes"),
+];
+
+impl ClientAuth {
+    pub fn parse(value: &str) -> Result<ClientAuth, Error> {
+        CLIENT_AUTHS
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(value))
+            .map(|(auth, _)| *auth)
+            .ok_or(Error::msg(format!("Invalid value for tls-auth-clients: {}", value)))
+    }
+}
+
+impl std::fmt::Display for ClientAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = CLIENT_AUTHS.iter().find(|(auth, _)| auth == self).map(|(_, name)| *name).unwrap_or_default();
+        write!(f, "{}", name)
+    }
+}
+
+// the certificates are PEM files, the same ones are used by the listener and by the clients connecting to the nodes behind,
+// which are the slaves of a master node and the backend nodes of the proxy
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+    pub connect: bool,                  // whether the clients connect to the nodes behind with TLS
+    pub cert_file: Option<String>,      // the certificate of this node or proxy, the listener uses TLS once it is given
+    pub key_file: Option<String>,
+    pub ca_cert_file: Option<String>,   // the CA the certificates of the other side are verified with
+    pub auth_clients: ClientAuth,
+    pub server_name: Option<String>,    // the name the certificates of the nodes behind are issued for, their ip by default
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let file = File::open(path).map_err(|e| Error::msg(format!("Failed to open {}: {}", path, e)))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::msg(format!("Failed to read the certificates in {}: {}", path, e)))?;
+    match certs.is_empty() {
+        true => Err(Error::msg(format!("No certificate found in {}", path))),
+        false => Ok(certs),
+    }
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, Error> {
+    let file = File::open(path).map_err(|e| Error::msg(format!("Failed to open {}: {}", path, e)))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| Error::msg(format!("Failed to read the private key in {}: {}", path, e)))?
+        .ok_or(Error::msg(format!("No private key found in {}", path)))
+}
+
+fn load_roots(path: &str) -> Result<RootCertStore, Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert).map_err(|e| Error::msg(format!("Invalid CA certificate in {}: {}", path, e)))?;
+    }
+    Ok(roots)
+}
+
+impl TlsConfig {
+    // the certificate chain and its key, which are given together or not at all
+    fn identity(&self) -> Result<Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>, Error> {
+        match (&self.cert_file, &self.key_file) {
+            (Some(cert_file), Some(key_file)) => Ok(Some((load_certs(cert_file)?, load_key(key_file)?))),
+            (None, None) => Ok(None),
+            _ => Err(Error::msg("tls-cert-file and tls-key-file must be given together")),
+        }
+    }
+
+    fn ca(&self, purpose: &str) -> Result<RootCertStore, Error> {
+        match &self.ca_cert_file {
+            Some(ca_cert_file) => load_roots(ca_cert_file),
+            None => Err(Error::msg(format!("tls-ca-cert-file is required to verify {}", purpose))),
+        }
+    }
+
+    // the acceptor of the listener, None when no certificate is given
+    pub fn acceptor(&self) -> Result<Option<TlsAcceptor>, Error> {
+        let (certs, key) = match self.identity()? {
+            Some(identity) => identity,
+            None => return Ok(None),
+        };
+        let builder = rustls::ServerConfig::builder();
+        let builder = match self.auth_clients {
+            ClientAuth::No => builder.with_no_client_auth(),
+            auth => {
+                let verifier = WebPkiClientVerifier::builder(Arc::new(self.ca("the clients")?));
+                let verifier = match auth {
+                    ClientAuth::Optional => verifier.allow_unauthenticated(),
+                    _ => verifier,
+                };
+                builder.with_client_cert_verifier(verifier.build().map_err(|e| Error::msg(e.to_string()))?)
+            },
+        };
+        let config = builder
+            .with_single_cert(certs, key)
+            .map_err(|e| Error::msg(format!("Invalid certificate or key: {}", e)))?;
+        Ok(Some(TlsAcceptor::from(Arc::new(config))))
+    }
+
+    // the connector of the clients, None when they do not use TLS
+    // the certificate of this node or proxy, if any, is sent to the nodes behind as the client certificate
+    pub fn connector(&self) -> Result<Option<TlsConnector>, Error> {
+        if !self.connect {
+            return Ok(None);
+        }
+        let builder = rustls::ClientConfig::builder().with_root_certificates(self.ca("the nodes behind")?);
+        let config = match self.identity()? {
+            Some((certs, key)) => builder
+                .with_client_auth_cert(certs, key)
+                .map_err(|e| Error::msg(format!("Invalid certificate or key: {}", e)))?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Some(TlsConnector::from(Arc::new(config))))
+    }
+
+    // load the files once on startup, so that a wrong path is reported before any connection is made
+    pub fn validate(&self) -> Result<(), Error> {
+        self.acceptor()?;
+        self.connector()?;
+        Ok(())
+    }
+}
+
+// MakeIncoming of a listener speaking TLS
+// volo only serves plain sockets, so each connection is decrypted here and handed to volo through a socket pair
+pub struct TlsIncoming {
+    addr: SocketAddr,
+    acceptor: TlsAcceptor,
+}
+
+impl TlsIncoming {
+    pub fn new(addr: SocketAddr, acceptor: TlsAcceptor) -> TlsIncoming {
+        TlsIncoming { addr, acceptor }
+    }
+}
+
+#[derive(Debug)]
+pub struct TlsConns {
+    conns: mpsc::Receiver<Conn>,
+}
+
+#[volo::async_trait]
+impl MakeIncoming for TlsIncoming {
+    type Incoming = TlsConns;
+
+    async fn make_incoming(self) -> io::Result<TlsConns> {
+        let listener = TcpListener::bind(self.addr).await?;
+        let (tx, rx) = mpsc::channel(128);
+        tokio::spawn(async move {
+            while !tx.is_closed() {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // such as too many open files, wait a moment instead of spinning
+                        tracing::warn!("Failed to accept a connection on {}: {}", self.addr, e);
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        continue;
+                    },
+                };
+                let acceptor = self.acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let _ = stream.set_nodelay(true);
+                    let mut stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(e)) => {
+                            tracing::warn!("TLS handshake with {} failed: {}", peer, e);
+                            return;
+                        },
+                        Err(_) => {
+                            tracing::warn!("TLS handshake with {} timed out", peer);
+                            return;
+                        },
+                    };
+                    let (local, mut remote) = match UnixStream::pair() {
+                        Ok(pair) => pair,
+                        Err(e) => {
+                            tracing::error!("Failed to create a socket pair for {}: {}", peer, e);
+                            return;
+                        },
+                    };
+                    let conn = Conn {
+                        stream: ConnStream::from(local),
+                        info: ConnInfo { peer_addr: Some(Address::from(peer)) },
+                    };
+                    if tx.send(conn).await.is_err() {
+                        return;
+                    }
+                    let _ = tokio::io::copy_bidirectional(&mut stream, &mut remote).await;
+                });
+            }
+        });
+        Ok(TlsConns { conns: rx })
+    }
+}
+
+#[volo::async_trait]
+impl Incoming for TlsConns {
+    async fn accept(&mut self) -> io::Result<Option<Conn>> {
+        Ok(self.conns.recv().await)
+    }
+}
+
+// MakeTransport of the clients connecting with TLS
+// the certificate of the node is verified against tls-server-name, or against its ip when it is not given
+#[derive(Clone)]
+pub struct TlsMakeTransport {
+    // a broken config fails every connection rather than falling back to plaintext
+    connector: Result<TlsConnector, Arc<str>>,
+    server_name: Option<String>,
+    connect_timeout: Option<Duration>,
+}
+
+impl TlsMakeTransport {
+    pub fn new(config: &TlsConfig) -> TlsMakeTransport {
+        let connector = match config.connector() {
+            Ok(Some(connector)) => Ok(connector),
+            Ok(None) => Err("TLS is not enabled".into()),
+            Err(e) => Err(e.to_string().into()),
+        };
+        TlsMakeTransport {
+            connector,
+            server_name: config.server_name.clone(),
+            connect_timeout: None,
+        }
+    }
+
+    async fn connect(&self, addr: SocketAddr) -> io::Result<TlsStream<TcpStream>> {
+        let connector = self.connector.as_ref().map_err(|e| io::Error::other(e.to_string()))?;
+        let server_name = match &self.server_name {
+            Some(name) => ServerName::try_from(name.clone()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+            None => ServerName::IpAddress(addr.ip().into()),
+        };
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        connector.connect(server_name, stream).await
+    }
+}
+
+#[volo::async_trait]
+impl MakeTransport for TlsMakeTransport {
+    type ReadHalf = ReadHalf<TlsStream<TcpStream>>;
+    type WriteHalf = WriteHalf<TlsStream<TcpStream>>;
+
+    async fn make_transport(&self, addr: Address) -> io::Result<(Self::ReadHalf, Self::WriteHalf)> {
+        let addr = match addr {
+            Address::Ip(addr) => addr,
+            addr => return Err(io::Error::new(io::ErrorKind::Unsupported, format!("TLS is not supported on {}", addr))),
+        };
+        let stream = match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.connect(addr))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("Connecting to {} timed out", addr)))??,
+            None => self.connect(addr).await?,
+        };
+        Ok(tokio::io::split(stream))
+    }
+
+    fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+        self.connect_timeout = timeout;
+    }
+
+    // the timeouts of the requests are applied by RedisClient on the nodes and by Node on the proxy instead
+    fn set_read_timeout(&mut self, _timeout: Option<Duration>) {}
+
+    fn set_write_timeout(&mut self, _timeout: Option<Duration>) {}
+}
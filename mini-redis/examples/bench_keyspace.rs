@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Instant;
+use ansi_term::Colour::Green;
+use rand::{thread_rng, Rng};
+
+use mini_redis::Keyspace;
+
+const KEYS: usize = 10_000;
+const OPS_PER_THREAD: usize = 200_000;
+const WRITE_PERCENT: u32 = 20;
+
+// the keyspace before the sharding, a single lock for all the keys
+#[derive(Default)]
+struct GlobalLock(RwLock<HashMap<String, String>>);
+
+trait Store: Sync {
+    fn get(&self, key: &str) -> Option<String>;
+    fn set(&self, key: String, value: String);
+}
+
+impl Store for GlobalLock {
+    fn get(&self, key: &str) -> Option<String> {
+        self.0.read().unwrap().get(key).cloned()
+    }
+
+    fn set(&self, key: String, value: String) {
+        self.0.write().unwrap().insert(key, value);
+    }
+}
+
+impl Store for Keyspace {
+    fn get(&self, key: &str) -> Option<String> {
+        Keyspace::get(self, key, 0)
+    }
+
+    fn set(&self, key: String, value: String) {
+        self.insert(key, value, 0);
+    }
+}
+
+// run the mix of gets and sets on the given number of threads, and return the operations per second
+fn run(store: &impl Store, threads: usize) -> f64 {
+    let keys: Vec<String> = (0..KEYS).map(|i| format!("key:{}", i)).collect();
+    for key in &keys {
+        store.set(key.clone(), "value".to_string());
+    }
+    let started = Instant::now();
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                let mut rng = thread_rng();
+                for _ in 0..OPS_PER_THREAD {
+                    let key = &keys[rng.gen_range(0..KEYS)];
+                    if rng.gen_range(0..100) < WRITE_PERCENT {
+                        store.set(key.clone(), "value".to_string());
+                    } else {
+                        store.get(key);
+                    }
+                }
+            });
+        }
+    });
+    (threads * OPS_PER_THREAD) as f64 / started.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    println!("{} cores, {} keys, {}% writes", cores, KEYS, WRITE_PERCENT);
+    println!("{:>8} {:>16} {:>16}", "threads", "global lock", "sharded");
+    for threads in [1, 2, 4, 8, 16] {
+        let global = run(&GlobalLock::default(), threads);
+        let sharded = run(&Keyspace::default(), threads);
+        println!("{:>8} {:>14.0}/s {:>14.0}/s", threads, global, sharded);
+    }
+    println!("{}", Green.paint("Benchmark finished"));
+}
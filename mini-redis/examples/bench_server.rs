@@ -0,0 +1,71 @@
+use std::net::SocketAddr;
+use std::time::Instant;
+use ansi_term::Colour::Green;
+use rand::{thread_rng, Rng};
+use volo_gen::volo::example::{GetItemRequest, ItemService};
+
+use mini_redis::{OPCode, S, Config};
+
+const KEYS: usize = 10_000;
+const OPS_PER_TASK: usize = 20_000;
+const WRITE_PERCENT: u32 = 20;
+
+fn request(opcode: OPCode, key: &str, value: &str) -> GetItemRequest {
+    GetItemRequest {
+        opcode: opcode as i32,
+        key_channal: key.to_string().into(),
+        value_message: value.to_string().into(),
+        txn_id: None,
+        session_id: None,
+        db: None,
+        auth: None,
+    }
+}
+
+// run the mix of gets and sets through S::get_item on the given number of tasks, and return the operations per second
+// unlike bench_keyspace, each set takes the lock of its key, and is appended to the AOF and sent to the slaves
+async fn run(server: &S, tasks: usize) -> f64 {
+    let started = Instant::now();
+    let handles = (0..tasks)
+        .map(|_| {
+            let server = server.clone();
+            tokio::spawn(async move {
+                for _ in 0..OPS_PER_TASK {
+                    let (write, key) = {
+                        let mut rng = thread_rng();
+                        (rng.gen_range(0..100) < WRITE_PERCENT, format!("key:{}", rng.gen_range(0..KEYS)))
+                    };
+                    let req = match write {
+                        true => request(OPCode::SET, &key, "value"),
+                        false => request(OPCode::GET, &key, " "),
+                    };
+                    server.get_item(req).await.unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    (tasks * OPS_PER_TASK) as f64 / started.elapsed().as_secs_f64()
+}
+
+#[tokio::main]
+async fn main() {
+    // only a master accepts the writes, its slave is not started, so the writes sent to it are dropped once its breaker opens
+    let slave: SocketAddr = "127.0.0.1:1".parse().unwrap();
+    let log_path = std::env::temp_dir().join(format!("mini-redis-bench-{}.log", std::process::id()));
+    let server = S::new(vec![slave], log_path.to_str().unwrap(), Config::default()).await.unwrap();
+    for i in 0..KEYS {
+        server.get_item(request(OPCode::SET, &format!("key:{}", i), "value")).await.unwrap();
+    }
+
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    println!("{} cores, {} keys, {}% writes, appendfsync everysec", cores, KEYS, WRITE_PERCENT);
+    println!("{:>8} {:>16}", "tasks", "S::get_item");
+    for tasks in [1, 2, 4, 8, 16] {
+        println!("{:>8} {:>14.0}/s", tasks, run(&server, tasks).await);
+    }
+    let _ = std::fs::remove_file(&log_path);
+    println!("{}", Green.paint("Benchmark finished"));
+}
@@ -0,0 +1,86 @@
+use volo_gen::volo::example::GetItemRequest;
+use std::net::SocketAddr;
+use mini_redis::RedisClient;
+use ansi_term::Colour::Green;
+use std::io::Write;
+
+fn request(opcode: i32, key: &str, value: &str, session_id: Option<&str>, auth: Option<&str>) -> GetItemRequest {
+    GetItemRequest {
+        opcode,
+        key_channal: key.to_string().into(),
+        value_message: value.to_string().into(),
+        txn_id: None,
+        session_id: session_id.map(|id| id.to_string().into()),
+        db: None,
+        auth: auth.map(|auth| auth.to_string().into()),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let master = RedisClient::new("127.0.0.1:45000".parse::<SocketAddr>().unwrap());
+    let tester = Some("tester tpw");
+
+    // create a user who may only get and set the keys starting with test:
+    print!("1. test acl setuser as the default user, expect to be OK: ");
+    std::io::stdout().flush().unwrap();
+    let resp = master.get_item(request(32, "setuser", "tester on >tpw ~test:* +get +set", None, None)).await.unwrap();
+    assert!(resp.success);
+    println!("{}", Green.paint("PASS"));
+
+    print!("2. test set and get on the allowed keys, expect to be OK: ");
+    std::io::stdout().flush().unwrap();
+    for i in 0..100 {
+        let key = format!("test:{}", i);
+        let resp = master.get_item(request(1, &key, &i.to_string(), None, tester)).await.unwrap();
+        assert!(resp.success);
+        let resp = master.get_item(request(0, &key, " ", None, tester)).await.unwrap();
+        assert_eq!(resp.value_message, i.to_string());
+    }
+    println!("{}", Green.paint("PASS"));
+
+    print!("3. test set on the other keys, expect to be refused: ");
+    std::io::stdout().flush().unwrap();
+    for i in 0..100 {
+        let resp = master.get_item(request(1, &format!("other:{}", i), "1", None, tester)).await.unwrap();
+        assert!(!resp.success);
+        assert!(resp.value_message.starts_with("NOPERM"));
+    }
+    println!("{}", Green.paint("PASS"));
+
+    print!("4. test del which is not granted, expect to be refused: ");
+    std::io::stdout().flush().unwrap();
+    let resp = master.get_item(request(2, "test:0", " ", None, tester)).await.unwrap();
+    assert!(resp.value_message.starts_with("NOPERM"));
+    println!("{}", Green.paint("PASS"));
+
+    print!("5. test a wrong password, expect to be refused: ");
+    std::io::stdout().flush().unwrap();
+    let resp = master.get_item(request(0, "test:0", " ", None, Some("tester wrong"))).await.unwrap();
+    assert!(resp.value_message.starts_with("WRONGPASS"));
+    let resp = master.get_item(request(31, "tester", "wrong", Some("acl-test"), None)).await.unwrap();
+    assert!(resp.value_message.starts_with("WRONGPASS"));
+    println!("{}", Green.paint("PASS"));
+
+    // the session keeps the user after AUTH
+    print!("6. test auth on a session, expect the session to run as the user: ");
+    std::io::stdout().flush().unwrap();
+    let resp = master.get_item(request(31, "tester", "tpw", Some("acl-test"), None)).await.unwrap();
+    assert!(resp.success);
+    let resp = master.get_item(request(32, "whoami", " ", Some("acl-test"), None)).await.unwrap();
+    assert_eq!(resp.value_message, "tester");
+    let resp = master.get_item(request(32, "list", " ", Some("acl-test"), None)).await.unwrap();
+    assert!(resp.value_message.starts_with("NOPERM"));
+    println!("{}", Green.paint("PASS"));
+
+    print!("7. test acl deluser, expect the session to lose the user: ");
+    std::io::stdout().flush().unwrap();
+    let resp = master.get_item(request(32, "deluser", "tester", None, None)).await.unwrap();
+    assert_eq!(resp.value_message, "1");
+    let resp = master.get_item(request(0, "test:0", " ", Some("acl-test"), None)).await.unwrap();
+    assert!(resp.value_message.starts_with("NOAUTH"));
+    for i in 0..100 {
+        master.get_item(request(2, &format!("test:{}", i), " ", None, None)).await.unwrap();
+    }
+    println!("{}", Green.paint("PASS"));
+}
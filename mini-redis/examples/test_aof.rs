@@ -1,7 +1,6 @@
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use volo_gen;
 use std::io::Write;
 use ansi_term::Colour::Green;
 
@@ -22,11 +21,11 @@ pub async fn func(opcode:&i32, key:&str, value: Option<&String>)  {
             let res = get(key).await;
             match res {
                 Some( str) => {
-                    assert!(value != None);
+                    assert!(value.is_some());
                     assert_eq!(str, value.unwrap().to_string());
                 },
                 None => {
-                    assert!(value == None);
+                    assert!(value.is_none());
                 }
             }
         },
@@ -42,6 +41,9 @@ async fn get(key:&str) -> Option<String>{
         key_channal : key.to_string().into(),
         value_message : " ".into(),
         txn_id: None,
+        session_id: None,
+        db: None,
+        auth: None,
     };
     let resp = CLIENT.get_item(req).await;
     match resp {
@@ -63,13 +65,16 @@ async fn set(key: &str, value: Option<&String>) {
         key_channal : key.to_string().into(),
         value_message : value.unwrap().to_string().into(),
         txn_id: None,
+        session_id: None,
+        db: None,
+        auth: None,
     };
     let resp = CLIENT.get_item(req).await;
     match resp {
         Ok(info) => {
-            assert_eq!(info.success, true);
+            assert!(info.success);
         },
-        Err(_) => {assert!(false)}
+        Err(_) => panic!("request failed"),
     };
 }
 
@@ -79,13 +84,16 @@ async fn del(key:&str) {
         key_channal : key.to_string().into(),
         value_message : " ".into(),
         txn_id: None,
+        session_id: None,
+        db: None,
+        auth: None,
     };
     let resp = CLIENT.get_item(req).await;
     match resp {
         Ok(info) => {
-            assert_eq!(info.success, true);
+            assert!(info.success);
         },
-        Err(_) => {assert!(false)}
+        Err(_) => panic!("request failed"),
     };
 }
 
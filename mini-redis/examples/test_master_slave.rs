@@ -1,7 +1,6 @@
 use volo_gen::volo::example::GetItemRequest;
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use volo_gen;
 use mini_redis::RedisClient;
 use ansi_term::Colour::Green;
 use std::io::Write;
@@ -29,6 +28,9 @@ async fn main() {
             key_channal: key.clone().into(),
             value_message: value.clone().into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_err());
     }
@@ -43,6 +45,9 @@ async fn main() {
             key_channal: key.clone().into(),
             value_message: value.clone().into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
     }
@@ -57,6 +62,9 @@ async fn main() {
             key_channal: key.clone().into(),
             value_message: "".into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap().value_message, value.clone());
@@ -72,6 +80,9 @@ async fn main() {
             key_channal: key.clone().into(),
             value_message: "".into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap().value_message, value.clone());
@@ -81,12 +92,15 @@ async fn main() {
     // test del for slave, expect to be failed
     print!("5. test del for slave, expect to be failed: ");
     std::io::stdout().flush().unwrap();
-    for (key, _) in map.iter() {
+    for key in map.keys() {
         let result = slave.get_item(GetItemRequest {
             opcode: 2,
             key_channal: key.clone().into(),
             value_message: "".into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_err());
     }
@@ -95,12 +109,15 @@ async fn main() {
     // test del for master, expect to be OK
     print!("6. test del for master, expect to be OK: ");
     std::io::stdout().flush().unwrap();
-    for (key, _) in map.iter() {
+    for key in map.keys() {
         let result = master.get_item(GetItemRequest {
             opcode: 2,
             key_channal: key.clone().into(),
             value_message: "".into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap().value_message, "1".to_string());
@@ -110,12 +127,15 @@ async fn main() {
     // test del repeatly for master, expect to be OK
     print!("7. test del repeatly for master, expect to be OK: ");
     std::io::stdout().flush().unwrap();
-    for (key, _) in map.iter() {
+    for key in map.keys() {
         let result = master.get_item(GetItemRequest {
             opcode: 2,
             key_channal: key.clone().into(),
             value_message: "".into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap().value_message, "0".to_string());
@@ -131,6 +151,9 @@ async fn main() {
             key_channal: key.clone().into(),
             value_message: value.clone().into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap().value_message, "(nil)".to_string());
@@ -146,6 +169,9 @@ async fn main() {
             key_channal: key.clone().into(),
             value_message: value.clone().into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap().value_message, "(nil)".to_string());
@@ -1,7 +1,6 @@
 use volo_gen::volo::example::GetItemRequest;
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use volo_gen;
 use mini_redis::RedisClient;
 use ansi_term::Colour::Green;
 use std::io::Write;
@@ -27,6 +26,9 @@ async fn main() {
             key_channal: key.clone().into(),
             value_message: value.clone().into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
     }
@@ -40,6 +42,9 @@ async fn main() {
             key_channal: key.clone().into(),
             value_message: value.clone().into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap().value_message, value.clone());
@@ -49,12 +54,15 @@ async fn main() {
     // test del through proxy, expect to be OK
     print!("3. test del through proxy, expect to be OK: ");
     std::io::stdout().flush().unwrap();
-    for (key, _) in map.iter() {
+    for key in map.keys() {
         let result = proxy.get_item(GetItemRequest {
             opcode: 2,
             key_channal: key.clone().into(),
             value_message: "".into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap().value_message, "1".to_string());
@@ -64,12 +72,15 @@ async fn main() {
     // test del through proxy, expect to be OK
     print!("4. test del through proxy, expect to be OK: ");
     std::io::stdout().flush().unwrap();
-    for (key, _) in map.iter() {
+    for key in map.keys() {
         let result = proxy.get_item(GetItemRequest {
             opcode: 2,
             key_channal: key.clone().into(),
             value_message: "".into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap().value_message, "0".to_string());
@@ -85,6 +96,9 @@ async fn main() {
             key_channal: key.clone().into(),
             value_message: value.clone().into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap().value_message, "(nil)".to_string());
@@ -100,6 +114,9 @@ async fn main() {
             key_channal: key.clone().into(),
             value_message: value.clone().into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
     }
@@ -114,6 +131,9 @@ async fn main() {
             key_channal: key.clone().into(),
             value_message: value.clone().into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap().value_message, value.clone());
@@ -0,0 +1,82 @@
+use volo_gen::volo::example::GetItemRequest;
+use std::net::SocketAddr;
+use mini_redis::{ClientConfig, RedisClient, TlsConfig};
+use ansi_term::Colour::Green;
+use std::io::Write;
+
+// the node started with the self-signed certificates in tls/, see the README
+const ADDR: &str = "127.0.0.1:47000";
+
+fn request(opcode: i32, key: &str, value: &str) -> GetItemRequest {
+    GetItemRequest {
+        opcode,
+        key_channal: key.to_string().into(),
+        value_message: value.to_string().into(),
+        txn_id: None,
+        session_id: None,
+        db: None,
+        auth: None,
+    }
+}
+
+fn client(tls: TlsConfig) -> RedisClient {
+    let config = ClientConfig {
+        max_retries: 0,
+        tls,
+        ..ClientConfig::default()
+    };
+    RedisClient::with_config(ADDR.parse::<SocketAddr>().unwrap(), config)
+}
+
+#[tokio::main]
+async fn main() {
+    let trusted = TlsConfig {
+        connect: true,
+        ca_cert_file: Some("tls/ca.crt".to_string()),
+        cert_file: Some("tls/client.crt".to_string()),
+        key_file: Some("tls/client.key".to_string()),
+        ..TlsConfig::default()
+    };
+
+    print!("1. test set and get over TLS with a client certificate, expect to be OK: ");
+    std::io::stdout().flush().unwrap();
+    let node = client(trusted.clone());
+    for i in 0..100 {
+        let key = format!("tls:{}", i);
+        let resp = node.get_item(request(1, &key, &i.to_string())).await.unwrap();
+        assert!(resp.success);
+        let resp = node.get_item(request(0, &key, " ")).await.unwrap();
+        assert_eq!(resp.value_message, i.to_string());
+    }
+    println!("{}", Green.paint("PASS"));
+
+    print!("2. test a plaintext client, expect to be refused: ");
+    std::io::stdout().flush().unwrap();
+    let resp = client(TlsConfig::default()).get_item(request(3, " ", " ")).await;
+    assert!(resp.is_err());
+    println!("{}", Green.paint("PASS"));
+
+    print!("3. test a client without a certificate, expect to be refused: ");
+    std::io::stdout().flush().unwrap();
+    let anonymous = TlsConfig {
+        cert_file: None,
+        key_file: None,
+        ..trusted.clone()
+    };
+    let resp = client(anonymous).get_item(request(3, " ", " ")).await;
+    assert!(resp.is_err());
+    println!("{}", Green.paint("PASS"));
+
+    print!("4. test a server certificate issued for another name, expect to be refused: ");
+    std::io::stdout().flush().unwrap();
+    let wrong_name = TlsConfig {
+        server_name: Some("example.com".to_string()),
+        ..trusted
+    };
+    let resp = client(wrong_name).get_item(request(3, " ", " ")).await;
+    assert!(resp.is_err());
+    for i in 0..100 {
+        node.get_item(request(2, &format!("tls:{}", i), " ")).await.unwrap();
+    }
+    println!("{}", Green.paint("PASS"));
+}
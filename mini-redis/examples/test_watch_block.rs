@@ -0,0 +1,63 @@
+use volo_gen::volo::example::GetItemRequest;
+use std::net::SocketAddr;
+use std::time::Duration;
+use mini_redis::RedisClient;
+use ansi_term::Colour::Green;
+use std::io::Write;
+
+// MOVE, SWAPDB and FLUSHDB sent after WATCH carry the txn_id without being in MULTI
+// they must still be replicated to the slave, see test_master_slave for the nodes
+fn request(opcode: i32, key: &str, value: &str, txn_id: Option<&str>, db: i32) -> GetItemRequest {
+    GetItemRequest {
+        opcode,
+        key_channal: key.to_string().into(),
+        value_message: value.to_string().into(),
+        txn_id: txn_id.map(|id| id.to_string().into()),
+        session_id: None,
+        db: Some(db),
+        auth: None,
+    }
+}
+
+async fn get(node: &RedisClient, key: &str, db: i32) -> String {
+    node.get_item(request(0, key, " ", None, db)).await.unwrap().value_message.to_string()
+}
+
+#[tokio::main]
+async fn main() {
+    let master = RedisClient::new("127.0.0.1:45000".parse::<SocketAddr>().unwrap());
+    let slave = RedisClient::new("127.0.0.1:45001".parse::<SocketAddr>().unwrap());
+
+    // watch the key, so that the later requests carry the txn_id
+    master.get_item(request(1, "watch_block", "value", None, 0)).await.unwrap();
+    let resp = master.get_item(request(202, "watch_block", " ", None, 0)).await.unwrap();
+    assert!(resp.success);
+    let txn_id = resp.key_channal.to_string();
+
+    print!("1. test move after watch, expect the slave to see the key moved: ");
+    std::io::stdout().flush().unwrap();
+    let resp = master.get_item(request(28, "watch_block", "1", Some(&txn_id), 0)).await.unwrap();
+    assert_eq!(resp.value_message, "1");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(get(&slave, "watch_block", 0).await, "(nil)");
+    assert_eq!(get(&slave, "watch_block", 1).await, "value");
+    println!("{}", Green.paint("PASS"));
+
+    print!("2. test swapdb after watch, expect the slave to see the databases swapped: ");
+    std::io::stdout().flush().unwrap();
+    let resp = master.get_item(request(29, "0", "1", Some(&txn_id), 0)).await.unwrap();
+    assert!(resp.success);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(get(&slave, "watch_block", 0).await, "value");
+    assert_eq!(get(&slave, "watch_block", 1).await, "(nil)");
+    println!("{}", Green.paint("PASS"));
+
+    print!("3. test flushdb after watch, expect the slave to see the database flushed: ");
+    std::io::stdout().flush().unwrap();
+    let resp = master.get_item(request(30, " ", " ", Some(&txn_id), 0)).await.unwrap();
+    assert!(resp.success);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(get(&slave, "watch_block", 0).await, "(nil)");
+    master.get_item(request(204, " ", " ", Some(&txn_id), 0)).await.unwrap();
+    println!("{}", Green.paint("PASS"));
+}
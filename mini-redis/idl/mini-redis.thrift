@@ -6,6 +6,9 @@ struct GetItemRequest {
     3: required string value_message,
 
     10: optional string txn_id,
+    11: optional string session_id,
+    12: optional i32 db,
+    13: optional string auth,
 }
 
 struct GetItemResponse {
@@ -1,79 +1,137 @@
 use lazy_static::lazy_static;
 use std::net::SocketAddr;
 use std::env;
-use mini_redis::LogLayer;
+use std::sync::OnceLock;
+use mini_redis::{LogLayer, ClientConfig, Config, TlsMakeTransport};
 use std::io;
 use std::io::Write;
+use tokio::io::{AsyncBufReadExt, BufReader};
 // use volo_gen::volo::example::{GetItemResponse, get_item};
 use mini_redis::OPCode;
 
-static mut ADDR_STR: String = String::new();
+static ADDR_STR: OnceLock<String> = OnceLock::new();
+static CLIENT_CONFIG: OnceLock<ClientConfig> = OnceLock::new();
 
 lazy_static! {
     static ref CLIENT: volo_gen::volo::example::ItemServiceClient = {
-        unsafe {
-            let addr: SocketAddr = ADDR_STR.parse().unwrap();
-            volo_gen::volo::example::ItemServiceClientBuilder::new("volo-example")
-                .layer_outer(LogLayer)
-                .address(addr)
-                .build()
+        let addr: SocketAddr = ADDR_STR.get().unwrap().parse().unwrap();
+        let config = CLIENT_CONFIG.get().unwrap();
+        let builder = volo_gen::volo::example::ItemServiceClientBuilder::new("volo-example")
+            .layer_outer(LogLayer)
+            .address(addr);
+        match config.tls.connect {
+            true => builder.make_transport(TlsMakeTransport::new(&config.tls)).build(),
+            false => builder.build(),
         }
     };
 }
 
 #[volo::main]
 async fn main() {
-    // 获取命令行参数，其为server的IP地址
-    let args: Vec<String> = env::args().collect();
-    unsafe { ADDR_STR = args[1].clone(); }
+    // 获取命令行参数，其为server的IP地址，之后可以以 `--name value` 的形式给出 tls 等配置项
+    let mut args: Vec<String> = env::args().collect();
+    let config = Config::from_args(&mut args).unwrap();
+    ADDR_STR.set(args[1].clone()).unwrap();
+    CLIENT_CONFIG.set(config.client).unwrap();
     tracing_subscriber::fmt::init();
 
-    // 判断当前是否在subscribe状态，若在，则会直接进入无限循环，监听publish程序
+    // 判断当前是否在subscribe状态，若在，则会一直拉取订阅的消息，同时只接受订阅相关的命令
     let mut is_subscribe: bool = false;
-    let mut channel_name: String = String::new();
-    let mut txn_id: usize = usize::MAX;
-    let mut watch_id: String = String::new();
+    // 最近一次收到的消息的序号，下一次拉取时发给服务端以确认之前的消息
+    let mut ack: u64 = 0;
+    // watch 或 multi 返回的事务 id，之后的请求都会携带它
+    let mut txn_id: Option<String> = None;
+    let mut is_multi: bool = false;
+    // 客户端会话的标识，proxy 根据它判断事务属于哪个客户端，服务端根据它保存订阅以及未读的消息
+    let session_id: String = format!("{:032x}", rand::random::<u128>());
+    // select 选择的数据库编号，之后的请求都会携带它，默认为 0 号数据库
+    let mut db: i32 = 0;
+    // 订阅时需要同时等待消息和用户输入，因此使用异步的方式读取标准输入
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdin_closed: bool = false;
 
     loop {
-        if is_subscribe {
-            let subscribe_resp = CLIENT.get_item(
-                volo_gen::volo::example::GetItemRequest {
-                    opcode: 4,
-                    key_channal: channel_name.clone().into(),
-                    value_message: " ".into(),
-                    txn_id: None,
-                }).await;
-            match subscribe_resp {
-                Ok(info) => {
-                    println!("{}", info.value_message);
-                },
-                Err(e) => tracing::error!("{:?}", e),
-            }
-            continue;
-        }
-        print!("mini-redis>  ");
-        let _ = io::stdout().flush();
-        // 读入传入的命令
-        let mut buf: String = String::new();
-        let _ = std::io::stdin().read_line(&mut buf).unwrap();
-        let buf: String = buf.trim().into();
+        let buf: String = match is_subscribe {
+            true => {
+                let poll_resp = CLIENT.get_item(
+                    volo_gen::volo::example::GetItemRequest {
+                        opcode: 14,
+                        key_channal: ack.to_string().into(),
+                        value_message: " ".into(),
+                        txn_id: None,
+                        session_id: Some(session_id.clone().into()),
+                        db: Some(db),
+                        auth: None,
+                    });
+                let line = async {
+                    match stdin_closed {
+                        true => std::future::pending().await,
+                        false => lines.next_line().await,
+                    }
+                };
+                tokio::select! {
+                    poll_resp = poll_resp => {
+                        match poll_resp {
+                            Ok(info) if info.success => {
+                                ack = info.key_channal.parse().unwrap_or(ack);
+                                print_message(&info.value_message);
+                            },
+                            // 在等待时间内没有新的消息
+                            Ok(info) if !info.value_message.starts_with("ERR") => {},
+                            Ok(info) => {
+                                // 例如订阅者因为消息积压过多而被服务端断开
+                                println!("Subscribe Error: {}", info.value_message);
+                                is_subscribe = false;
+                                ack = 0;
+                            },
+                            Err(e) => tracing::error!("{:?}", e),
+                        }
+                        continue;
+                    },
+                    line = line => match line {
+                        Ok(Some(line)) => line.trim().into(),
+                        _ => {
+                            stdin_closed = true;
+                            continue;
+                        }
+                    },
+                }
+            },
+            false => {
+                // 与 redis-cli 相同，选择了非 0 号数据库时在提示符中显示其编号
+                match db {
+                    0 => print!("mini-redis>  "),
+                    _ => print!("mini-redis[{}]>  ", db),
+                }
+                let _ = io::stdout().flush();
+                // 读入传入的命令，标准输入关闭时退出
+                match lines.next_line().await {
+                    Ok(Some(line)) => line.trim().into(),
+                    _ => break,
+                }
+            },
+        };
         // 将读入的命令按照空格分裂成字符串向量
         let command: Vec<String> = parse_command(&buf);
-        if command.len() == 0 {
+        if command.is_empty() {
             println!("error: The command is empty");
             continue;
         }
+        if is_subscribe && !matches!(
+            command[0].to_lowercase().as_str(),
+            "subscribe" | "psubscribe" | "unsubscribe" | "punsubscribe" | "ping" | "exit"
+        ) {
+            println!("Subscribe Error: Only (p)subscribe, (p)unsubscribe, ping and exit are allowed in the subscribe mode");
+            continue;
+        }
         let mut req = volo_gen::volo::example::GetItemRequest {
             opcode: 0,
             key_channal: " ".into(),
             value_message: "pong".into(),
-            txn_id: match txn_id == usize::MAX {
-                true => match watch_id.is_empty() {
-                    true => None,
-                    false => Some(watch_id.clone().into()),
-                },
-                false => Some(txn_id.to_string().into()),
-            },
+            txn_id: txn_id.clone().map(|id| id.into()),
+            session_id: Some(session_id.clone().into()),
+            db: Some(db),
+            auth: None,
         };
         // 判断输入的命令，设置req
         match command[0].to_lowercase().as_str() {
@@ -110,6 +168,38 @@ async fn main() {
                 req.opcode = 2;
                 req.key_channal = command[1].clone().into();
             }
+            "expire" => {
+                // expire命令，第二个参数为key，第三个参数为key的存活时间（秒）
+                if command.len() != 3 {
+                    println!("Usage: expire <key> <seconds>");
+                    continue;
+                }
+                req.opcode = 23;
+                req.key_channal = command[1].clone().into();
+                req.value_message = command[2].clone().into();
+            }
+            "ttl" | "persist" => {
+                // 第二个参数为key
+                if command.len() != 2 {
+                    println!("Usage: {} <key>", command[0]);
+                    continue;
+                }
+                req.opcode = match command[0].to_lowercase().as_str() {
+                    "ttl" => 24,
+                    _ => 25,
+                };
+                req.key_channal = command[1].clone().into();
+            }
+            "memory" => {
+                // memory命令，第二个参数为子命令 usage/stats，usage 的第三个参数为key
+                if command.len() < 2 {
+                    println!("Usage: memory usage <key> | memory stats");
+                    continue;
+                }
+                req.opcode = 26;
+                req.key_channal = command[1].clone().into();
+                req.value_message = command[2..].join(" ").into();
+            }
             "ping" => {
                 // ping命令
                 if command.len() > 2 {
@@ -123,20 +213,46 @@ async fn main() {
                     false => "pong".into(),
                 }
             }
-            "subscribe" => {
-                if command.len() < 2 {
-                    println!("Usage: subscribe <channal_name> ");
+            "subscribe" | "psubscribe" | "unsubscribe" | "punsubscribe" => {
+                // 第二个参数起为频道（或模式），可以同时订阅多个，取消订阅时不指定则取消所有的订阅
+                if command.len() < 2 && matches!(command[0].to_lowercase().as_str(), "subscribe" | "psubscribe") {
+                    println!("Usage: {} <channel_name> [channel_name ...]", command[0]);
                     continue;
                 }
                 if req.txn_id.is_some() {
                     println!("Subscribe Error: Already have a transaction");
                     continue;
                 }
-                is_subscribe = true;
-                req.opcode = 4;
+                req.opcode = match command[0].to_lowercase().as_str() {
+                    "subscribe" => 4,
+                    "psubscribe" => 11,
+                    "punsubscribe" => 12,
+                    _ => 13,
+                };
+                req.key_channal = match command.len() > 1 {
+                    true => command[1..].join(" ").into(),
+                    false => " ".into(),
+                };
+            }
+            "pubsub" => {
+                // pubsub命令，第二个参数为子命令 channels/numsub/numpat，其后为子命令的参数
+                if command.len() < 2 {
+                    println!("Usage: pubsub channels [pattern] | pubsub numsub [channel ...] | pubsub numpat");
+                    continue;
+                }
+                req.opcode = 15;
+                req.key_channal = command[1].clone().into();
+                req.value_message = command[2..].join(" ").into();
+            }
+            "config" => {
+                // config命令，第二个参数为子命令 get/set，其后为配置项的名称以及要设置的值
+                if command.len() < 3 || command.len() > 4 {
+                    println!("Usage: config get <name> | config set <name> [value]");
+                    continue;
+                }
+                req.opcode = 16;
                 req.key_channal = command[1].clone().into();
-                channel_name = command[1].clone();
-                println!("The message is as follow: ");
+                req.value_message = command[2..].join(" ").into();
             }
             "publish" => {
                 if command.len() < 3 {
@@ -151,15 +267,169 @@ async fn main() {
                 req.key_channal = command[1].clone().into();
                 req.value_message = command[2].clone().into();
             }
+            "scan" => {
+                // scan命令，第二个参数为游标，其后为可选的 match/count/type 选项
+                if command.len() < 2 || !command.len().is_multiple_of(2) {
+                    println!("Usage: scan <cursor> [match <pattern>] [count <count>] [type <type>]");
+                    continue;
+                }
+                req.opcode = 6;
+                req.key_channal = command[1].clone().into();
+                req.value_message = command[2..].join(" ").into();
+            }
+            "keys" => {
+                // keys命令，第二个参数为要匹配的模式
+                if command.len() != 2 {
+                    println!("Usage: keys <pattern>");
+                    continue;
+                }
+                req.opcode = 7;
+                req.key_channal = command[1].clone().into();
+            }
+            "select" => {
+                // select命令，第二个参数为要选择的数据库编号
+                if command.len() != 2 {
+                    println!("Usage: select <index>");
+                    continue;
+                }
+                req.opcode = 27;
+                req.key_channal = command[1].clone().into();
+            }
+            "move" => {
+                // move命令，第二个参数为key，第三个参数为要移动到的数据库编号
+                if command.len() != 3 {
+                    println!("Usage: move <key> <db>");
+                    continue;
+                }
+                req.opcode = 28;
+                req.key_channal = command[1].clone().into();
+                req.value_message = command[2].clone().into();
+            }
+            "swapdb" => {
+                // swapdb命令，第二个和第三个参数为要交换的两个数据库的编号
+                if command.len() != 3 {
+                    println!("Usage: swapdb <index1> <index2>");
+                    continue;
+                }
+                req.opcode = 29;
+                req.key_channal = command[1].clone().into();
+                req.value_message = command[2].clone().into();
+            }
+            "auth" => {
+                // auth命令，只给出密码时以 default 用户登录，否则第二个参数为用户名，第三个参数为密码
+                if command.len() < 2 || command.len() > 3 {
+                    println!("Usage: auth [username] <password>");
+                    continue;
+                }
+                req.opcode = 31;
+                (req.key_channal, req.value_message) = match command.len() {
+                    2 => ("default".into(), command[1].clone().into()),
+                    _ => (command[1].clone().into(), command[2].clone().into()),
+                };
+            }
+            "acl" => {
+                // acl命令，第二个参数为子命令 setuser/getuser/deluser/list/whoami/cat，其后为子命令的参数
+                if command.len() < 2 {
+                    println!("Usage: acl setuser <username> [rule ...] | acl getuser <username> | acl deluser <username> [username ...] | acl list | acl whoami | acl cat [category]");
+                    continue;
+                }
+                req.opcode = 32;
+                req.key_channal = command[1].clone().into();
+                req.value_message = command[2..].join(" ").into();
+            }
+            "filter" => {
+                // filter命令，第二个参数为子命令 reload/mode/words，mode 之后可以给出要设置的模式
+                if command.len() < 2 || command.len() > 3 {
+                    println!("Usage: filter reload | filter mode [reject|mask|log] | filter words");
+                    continue;
+                }
+                req.opcode = 33;
+                req.key_channal = command[1].clone().into();
+                req.value_message = command[2..].join(" ").into();
+            }
+            "info" => {
+                // info命令，第二个参数为可选的段名，不给出时输出所有的段
+                if command.len() > 2 {
+                    println!("Usage: info [section]");
+                    continue;
+                }
+                req.opcode = 34;
+                req.key_channal = command.get(1).cloned().unwrap_or_default().into();
+            }
+            "slowlog" => {
+                // slowlog命令，第二个参数为子命令 get/len/reset，get 之后可以给出返回的条数
+                if command.len() < 2 || command.len() > 3 {
+                    println!("Usage: slowlog get [count] | slowlog len | slowlog reset");
+                    continue;
+                }
+                req.opcode = 35;
+                req.key_channal = command[1].clone().into();
+                req.value_message = command[2..].join(" ").into();
+            }
+            "dbsize" | "flushall" | "flushdb" | "randomkey" => {
+                if command.len() > 1 {
+                    println!("Usage: {}", command[0]);
+                    continue;
+                }
+                req.opcode = match command[0].to_lowercase().as_str() {
+                    "dbsize" => 8,
+                    "flushall" => 9,
+                    "flushdb" => 30,
+                    _ => 10,
+                };
+            }
+            "eval" | "evalsha" => {
+                // 第二个参数为脚本（evalsha 为脚本的 sha1），含有空格的脚本需要用双引号括起来，第三个参数为 key 的数量，其后为 key 和参数
+                if command.len() < 3 {
+                    println!("Usage: {} <script> <numkeys> [key ...] [arg ...]", command[0]);
+                    continue;
+                }
+                req.opcode = match command[0].to_lowercase().as_str() {
+                    "eval" => 17,
+                    _ => 18,
+                };
+                req.key_channal = command[1].clone().into();
+                req.value_message = command[2..].join(" ").into();
+            }
+            "script" => {
+                // script命令，第二个参数为子命令 load/exists/flush/kill，其后为子命令的参数
+                if command.len() < 2 {
+                    println!("Usage: script load <script> | script exists <sha1> [sha1 ...] | script flush | script kill");
+                    continue;
+                }
+                req.opcode = 19;
+                req.key_channal = command[1].clone().into();
+                req.value_message = command[2..].join(" ").into();
+            }
+            "function" => {
+                // function命令，第二个参数为子命令 load/list/delete/dump/restore/flush/kill，其后为子命令的参数
+                // 库的代码需要用双引号括起来，其中的换行写作 \n
+                if command.len() < 2 {
+                    println!("Usage: function load [replace] <code> | function list [libraryname <pattern>] [withcode] | function delete <library> | function dump | function restore <payload> [flush|append|replace] | function flush | function kill");
+                    continue;
+                }
+                req.opcode = 20;
+                req.key_channal = command[1].clone().into();
+                req.value_message = command[2..].join(" ").into();
+            }
+            "fcall" | "fcall_ro" => {
+                // 第二个参数为函数名，第三个参数为 key 的数量，其后为 key 和参数
+                if command.len() < 3 {
+                    println!("Usage: {} <function> <numkeys> [key ...] [arg ...]", command[0]);
+                    continue;
+                }
+                req.opcode = match command[0].to_lowercase().as_str() {
+                    "fcall" => 21,
+                    _ => 22,
+                };
+                req.key_channal = command[1].clone().into();
+                req.value_message = command[2..].join(" ").into();
+            }
             "multi" => {
                 if command.len() > 1 {
                     println!("Usage: multi");
                     continue;
                 }
-                req.txn_id = match watch_id.is_empty() {
-                    true => None,
-                    false => Some(watch_id.clone().into()),
-                };
                 req.opcode = 200;
             }
             "exec" => {
@@ -169,17 +439,46 @@ async fn main() {
                 }
                 req.opcode = 201;
             }
+            "discard" | "unwatch" => {
+                if command.len() > 1 {
+                    println!("Usage: {}", command[0]);
+                    continue;
+                }
+                req.opcode = match command[0].to_lowercase().as_str() {
+                    "discard" => 203,
+                    _ => 204,
+                };
+            }
             "watch" => {
+                // 可以同时 watch 多个 key，也可以多次 watch，任意一个 key 被修改时 exec 都会失败
                 if command.len() < 2 {
-                    println!("Usage: watch <key>");
+                    println!("Usage: watch <key> [key ...]");
+                    continue;
+                }
+                req.opcode = 202;
+                req.key_channal = command[1..].join(" ").into();
+            }
+            "topology" => {
+                // 查看集群拓扑以及各节点的健康状态，仅在连接 proxy 时可用
+                if command.len() > 1 {
+                    println!("Usage: topology");
                     continue;
                 }
-                if !watch_id.is_empty() {
-                    println!("Transaction Error: Already have a watch");
+                req.opcode = 300;
+            }
+            "addslave" | "delslave" | "replacemaster" => {
+                // 修改集群拓扑，第二个参数为分片编号，第三个参数为节点地址
+                if command.len() != 3 {
+                    println!("Usage: {} <shard_id> <addr>", command[0]);
                     continue;
                 }
-                req.opcode = 202;
+                req.opcode = match command[0].to_lowercase().as_str() {
+                    "addslave" => 301,
+                    "delslave" => 302,
+                    _ => 303,
+                };
                 req.key_channal = command[1].clone().into();
+                req.value_message = command[2].clone().into();
             }
             _ => {
                 println!("Can't not find the command: {}", command[0]);
@@ -191,6 +490,13 @@ async fn main() {
         let resp = CLIENT.get_item(req).await;
         match resp {
             Ok(info) => {
+                // 事务长时间未使用后会被服务端删除，此时放弃客户端保存的事务
+                if txn_id.is_some() && info.value_message == "ERR no such transaction" {
+                    println!("Transaction Error: The transaction has expired");
+                    txn_id = None;
+                    is_multi = false;
+                    continue;
+                }
                 match OPCode::from(info.opcode) {
                     OPCode::GET => {
                         println!("{}", info.value_message);
@@ -208,12 +514,20 @@ async fn main() {
                             println!("The connect is fail");
                         }
                     }
-                    OPCode::SUBSCRIBE => {
+                    OPCode::SUBSCRIBE | OPCode::PSUBSCRIBE | OPCode::UNSUBSCRIBE | OPCode::PUNSUBSCRIBE => {
+                        // 返回当前会话的订阅数量，仍有订阅时进入订阅状态
                         if info.success {
-                            println!("{}", info.value_message);
-                        }
-                        else {
-                            println!("no publish");
+                            println!("The number of subscriptions is {}", info.value_message);
+                            let was_subscribe = is_subscribe;
+                            is_subscribe = info.value_message != "0";
+                            if is_subscribe && !was_subscribe {
+                                println!("The message is as follow: ");
+                            }
+                            if !is_subscribe {
+                                ack = 0;
+                            }
+                        } else {
+                            println!("Subscribe Error: {}", info.value_message);
                         }
                     }
                     OPCode::PUBLISH => {
@@ -227,9 +541,161 @@ async fn main() {
                             println!("No subscriber found");
                         }
                     }
+                    OPCode::EXPIRE | OPCode::TTL | OPCode::PERSIST => {
+                        // 在事务中排队时返回的是 QUEUED
+                        if info.success && info.value_message.parse::<i64>().is_ok() {
+                            println!("(integer) {}", info.value_message);
+                        } else {
+                            println!("{}", info.value_message);
+                        }
+                    }
+                    OPCode::MEMORY => {
+                        if info.success && info.key_channal.to_lowercase() == "stats" {
+                            print_keys(&info.value_message);
+                        } else if info.success {
+                            println!("(integer) {}", info.value_message);
+                        } else if info.value_message == "(nil)" {
+                            println!("{}", info.value_message);
+                        } else {
+                            println!("Memory Error: {}", info.value_message);
+                        }
+                    }
+                    OPCode::PUBSUB => {
+                        if !info.success {
+                            println!("Pubsub Error: {}", info.value_message);
+                        } else if info.key_channal.to_lowercase() == "numpat" {
+                            println!("{}", info.value_message);
+                        } else {
+                            print_keys(&info.value_message);
+                        }
+                    }
+                    OPCode::CONFIG => {
+                        if !info.success {
+                            println!("Config Error: {}", info.value_message);
+                        } else if info.key_channal.to_lowercase() == "get" {
+                            // 配置项的值可能为空，因此不能按行输出
+                            let (name, value) = info.value_message.split_once('\n').unwrap_or((&info.value_message, ""));
+                            println!("1) \"{}\"\n2) \"{}\"", name, value);
+                        } else {
+                            println!("{}", info.value_message);
+                        }
+                    }
+                    OPCode::SCAN => {
+                        // 先输出下一次使用的游标，再逐行输出本次扫描到的 key
+                        if info.success {
+                            println!("cursor: {}", info.key_channal);
+                            print_keys(&info.value_message);
+                        } else {
+                            println!("Scan Error: {}", info.value_message);
+                        }
+                    }
+                    OPCode::KEYS => {
+                        print_keys(&info.value_message);
+                    }
+                    OPCode::SELECT => {
+                        // 选择成功后（在事务中为排队成功后）之后的请求都使用新的数据库
+                        if info.success {
+                            db = info.key_channal.parse().unwrap_or(db);
+                            println!("{}", info.value_message);
+                        } else {
+                            println!("Select Error: {}", info.value_message);
+                        }
+                    }
+                    OPCode::MOVE => {
+                        if info.success && info.value_message.parse::<i64>().is_ok() {
+                            println!("(integer) {}", info.value_message);
+                        } else {
+                            println!("{}", info.value_message);
+                        }
+                    }
+                    OPCode::AUTH => {
+                        if info.success {
+                            println!("{}", info.value_message);
+                        } else {
+                            println!("Auth Error: {}", info.value_message);
+                        }
+                    }
+                    OPCode::ACL => {
+                        if info.value_message == "(nil)" {
+                            println!("{}", info.value_message);
+                        } else if !info.success {
+                            println!("Acl Error: {}", info.value_message);
+                        } else if matches!(info.key_channal.to_lowercase().as_str(), "list" | "getuser" | "cat") {
+                            print_keys(&info.value_message);
+                        } else {
+                            println!("{}", info.value_message);
+                        }
+                    }
+                    OPCode::FILTER => {
+                        if !info.success {
+                            println!("Filter Error: {}", info.value_message);
+                        } else if info.key_channal.to_lowercase() == "words" {
+                            print_keys(&info.value_message);
+                        } else if info.key_channal.to_lowercase() == "reload" {
+                            println!("(integer) {}", info.value_message);
+                        } else {
+                            println!("{}", info.value_message);
+                        }
+                    }
+                    OPCode::INFO => {
+                        if info.success {
+                            println!("{}", info.value_message);
+                        } else {
+                            println!("Info Error: {}", info.value_message);
+                        }
+                    }
+                    OPCode::SLOWLOG => {
+                        if !info.success {
+                            println!("Slowlog Error: {}", info.value_message);
+                        } else if info.key_channal.to_lowercase() == "len" {
+                            println!("(integer) {}", info.value_message);
+                        } else if info.value_message.is_empty() {
+                            println!("(empty array)");
+                        } else {
+                            println!("{}", info.value_message);
+                        }
+                    }
+                    OPCode::DBSIZE | OPCode::FLUSHALL | OPCode::FLUSHDB | OPCode::SWAPDB | OPCode::RANDOMKEY => {
+                        println!("{}", info.value_message);
+                    }
+                    OPCode::EVAL | OPCode::EVALSHA | OPCode::FCALL | OPCode::FCALLRO => {
+                        // 服务端在 key_channal 中返回脚本或函数返回值的类型
+                        if !info.success {
+                            match info.opcode {
+                                21 | 22 => println!("Function Error: {}", info.value_message),
+                                _ => println!("Script Error: {}", info.value_message),
+                            }
+                        } else {
+                            match info.key_channal.as_str() {
+                                "integer" => println!("(integer) {}", info.value_message),
+                                "string" => println!("\"{}\"", info.value_message),
+                                "array" => print_keys(&info.value_message),
+                                _ => println!("{}", info.value_message),
+                            }
+                        }
+                    }
+                    OPCode::SCRIPT => {
+                        if !info.success {
+                            println!("Script Error: {}", info.value_message);
+                        } else if info.key_channal.to_lowercase() == "exists" {
+                            for (index, exists) in info.value_message.lines().enumerate() {
+                                println!("{}) (integer) {}", index + 1, exists);
+                            }
+                        } else {
+                            println!("{}", info.value_message);
+                        }
+                    }
+                    OPCode::FUNCTION => {
+                        if info.success {
+                            println!("{}", info.value_message);
+                        } else {
+                            println!("Function Error: {}", info.value_message);
+                        }
+                    }
                     OPCode::MULTI => {
                         if info.success {
-                            txn_id = info.key_channal.parse().unwrap();
+                            txn_id = Some(info.key_channal.to_string());
+                            is_multi = true;
                             println!("{}", info.value_message);
                         } else {
                             println!("Transaction Error: {}", info.value_message);
@@ -241,17 +707,40 @@ async fn main() {
                         } else {
                             println!("Transaction Error: {}", info.value_message);
                         }
-                        txn_id = usize::MAX;
-                        watch_id = String::new();
+                        txn_id = None;
+                        is_multi = false;
+                    }
+                    OPCode::DISCARD => {
+                        if info.success {
+                            txn_id = None;
+                            is_multi = false;
+                            println!("{}", info.value_message);
+                        } else {
+                            println!("Transaction Error: {}", info.value_message);
+                        }
+                    }
+                    OPCode::UNWATCH => {
+                        // 在 multi 中 unwatch 会被放入事务中排队
+                        if !is_multi {
+                            txn_id = None;
+                        }
+                        println!("{}", info.value_message);
                     }
                     OPCode::WATCH => {
                         if info.success {
-                            watch_id = info.key_channal.clone().into();
+                            txn_id = Some(info.key_channal.to_string());
                             println!("{}", info.value_message);
                         } else {
                             println!("Transaction Error: {}", info.value_message);
                         }
                     }
+                    OPCode::TOPOLOGY | OPCode::ADDSLAVE | OPCode::DELSLAVE | OPCode::REPLACEMASTER => {
+                        if info.success {
+                            println!("{}", info.value_message);
+                        } else {
+                            println!("Topology Error: {}", info.value_message);
+                        }
+                    }
                     _ => {
                         println!("error: The opcode is error");
                     }
@@ -262,16 +751,51 @@ async fn main() {
     }
 }
 
-fn parse_command(buf: &String) -> Vec<String> {
+// 按照空格分裂命令，双引号中的内容作为一个参数，其中可以用反斜杠转义双引号，\n 表示换行
+fn parse_command(buf: &str) -> Vec<String> {
     let mut v: Vec<String> = Vec::new();
-    let v1: Vec<&str> = buf.split(" ").collect();
-    for s in v1 {
-        v.push(s.into());
+    let mut chars = buf.chars();
+    let mut current = String::new();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => match chars.next() {
+                Some('n') => current.push('\n'),
+                next => current.extend(next),
+            },
+            ' ' if !quoted => v.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
     }
+    v.push(current);
     v
 }
 
-fn get_num(v: &Vec<char>) -> i32 {
+// 输出订阅收到的消息，模式订阅的消息会同时输出匹配的模式
+fn print_message(message: &str) {
+    let parts: Vec<&str> = message.splitn(4, '\n').collect();
+    match parts[0] {
+        "message" if parts.len() >= 3 => println!("{}: {}", parts[1], message.splitn(3, '\n').nth(2).unwrap()),
+        "pmessage" if parts.len() == 4 => println!("[{}] {}: {}", parts[1], parts[2], parts[3]),
+        // 订阅者处理过慢时服务端丢弃的消息数量
+        "dropped" if parts.len() == 2 => println!("({} messages dropped)", parts[1]),
+        _ => println!("{}", message),
+    }
+}
+
+// 按照 redis-cli 的格式逐行输出 key 列表
+fn print_keys(keys: &str) {
+    if keys.trim().is_empty() {
+        println!("(empty array)");
+        return;
+    }
+    for (index, key) in keys.lines().enumerate() {
+        println!("{}) \"{}\"", index + 1, key);
+    }
+}
+
+fn get_num(v: &[char]) -> i32 {
     let mut index = 0;
     let mut res = 0;
     while index < v.len() {
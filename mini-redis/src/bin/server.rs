@@ -1,19 +1,19 @@
-#![feature(impl_trait_in_assoc_type)]
 use std::{
     net::SocketAddr,
     env,
 };
 
-use mini_redis::{S, LogLayer};
+use mini_redis::{S, LogLayer, AclLayer, FilterLayer, MetricsLayer, SlowLogLayer, Config, TlsIncoming};
 use volo_gen::volo::example::GetItemRequest;
 
 #[volo::main]
 async fn main() {
     // get args from env
-    let args = env::args().collect::<Vec<_>>();
+    let mut args = env::args().collect::<Vec<_>>();
     println!("{:?}", args);
+    let config = Config::from_args(&mut args).unwrap();
     if args.len() < 3 {
-        panic!("Usage: {} <host> <port> [slave_addr]", args[0]);
+        panic!("Usage: {} <host> <port> [slave_addr] [--option value]", args[0]);
     }
 
     // get host, port and judge if it is a master
@@ -24,11 +24,9 @@ async fn main() {
         .iter()
         .map(|s| s.parse::<SocketAddr>().unwrap())
         .collect::<Vec<_>>();
-    let is_master = !slave_addr.is_empty();
 
     // get SocketAddr and log_path
     let addr = format!("{}:{}", host, port).parse::<SocketAddr>().unwrap();
-    let addr = volo::net::Address::from(addr);
     let log_path = format!(
         "log/{}_{}_{}.log",
         host,
@@ -39,22 +37,54 @@ async fn main() {
         }
     );
 
+    // the listener speaks TLS once a certificate is given
+    let acceptor = config.client.tls.acceptor().unwrap();
+    let metrics_port = config.metrics_port;
+
     // create server
-    let server = S::new(slave_addr, log_path.as_str()).await;
+    let server = match S::new(slave_addr, log_path.as_str(), config).await {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Failed to start the server: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // store log_file and op_tx for graceful shutdown
     let log_file = server.log_file.clone();
-    let op_tx = match is_master {
-        true => Some(server.op_tx.as_ref().unwrap().clone()),
-        false => None,
-    };
+    let op_tx = server.op_tx.clone();
+    // the users are checked by the AclLayer before the requests reach the server
+    let acl = server.acl.clone();
+    // then the content of the requests is checked by the FilterLayer
+    let filter = server.filter.clone();
+    // and the requests passing the filter are timed by the SlowLogLayer
+    let slowlog = server.slowlog.clone();
+    // every request, even the refused ones, is counted by the MetricsLayer
+    let metrics = server.metrics.clone();
+
+    // serve the metrics for prometheus on the host of the node
+    if metrics_port != 0 {
+        let state = server.clone();
+        let metrics_addr = SocketAddr::new(addr.ip(), metrics_port);
+        tokio::spawn(async move {
+            if let Err(e) = mini_redis::serve_metrics(metrics_addr, move || state.metrics_text()).await {
+                tracing::error!("Failed to serve the metrics on {}: {}", metrics_addr, e);
+            }
+        });
+    }
 
     // run server
-    volo_gen::volo::example::ItemServiceServer::new(server)
-        .layer_front(LogLayer)
-        .run(addr)
-        .await
-        .unwrap();
+    let server = volo_gen::volo::example::ItemServiceServer::new(server)
+        .layer_front(SlowLogLayer(slowlog))
+        .layer_front(FilterLayer(filter))
+        .layer_front(AclLayer(acl))
+        .layer_front(MetricsLayer(metrics))
+        .layer_front(LogLayer);
+    match acceptor {
+        Some(acceptor) => server.run(TlsIncoming::new(addr, acceptor)).await,
+        None => server.run(volo::net::Address::from(addr)).await,
+    }
+    .unwrap();
 
     // If this is a master, we need to wait for all the requests to be processed
     // For exapmle, we need to wait for all requests are broadcasted to slaves
@@ -62,11 +92,14 @@ async fn main() {
 
     if let Some(op_tx) = op_tx {
         tracing::info!("Server {}:{} is closing spawned tasks by using broadcast channel", host, port);
-        match op_tx.lock().unwrap().send(GetItemRequest {
+        match op_tx.send(GetItemRequest {
             opcode: 255,
             key_channal: "".into(),
             value_message: "".into(),
             txn_id: None,
+            session_id: None,
+            db: None,
+            auth: None,
         }) {
             Ok(_) => tracing::info!("Server {}:{} is closed spawned tasks successfully", host, port),
             Err(e) => tracing::error!("Server {}:{} is closed spawned tasks failed: {}", host, port, e),
@@ -76,7 +109,7 @@ async fn main() {
 
     // Sync log file to disk to avoid data loss
     tracing::info!("Server {}:{} is syncing log file", host, port);
-    let result = log_file.lock().await.sync_all().await;
+    let result = log_file.lock().await.file.sync_all().await;
     match result {
         Ok(_) => tracing::info!("Sync log file successfully"),
         Err(e) => tracing::error!("Sync log file failed: {}", e),
@@ -0,0 +1,191 @@
+use std::{
+    net::SocketAddr,
+    sync::{Mutex, atomic::{AtomicUsize, Ordering}},
+    time::{Duration, Instant},
+};
+use anyhow::Error;
+use rand::Rng;
+use volo_gen::volo::example::{GetItemRequest, GetItemResponse};
+
+use crate::{pubsub::POLL_TIMEOUT, OPCode, TlsConfig, TlsMakeTransport};
+
+// the options of the client connecting to other nodes
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    pub connect_timeout: Duration,
+    pub request_timeout: Duration,      // added to the time the node may wait for the blocking commands such as POLL
+    pub max_retries: usize,             // only idempotent commands are retried
+    pub retry_backoff: Duration,        // the backoff before the first retry, doubled on each retry
+    pub pool_max_idle: usize,           // the max idle connections kept in the pool
+    pub pool_idle_timeout: Duration,
+    pub breaker_threshold: usize,       // open the circuit after so many consecutive failures
+    pub breaker_cooldown: Duration,     // how long the circuit stays open before a trial request
+    pub auth_user: String,              // the user the requests are sent as, once auth_password is given
+    pub auth_password: Option<String>,
+    pub tls: TlsConfig,                 // also used by the listener of the node, see TlsConfig
+}
+
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            connect_timeout: Duration::from_secs(1),
+            request_timeout: Duration::from_secs(3),
+            max_retries: 2,
+            retry_backoff: Duration::from_millis(50),
+            pool_max_idle: 64,
+            pool_idle_timeout: Duration::from_secs(15),
+            breaker_threshold: 5,
+            breaker_cooldown: Duration::from_secs(5),
+            auth_user: "default".to_string(),
+            auth_password: None,
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+impl ClientConfig {
+    // the jittered backoff before the n-th retry (starting from 0)
+    fn backoff(&self, retry: usize) -> Duration {
+        let backoff = self.retry_backoff.saturating_mul(1 << retry.min(16));
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    // the credentials `<user> <password>` carried by each request, checked by the AclLayer of the other node
+    pub fn credentials(&self) -> Option<String> {
+        self.auth_password.as_ref().map(|password| format!("{} {}", self.auth_user, password))
+    }
+}
+
+// CircuitBreaker fails the requests fast when the node keeps erroring
+// closed: requests are sent, consecutive failures are counted
+// open: requests fail immediately until the cooldown is over
+// half-open: after the cooldown, requests are sent again and a single failure reopens the circuit
+pub struct CircuitBreaker {
+    threshold: usize,
+    cooldown: Duration,
+    failures: AtomicUsize,
+    open_until: Mutex<Option<Instant>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: usize, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            failures: AtomicUsize::new(0),
+            open_until: Mutex::new(None),
+        }
+    }
+
+    pub fn allow(&self) -> bool {
+        match *self.open_until.lock().unwrap() {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        !self.allow()
+    }
+
+    pub fn on_success(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+        *self.open_until.lock().unwrap() = None;
+    }
+
+    pub fn on_failure(&self) {
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.threshold {
+            *self.open_until.lock().unwrap() = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+// package the redis client
+pub struct RedisClient {
+    addr: SocketAddr,
+    client: volo_gen::volo::example::ItemServiceClient,
+    config: ClientConfig,
+    breaker: CircuitBreaker,
+}
+
+impl RedisClient {
+    pub fn new(addr: SocketAddr) -> RedisClient {
+        RedisClient::with_config(addr, ClientConfig::default())
+    }
+
+    pub fn with_config(addr: SocketAddr, config: ClientConfig) -> RedisClient {
+        RedisClient {
+            addr,
+            client: {
+                let builder = volo_gen::volo::example::ItemServiceClientBuilder::new("volo-example")
+                    .address(addr)
+                    .connect_timeout(Some(config.connect_timeout))
+                    .pool_config(volo_thrift::transport::pool::Config::new(config.pool_max_idle, config.pool_idle_timeout));
+                match config.tls.connect {
+                    true => builder.make_transport(TlsMakeTransport::new(&config.tls)).build(),
+                    false => builder.build(),
+                }
+            },
+            breaker: CircuitBreaker::new(config.breaker_threshold, config.breaker_cooldown),
+            config,
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    // send the request once, the blocking commands wait up to POLL_TIMEOUT on the node, which is added to their timeout
+    // volo reports the connection errors as application errors, so all the errors are counted as failures of the node
+    async fn try_get_item(&self, mut req: GetItemRequest) -> Result<GetItemResponse, Error> {
+        let opcode = OPCode::from(req.opcode);
+        if req.auth.is_none() {
+            req.auth = self.config.credentials().map(|credentials| credentials.into());
+        }
+        let timeout = match opcode.is_blocking() {
+            true => POLL_TIMEOUT + self.config.request_timeout,
+            false => self.config.request_timeout,
+        };
+        match tokio::time::timeout(timeout, self.client.get_item(req)).await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(Error::msg(format!("Request to {} timed out", self.addr))),
+        }
+    }
+
+    pub async fn get_item(&self, req: GetItemRequest) -> ::core::result::Result<GetItemResponse, Error> {
+        let retries = match OPCode::from(req.opcode).is_idempotent() {
+            true => self.config.max_retries,
+            false => 0,
+        };
+        let mut retry = 0;
+        loop {
+            if !self.breaker.allow() {
+                tracing::error!("Circuit breaker of {} is open", self.addr);
+                return Err(Error::msg(format!("Circuit breaker of {} is open", self.addr)));
+            }
+            match self.try_get_item(req.clone()).await {
+                Ok(resp) => {
+                    self.breaker.on_success();
+                    tracing::info!("Get response: {:?}", resp);
+                    return Ok(resp);
+                },
+                Err(e) => {
+                    self.breaker.on_failure();
+                    if retry >= retries {
+                        tracing::error!("Get error: {:?}", e);
+                        return Err(e);
+                    }
+                    let backoff = self.config.backoff(retry);
+                    tracing::warn!("Request to {} failed: {:?}, retry in {}ms", self.addr, e, backoff.as_millis());
+                    tokio::time::sleep(backoff).await;
+                    retry += 1;
+                }
+            }
+        }
+    }
+}
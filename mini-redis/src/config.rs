@@ -0,0 +1,353 @@
+use std::time::Duration;
+use anyhow::Error;
+
+use crate::{BufferLimits, ClientAuth, ClientConfig, FilterMode};
+
+// the classes of the keyspace events to publish, given as the flags of redis
+// K: keyspace events on __keyspace@<db>__:<key>, E: keyevent events on __keyevent@<db>__:<event>
+// g: generic commands such as del, $: string commands such as set, x: expired keys, e: evicted keys, A: alias of g$xe
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyspaceEvents {
+    pub keyspace: bool,
+    pub keyevent: bool,
+    pub generic: bool,
+    pub string: bool,
+    pub expired: bool,
+    pub evicted: bool,
+}
+
+impl KeyspaceEvents {
+    fn parse(flags: &str) -> Result<KeyspaceEvents, Error> {
+        let mut events = KeyspaceEvents::default();
+        for flag in flags.chars() {
+            match flag {
+                'K' => events.keyspace = true,
+                'E' => events.keyevent = true,
+                'g' => events.generic = true,
+                '$' => events.string = true,
+                'x' => events.expired = true,
+                'e' => events.evicted = true,
+                'A' => {
+                    events.generic = true;
+                    events.string = true;
+                    events.expired = true;
+                    events.evicted = true;
+                },
+                _ => return Err(Error::msg(format!("Invalid value for notify-keyspace-events: {}", flags))),
+            }
+        }
+        Ok(events)
+    }
+
+    // whether the events of the class are published, the class is one of the flags g$xe
+    pub fn is_enabled(&self, class: char) -> bool {
+        (self.keyspace || self.keyevent) && match class {
+            'g' => self.generic,
+            '$' => self.string,
+            'x' => self.expired,
+            'e' => self.evicted,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for KeyspaceEvents {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flags = [
+            (self.generic, 'g'),
+            (self.string, '$'),
+            (self.expired, 'x'),
+            (self.evicted, 'e'),
+            (self.keyspace, 'K'),
+            (self.keyevent, 'E'),
+        ];
+        let flags: String = flags.iter().filter(|(enabled, _)| *enabled).map(|(_, flag)| *flag).collect();
+        write!(f, "{}", flags)
+    }
+}
+
+// how to free the memory once the keys take more than maxmemory, the volatile policies evict the keys with an expire time only
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MaxmemoryPolicy {
+    #[default]
+    NoEviction,         // refuse the writes instead
+    AllkeysLru,         // the least recently used keys
+    AllkeysLfu,         // the least frequently used keys
+    AllkeysRandom,
+    VolatileLru,
+    VolatileLfu,
+    VolatileRandom,
+    VolatileTtl,        // the keys closest to expire
+}
+
+const POLICIES: &[(MaxmemoryPolicy, &str)] = &[
+    (MaxmemoryPolicy::NoEviction, "noeviction"),
+    (MaxmemoryPolicy::AllkeysLru, "allkeys-lru"),
+    (MaxmemoryPolicy::AllkeysLfu, "allkeys-lfu"),
+    (MaxmemoryPolicy::AllkeysRandom, "allkeys-random"),
+    (MaxmemoryPolicy::VolatileLru, "volatile-lru"),
+    (MaxmemoryPolicy::VolatileLfu, "volatile-lfu"),
+    (MaxmemoryPolicy::VolatileRandom, "volatile-random"),
+    (MaxmemoryPolicy::VolatileTtl, "volatile-ttl"),
+];
+
+impl MaxmemoryPolicy {
+    fn parse(value: &str) -> Result<MaxmemoryPolicy, Error> {
+        POLICIES
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(value))
+            .map(|(policy, _)| *policy)
+            .ok_or(Error::msg(format!("Invalid value for maxmemory-policy: {}", value)))
+    }
+
+    pub fn is_volatile(&self) -> bool {
+        matches!(
+            self,
+            MaxmemoryPolicy::VolatileLru | MaxmemoryPolicy::VolatileLfu | MaxmemoryPolicy::VolatileRandom | MaxmemoryPolicy::VolatileTtl
+        )
+    }
+}
+
+impl std::fmt::Display for MaxmemoryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = POLICIES.iter().find(|(policy, _)| policy == self).map(|(_, name)| *name).unwrap_or_default();
+        write!(f, "{}", name)
+    }
+}
+
+// when the AOF is flushed to the disk
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AppendFsync {
+    Always,             // after each write, before the request is answered
+    #[default]
+    EverySec,           // once a second, so at most a second of writes is lost on a crash
+    No,                 // left to the operating system
+}
+
+const FSYNC_POLICIES: &[(AppendFsync, &str)] = &[
+    (AppendFsync::Always, "always"),
+    (AppendFsync::EverySec, "everysec"),
+    (AppendFsync::No, "no"),
+];
+
+impl AppendFsync {
+    fn parse(value: &str) -> Result<AppendFsync, Error> {
+        FSYNC_POLICIES
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(value))
+            .map(|(policy, _)| *policy)
+            .ok_or(Error::msg(format!("Invalid value for appendfsync: {}", value)))
+    }
+}
+
+impl std::fmt::Display for AppendFsync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = FSYNC_POLICIES.iter().find(|(policy, _)| policy == self).map(|(_, name)| *name).unwrap_or_default();
+        write!(f, "{}", name)
+    }
+}
+
+// the options of the server, given on the command line as `--name value`
+// the options in RUNTIME_OPTIONS can also be changed by CONFIG SET
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub client: ClientConfig,                       // the options of the client connecting to the slave nodes, and the TLS options
+    pub notify_keyspace_events: KeyspaceEvents,     // off by default
+    pub pubsub_limits: BufferLimits,                // the output buffer limits of each subscriber
+    pub txn_idle_timeout: Duration,                 // a transaction not used for so long is dropped
+    pub lua_time_limit: Duration,                   // the other requests are refused while a script runs for longer
+    pub maxmemory: usize,                           // the bytes the keys may take on the master, 0 for no limit
+    pub maxmemory_policy: MaxmemoryPolicy,
+    pub maxmemory_samples: usize,                   // the number of the keys sampled to choose the one to evict
+    pub databases: usize,                           // the number of the logical databases, numbered from 0
+    pub requirepass: Option<String>,                // the password of the default user, which needs none by default
+    pub aclfile: Option<String>,                    // the users loaded on startup, see Acl::new
+    pub filter_file: Option<String>,                // the words filtered by the FilterLayer, see ContentFilter
+    pub filter_mode: FilterMode,
+    pub appendfsync: AppendFsync,
+    pub metrics_port: u16,                          // the port of the prometheus metrics on the host of the node, 0 for none
+    pub slowlog_log_slower_than: i64,               // the microseconds a command takes to be logged, negative to log none
+    pub slowlog_max_len: usize,                     // the number of the slow commands kept
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            client: ClientConfig::default(),
+            notify_keyspace_events: KeyspaceEvents::default(),
+            pubsub_limits: BufferLimits::default(),
+            txn_idle_timeout: Duration::from_secs(300),
+            lua_time_limit: Duration::from_secs(5),
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::default(),
+            maxmemory_samples: 5,
+            databases: 16,
+            requirepass: None,
+            aclfile: None,
+            filter_file: None,
+            filter_mode: FilterMode::default(),
+            appendfsync: AppendFsync::default(),
+            metrics_port: 0,
+            slowlog_log_slower_than: 10000,
+            slowlog_max_len: 128,
+        }
+    }
+}
+
+pub const RUNTIME_OPTIONS: &[&str] = &[
+    "notify-keyspace-events",
+    "pubsub-hard-limit",
+    "pubsub-soft-limit",
+    "pubsub-soft-seconds",
+    "lua-time-limit-ms",
+    "maxmemory",
+    "maxmemory-policy",
+    "maxmemory-samples",
+    "slowlog-log-slower-than",
+    "slowlog-max-len",
+];
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, Error> {
+    value.parse::<T>().map_err(|_| Error::msg(format!("Invalid value for {}: {}", name, value)))
+}
+
+// the memory is given in bytes, or with the unit kb, mb or gb such as 100mb
+fn parse_memory(name: &str, value: &str) -> Result<usize, Error> {
+    let lower = value.to_lowercase();
+    let (number, unit) = [("kb", 1 << 10), ("mb", 1 << 20), ("gb", 1 << 30)]
+        .iter()
+        .find_map(|(suffix, unit)| lower.strip_suffix(suffix).map(|number| (number, *unit)))
+        .unwrap_or((&lower, 1));
+    parse::<usize>(name, number)?
+        .checked_mul(unit)
+        .ok_or(Error::msg(format!("Invalid value for {}: {}", name, value)))
+}
+
+fn parse_millis(name: &str, value: &str) -> Result<Duration, Error> {
+    Ok(Duration::from_millis(parse(name, value)?))
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, Error> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(Error::msg(format!("Invalid value for {}: {}", name, value))),
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    match value {
+        true => "yes".to_string(),
+        false => "no".to_string(),
+    }
+}
+
+impl Config {
+    // take the options out of the args, and leave the positional args in it
+    pub fn from_args(args: &mut Vec<String>) -> Result<Config, Error> {
+        let mut config = Config::default();
+        let mut positional = Vec::new();
+        let mut iter = args.drain(..);
+        while let Some(arg) = iter.next() {
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let value = iter.next().ok_or(Error::msg(format!("Missing value for option --{}", name)))?;
+                    config.set(name, &value)?;
+                },
+                None => positional.push(arg),
+            }
+        }
+        drop(iter);
+        *args = positional;
+        config.client.tls.validate()?;
+        Ok(config)
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        match name {
+            "connect-timeout-ms" => self.client.connect_timeout = parse_millis(name, value)?,
+            "request-timeout-ms" => self.client.request_timeout = parse_millis(name, value)?,
+            "max-retries" => self.client.max_retries = parse(name, value)?,
+            "retry-backoff-ms" => self.client.retry_backoff = parse_millis(name, value)?,
+            "pool-max-idle" => self.client.pool_max_idle = parse(name, value)?,
+            "pool-idle-timeout-ms" => self.client.pool_idle_timeout = parse_millis(name, value)?,
+            "breaker-threshold" => self.client.breaker_threshold = parse(name, value)?,
+            "breaker-cooldown-ms" => self.client.breaker_cooldown = parse_millis(name, value)?,
+            "auth-user" => self.client.auth_user = value.to_string(),
+            "auth-password" => self.client.auth_password = Some(value.to_string()),
+            "tls" => self.client.tls.connect = parse_bool(name, value)?,
+            "tls-cert-file" => self.client.tls.cert_file = Some(value.to_string()),
+            "tls-key-file" => self.client.tls.key_file = Some(value.to_string()),
+            "tls-ca-cert-file" => self.client.tls.ca_cert_file = Some(value.to_string()),
+            "tls-auth-clients" => self.client.tls.auth_clients = ClientAuth::parse(value)?,
+            "tls-server-name" => self.client.tls.server_name = Some(value.to_string()),
+            "notify-keyspace-events" => self.notify_keyspace_events = KeyspaceEvents::parse(value)?,
+            "pubsub-hard-limit" => self.pubsub_limits.hard = parse(name, value)?,
+            "pubsub-soft-limit" => self.pubsub_limits.soft = parse(name, value)?,
+            "pubsub-soft-seconds" => self.pubsub_limits.soft_duration = Duration::from_secs(parse(name, value)?),
+            "txn-idle-timeout-ms" => self.txn_idle_timeout = parse_millis(name, value)?,
+            "lua-time-limit-ms" => self.lua_time_limit = parse_millis(name, value)?,
+            "maxmemory" => self.maxmemory = parse_memory(name, value)?,
+            "maxmemory-policy" => self.maxmemory_policy = MaxmemoryPolicy::parse(value)?,
+            "maxmemory-samples" => match parse(name, value)? {
+                0 => return Err(Error::msg(format!("Invalid value for {}: {}", name, value))),
+                samples => self.maxmemory_samples = samples,
+            },
+            "databases" => match parse(name, value)? {
+                0 => return Err(Error::msg(format!("Invalid value for {}: {}", name, value))),
+                databases => self.databases = databases,
+            },
+            "requirepass" => self.requirepass = Some(value.to_string()),
+            "aclfile" => self.aclfile = Some(value.to_string()),
+            "filter-file" => self.filter_file = Some(value.to_string()),
+            "filter-mode" => self.filter_mode = FilterMode::parse(value)?,
+            "appendfsync" => self.appendfsync = AppendFsync::parse(value)?,
+            "metrics-port" => self.metrics_port = parse(name, value)?,
+            "slowlog-log-slower-than" => self.slowlog_log_slower_than = parse(name, value)?,
+            "slowlog-max-len" => self.slowlog_max_len = parse(name, value)?,
+            _ => return Err(Error::msg(format!("Unknown option: {}", name))),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<String, Error> {
+        let value = match name {
+            "connect-timeout-ms" => self.client.connect_timeout.as_millis().to_string(),
+            "request-timeout-ms" => self.client.request_timeout.as_millis().to_string(),
+            "max-retries" => self.client.max_retries.to_string(),
+            "retry-backoff-ms" => self.client.retry_backoff.as_millis().to_string(),
+            "pool-max-idle" => self.client.pool_max_idle.to_string(),
+            "pool-idle-timeout-ms" => self.client.pool_idle_timeout.as_millis().to_string(),
+            "breaker-threshold" => self.client.breaker_threshold.to_string(),
+            "breaker-cooldown-ms" => self.client.breaker_cooldown.as_millis().to_string(),
+            "auth-user" => self.client.auth_user.clone(),
+            "auth-password" => self.client.auth_password.clone().unwrap_or_default(),
+            "tls" => yes_no(self.client.tls.connect),
+            "tls-cert-file" => self.client.tls.cert_file.clone().unwrap_or_default(),
+            "tls-key-file" => self.client.tls.key_file.clone().unwrap_or_default(),
+            "tls-ca-cert-file" => self.client.tls.ca_cert_file.clone().unwrap_or_default(),
+            "tls-auth-clients" => self.client.tls.auth_clients.to_string(),
+            "tls-server-name" => self.client.tls.server_name.clone().unwrap_or_default(),
+            "notify-keyspace-events" => self.notify_keyspace_events.to_string(),
+            "pubsub-hard-limit" => self.pubsub_limits.hard.to_string(),
+            "pubsub-soft-limit" => self.pubsub_limits.soft.to_string(),
+            "pubsub-soft-seconds" => self.pubsub_limits.soft_duration.as_secs().to_string(),
+            "txn-idle-timeout-ms" => self.txn_idle_timeout.as_millis().to_string(),
+            "lua-time-limit-ms" => self.lua_time_limit.as_millis().to_string(),
+            "maxmemory" => self.maxmemory.to_string(),
+            "maxmemory-policy" => self.maxmemory_policy.to_string(),
+            "maxmemory-samples" => self.maxmemory_samples.to_string(),
+            "databases" => self.databases.to_string(),
+            "requirepass" => self.requirepass.clone().unwrap_or_default(),
+            "aclfile" => self.aclfile.clone().unwrap_or_default(),
+            "filter-file" => self.filter_file.clone().unwrap_or_default(),
+            "filter-mode" => self.filter_mode.to_string(),
+            "appendfsync" => self.appendfsync.to_string(),
+            "metrics-port" => self.metrics_port.to_string(),
+            "slowlog-log-slower-than" => self.slowlog_log_slower_than.to_string(),
+            "slowlog-max-len" => self.slowlog_max_len.to_string(),
+            _ => return Err(Error::msg(format!("Unknown option: {}", name))),
+        };
+        Ok(value)
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use anyhow::Error;
+
+use crate::{glob_match, script};
+
+// a function registered by a library, only the functions with the no-writes flag can be called by FCALL_RO
+#[derive(Clone, Debug)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub no_writes: bool,
+}
+
+// a library of functions, the version starts from 1 and increases each time the library is replaced
+#[derive(Clone, Debug)]
+pub struct Library {
+    pub name: String,
+    pub code: String,
+    pub version: u64,
+    pub functions: Vec<FunctionInfo>,
+}
+
+// what to do with the existing libraries on FUNCTION RESTORE
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RestorePolicy {
+    Append,     // fail if any library already exists
+    Replace,    // replace the existing libraries with the same names
+    Flush,      // delete all the existing libraries first
+}
+
+impl RestorePolicy {
+    pub fn parse(policy: Option<&str>) -> Result<RestorePolicy, Error> {
+        match policy.map(|policy| policy.to_lowercase()).as_deref() {
+            None | Some("append") => Ok(RestorePolicy::Append),
+            Some("replace") => Ok(RestorePolicy::Replace),
+            Some("flush") => Ok(RestorePolicy::Flush),
+            Some(_) => Err(Error::msg("ERR syntax error")),
+        }
+    }
+}
+
+// the code is hex encoded in the AOF and in the payload of FUNCTION DUMP, so that it fits in a single line
+pub fn to_hex(text: &str) -> String {
+    text.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Result<String, Error> {
+    let invalid = || Error::msg("ERR payload is not valid");
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2).ok_or_else(invalid)?, 16).map_err(|_| invalid()))
+        .collect::<Result<Vec<u8>, Error>>()?;
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+// the names of the libraries and the functions can only contain letters, numbers and underscores
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// the name of the library, given in the first line of its code as `#!lua name=<name>`
+pub fn library_name(code: &str) -> Result<String, Error> {
+    let metadata = code.lines().next().unwrap_or("");
+    let mut fields = metadata.split_whitespace();
+    if fields.next() != Some("#!lua") {
+        return Err(Error::msg("ERR Missing library metadata"));
+    }
+    let mut name = None;
+    for field in fields {
+        match field.split_once('=') {
+            Some(("name", value)) => name = Some(value.to_string()),
+            _ => return Err(Error::msg(format!("ERR Invalid metadata value given: {}", field))),
+        }
+    }
+    let name = name.ok_or(Error::msg("ERR Library name was not given"))?;
+    if !is_valid_name(&name) {
+        return Err(Error::msg("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long"));
+    }
+    Ok(name)
+}
+
+// the registry of the libraries, which is persisted in the AOF and replicated to the slaves
+#[derive(Default)]
+pub struct Functions {
+    libraries: BTreeMap<String, Library>,
+}
+
+impl Functions {
+    // parse the library and check that its functions are not registered by the other libraries
+    fn parse(&self, code: &str, version: u64) -> Result<Library, Error> {
+        let (name, functions) = script::parse_library(code)?;
+        for function in &functions {
+            let owner = self.libraries.values().find(|library| {
+                library.name != name && library.functions.iter().any(|other| other.name == function.name)
+            });
+            if owner.is_some() {
+                return Err(Error::msg(format!("ERR Function {} already exists", function.name)));
+            }
+        }
+        Ok(Library {
+            name,
+            code: code.to_string(),
+            version,
+            functions,
+        })
+    }
+
+    // add the library, an existing library with the same name is replaced only with REPLACE
+    pub fn load(&mut self, code: &str, replace: bool) -> Result<&Library, Error> {
+        let name = library_name(code)?;
+        let version = match self.libraries.get(&name) {
+            Some(_) if !replace => return Err(Error::msg(format!("ERR Library '{}' already exists", name))),
+            Some(library) => library.version + 1,
+            None => 1,
+        };
+        let library = self.parse(code, version)?;
+        self.libraries.insert(name.clone(), library);
+        Ok(&self.libraries[&name])
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<(), Error> {
+        self.libraries.remove(name).map(|_| ()).ok_or(Error::msg("ERR Library not found"))
+    }
+
+    pub fn flush(&mut self) {
+        self.libraries.clear();
+    }
+
+    // the library of the function, and the function itself
+    pub fn find(&self, function: &str) -> Option<(&Library, &FunctionInfo)> {
+        self.libraries.values().find_map(|library| {
+            library.functions.iter().find(|info| info.name == function).map(|info| (library, info))
+        })
+    }
+
+    // the libraries whose names match the pattern, separated by empty lines
+    pub fn list(&self, pattern: Option<&str>, with_code: bool) -> String {
+        self.libraries
+            .values()
+            .filter(|library| pattern.is_none_or(|pattern| glob_match(pattern, &library.name)))
+            .map(|library| {
+                let functions: Vec<String> = library.functions
+                    .iter()
+                    .map(|info| match info.no_writes {
+                        true => format!("{} (no-writes)", info.name),
+                        false => info.name.clone(),
+                    })
+                    .collect();
+                let mut text = format!(
+                    "library_name: {}\nversion: {}\nfunctions: {}",
+                    library.name, library.version, functions.join(", ")
+                );
+                if with_code {
+                    text = format!("{}\nlibrary_code:\n{}", text, library.code);
+                }
+                text
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    // all the libraries along with their versions, as `version:code` separated by commas with the code hex encoded
+    pub fn dump(&self) -> String {
+        self.libraries
+            .values()
+            .map(|library| format!("{}:{}", library.version, to_hex(&library.code)))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    // restore the libraries of the payload of FUNCTION DUMP, nothing is restored if any of them fails
+    // the restored libraries are returned in order to be logged
+    pub fn restore(&mut self, payload: &str, policy: RestorePolicy) -> Result<Vec<Library>, Error> {
+        let mut restored = Functions::default();
+        if policy != RestorePolicy::Flush {
+            restored.libraries = self.libraries.clone();
+        }
+        let mut libraries = Vec::new();
+        for entry in payload.split(',').filter(|entry| !entry.is_empty()) {
+            let (version, code) = entry.split_once(':').ok_or(Error::msg("ERR payload is not valid"))?;
+            let version = version.parse::<u64>().map_err(|_| Error::msg("ERR payload is not valid"))?;
+            let code = from_hex(code)?;
+            let name = library_name(&code)?;
+            if policy == RestorePolicy::Append && self.libraries.contains_key(&name) {
+                return Err(Error::msg(format!("ERR Library '{}' already exists", name)));
+            }
+            let library = restored.parse(&code, version)?;
+            restored.libraries.insert(name, library.clone());
+            libraries.push(library);
+        }
+        self.libraries = restored.libraries;
+        Ok(libraries)
+    }
+
+    // the line of the AOF that sets the library as it is
+    pub fn log_line(library: &Library) -> String {
+        format!("FUNCTION SET {} {}", library.version, to_hex(&library.code))
+    }
+
+    // apply a FUNCTION line of the AOF, which is `SET <version> <code>`, `DELETE <name>` or `FLUSH` after FUNCTION
+    pub fn apply_log(&mut self, args: &str) {
+        let args: Vec<&str> = args.split(' ').collect();
+        let result = match args.as_slice() {
+            ["SET", version, code] => version
+                .parse::<u64>()
+                .map_err(|_| Error::msg("invalid version"))
+                .and_then(|version| Ok((version, from_hex(code)?)))
+                .and_then(|(version, code)| self.parse(&code, version))
+                .map(|library| {
+                    self.libraries.insert(library.name.clone(), library);
+                }),
+            ["DELETE", name] => self.delete(name),
+            ["FLUSH"] => {
+                self.flush();
+                Ok(())
+            },
+            _ => Err(Error::msg("invalid log item")),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Invalid function log item: {}", e);
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    sync::{
+        atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::config::MaxmemoryPolicy;
+
+// the approximate bytes taken by a key besides its name and value, such as its slot in the map and its metadata
+const ENTRY_OVERHEAD: usize = 64;
+// the LFU counter of a new key, so that it is not evicted before it has a chance to be accessed again
+const LFU_INIT_VAL: u8 = 5;
+// the higher the factor, the more accesses it takes to increase the LFU counter
+const LFU_LOG_FACTOR: f64 = 10.0;
+// the LFU counter is decreased by one for each period the key is not accessed
+const LFU_DECAY_MS: u64 = 60_000;
+// the number of the independently locked shards of the keyspace
+const SHARDS: usize = 32;
+
+// the current unix time in milliseconds, in which the expire times are given
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_millis() as u64).unwrap_or(0)
+}
+
+// the approximate memory taken by a key
+fn entry_size(key: &str, value: &str) -> usize {
+    ENTRY_OVERHEAD + key.len() + value.len()
+}
+
+// the value of a key, along with what the expiration and the eviction need to know about it
+// the access time and the counter are updated by the reads as well, so they are atomic
+struct Entry {
+    value: String,
+    expire_at: Option<u64>,         // the unix time in milliseconds when the key expires
+    accessed: AtomicU64,            // the unix time in milliseconds of the last access, for LRU and the decay of the LFU counter
+    counter: AtomicU8,              // the logarithmic access counter, for LFU
+    slot: usize,                    // the index of the key in the keys of the keyspace
+    volatile_slot: Option<usize>,   // the index of the key in the volatile keys of the keyspace, if it has an expire time
+}
+
+impl Entry {
+    fn new(value: String, slot: usize, now: u64) -> Entry {
+        Entry {
+            value,
+            expire_at: None,
+            accessed: AtomicU64::new(now),
+            counter: AtomicU8::new(LFU_INIT_VAL),
+            slot,
+            volatile_slot: None,
+        }
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expire_at.is_some_and(|expire_at| expire_at <= now)
+    }
+
+    // the LFU counter after the decay for the time the key has not been accessed
+    fn lfu(&self, now: u64) -> u8 {
+        let periods = now.saturating_sub(self.accessed.load(Ordering::Relaxed)) / LFU_DECAY_MS;
+        self.counter.load(Ordering::Relaxed).saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+
+    // record an access, the counter grows slower as it gets larger like redis
+    fn touch(&self, now: u64) {
+        let counter = self.lfu(now);
+        let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+        let counter = match counter < u8::MAX && rand::random::<f64>() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+            true => counter + 1,
+            false => counter,
+        };
+        self.counter.store(counter, Ordering::Relaxed);
+        self.accessed.store(now, Ordering::Relaxed);
+    }
+}
+
+// the keys removed by the server itself
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KeyspaceStats {
+    pub expired_keys: u64,
+    pub evicted_keys: u64,
+}
+
+// a part of the key-value pairs, with the approximate memory they take
+// the keys are also kept in vectors, so that they can be sampled randomly for the expiration and the eviction
+#[derive(Default)]
+struct Shard {
+    entries: HashMap<String, Entry>,
+    keys: Vec<String>,          // all the keys
+    volatile: Vec<String>,      // the keys with an expire time
+    used_memory: usize,
+    stats: KeyspaceStats,
+}
+
+impl Shard {
+    // the value of the key, which is missing once the key has expired
+    fn get(&self, key: &str, now: u64) -> Option<&str> {
+        let entry = self.entries.get(key).filter(|entry| !entry.is_expired(now))?;
+        entry.touch(now);
+        Some(&entry.value)
+    }
+
+    fn contains(&self, key: &str, now: u64) -> bool {
+        self.entries.get(key).is_some_and(|entry| !entry.is_expired(now))
+    }
+
+    // set the value of the key, which clears its expire time like SET of redis
+    fn insert(&mut self, key: String, value: String, now: u64) {
+        let size = entry_size(&key, &value);
+        match self.entries.get_mut(&key) {
+            Some(entry) => {
+                self.used_memory = self.used_memory - entry_size(&key, &entry.value) + size;
+                entry.value = value;
+                entry.expire_at = None;
+                entry.touch(now);
+                if let Some(slot) = entry.volatile_slot.take() {
+                    self.unset_volatile(slot);
+                }
+            },
+            None => {
+                self.used_memory += size;
+                self.entries.insert(key.clone(), Entry::new(value, self.keys.len(), now));
+                self.keys.push(key);
+            },
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> bool {
+        let Some(entry) = self.entries.remove(key) else {
+            return false;
+        };
+        self.used_memory -= entry_size(key, &entry.value);
+        self.keys.swap_remove(entry.slot);
+        if let Some(moved) = self.keys.get(entry.slot) {
+            if let Some(moved) = self.entries.get_mut(moved) {
+                moved.slot = entry.slot;
+            }
+        }
+        if let Some(slot) = entry.volatile_slot {
+            self.unset_volatile(slot);
+        }
+        true
+    }
+
+    // remove the key if it has expired, and count it
+    fn remove_expired(&mut self, key: &str, now: u64) -> bool {
+        let expired = self.entries.get(key).is_some_and(|entry| entry.is_expired(now)) && self.remove(key);
+        if expired {
+            self.stats.expired_keys += 1;
+        }
+        expired
+    }
+
+    // remove the key chosen by the eviction, and count it
+    fn remove_evicted(&mut self, key: &str) -> bool {
+        let evicted = self.remove(key);
+        if evicted {
+            self.stats.evicted_keys += 1;
+        }
+        evicted
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.keys.clear();
+        self.volatile.clear();
+        self.used_memory = 0;
+    }
+
+    fn unset_volatile(&mut self, slot: usize) {
+        self.volatile.swap_remove(slot);
+        if let Some(moved) = self.volatile.get(slot) {
+            if let Some(moved) = self.entries.get_mut(moved) {
+                moved.volatile_slot = Some(slot);
+            }
+        }
+    }
+
+    // set the expire time of the key, or clear it with None, false is returned if there is no such key
+    fn set_expire(&mut self, key: &str, expire_at: Option<u64>) -> bool {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return false;
+        };
+        entry.expire_at = expire_at;
+        match (expire_at, entry.volatile_slot) {
+            (Some(_), None) => {
+                entry.volatile_slot = Some(self.volatile.len());
+                self.volatile.push(key.to_string());
+            },
+            (None, Some(slot)) => {
+                entry.volatile_slot = None;
+                self.unset_volatile(slot);
+            },
+            _ => {},
+        }
+        true
+    }
+
+    // the expire time of the key, None if there is no such key
+    fn expire_at(&self, key: &str, now: u64) -> Option<Option<u64>> {
+        self.entries.get(key).filter(|entry| !entry.is_expired(now)).map(|entry| entry.expire_at)
+    }
+
+    // the approximate memory taken by the key
+    fn usage(&self, key: &str, now: u64) -> Option<usize> {
+        self.entries.get_key_value(key).filter(|(_, entry)| !entry.is_expired(now)).map(|(key, entry)| entry_size(key, &entry.value))
+    }
+
+    // sample the keys with an expire time, and delete the expired ones among them
+    // return the number of the sampled keys and the deleted ones
+    fn expire_sampled(&mut self, count: usize, now: u64) -> (usize, Vec<String>) {
+        let count = count.min(self.volatile.len());
+        let expired: Vec<String> = sample(&self.volatile, count)
+            .filter(|key| self.entries.get(*key).is_some_and(|entry| entry.is_expired(now)))
+            .cloned()
+            .collect();
+        let expired = expired.into_iter().filter(|key| self.remove_expired(key, now)).collect();
+        (count, expired)
+    }
+}
+
+// how likely the key is to be evicted by the policy, the higher the more likely
+fn eviction_score(policy: MaxmemoryPolicy, entry: &Entry, now: u64) -> u64 {
+    match policy {
+        MaxmemoryPolicy::AllkeysLru | MaxmemoryPolicy::VolatileLru => now.saturating_sub(entry.accessed.load(Ordering::Relaxed)),
+        MaxmemoryPolicy::AllkeysLfu | MaxmemoryPolicy::VolatileLfu => (u8::MAX - entry.lfu(now)) as u64,
+        MaxmemoryPolicy::VolatileTtl => u64::MAX - entry.expire_at.unwrap_or(u64::MAX),
+        // the candidates of different keyspaces are compared by the score, so a random one wins
+        _ => rand::random(),
+    }
+}
+
+// pick the given number of keys randomly, a key may be picked more than once
+fn sample(keys: &[String], count: usize) -> impl Iterator<Item = &String> {
+    let count = if keys.is_empty() { 0 } else { count };
+    (0..count).map(move |_| &keys[rand::random::<usize>() % keys.len()])
+}
+
+// the key-value pairs, split into shards by the hashes of the keys, and each shard is locked on its own,
+// so that the commands on the keys of different shards do not wait for each other
+// a command locks one shard at a time, except the commands on the whole keyspace, which lock all the shards
+// in the order of their indexes, so the locks never deadlock
+// the transactions and the scripts are kept atomic by the keyspace lock of the server instead
+pub struct Keyspace {
+    shards: Vec<RwLock<Shard>>,
+    hasher: RandomState,
+    used_memory: AtomicUsize,   // the sum of the used memory of the shards, so that it is checked without locking them
+}
+
+impl Default for Keyspace {
+    fn default() -> Keyspace {
+        Keyspace::with_hasher(RandomState::new())
+    }
+}
+
+impl Keyspace {
+    // the keyspaces sharing the hasher put a key into the same shard, so that their shards can be swapped
+    pub fn with_hasher(hasher: RandomState) -> Keyspace {
+        Keyspace {
+            shards: (0..SHARDS).map(|_| RwLock::new(Shard::default())).collect(),
+            hasher,
+            used_memory: AtomicUsize::new(0),
+        }
+    }
+
+    fn shard(&self, key: &str) -> &RwLock<Shard> {
+        &self.shards[self.hasher.hash_one(key) as usize % SHARDS]
+    }
+
+    fn read(&self, key: &str) -> RwLockReadGuard<'_, Shard> {
+        self.shard(key).read().unwrap()
+    }
+
+    // change the shard, and keep the used memory of the whole keyspace up to date
+    fn write<R>(&self, shard: &RwLock<Shard>, f: impl FnOnce(&mut Shard) -> R) -> R {
+        let mut shard = shard.write().unwrap();
+        let before = shard.used_memory;
+        let result = f(&mut shard);
+        match shard.used_memory >= before {
+            true => self.used_memory.fetch_add(shard.used_memory - before, Ordering::Relaxed),
+            false => self.used_memory.fetch_sub(before - shard.used_memory, Ordering::Relaxed),
+        };
+        result
+    }
+
+    fn read_all(&self) -> Vec<RwLockReadGuard<'_, Shard>> {
+        self.shards.iter().map(|shard| shard.read().unwrap()).collect()
+    }
+
+    fn write_all(&self) -> Vec<RwLockWriteGuard<'_, Shard>> {
+        self.shards.iter().map(|shard| shard.write().unwrap()).collect()
+    }
+
+    // the value of the key, which is missing once the key has expired
+    pub fn get(&self, key: &str, now: u64) -> Option<String> {
+        self.read(key).get(key, now).map(|value| value.to_string())
+    }
+
+    pub fn contains(&self, key: &str, now: u64) -> bool {
+        self.read(key).contains(key, now)
+    }
+
+    // set the value of the key, which clears its expire time like SET of redis
+    pub fn insert(&self, key: String, value: String, now: u64) {
+        self.write(self.shard(&key), |shard| shard.insert(key, value, now))
+    }
+
+    pub fn remove(&self, key: &str) -> bool {
+        self.write(self.shard(key), |shard| shard.remove(key))
+    }
+
+    // remove the key if it has expired, and count it
+    pub fn remove_expired(&self, key: &str, now: u64) -> bool {
+        self.write(self.shard(key), |shard| shard.remove_expired(key, now))
+    }
+
+    // remove the key chosen by the eviction, and count it
+    pub fn remove_evicted(&self, key: &str) -> bool {
+        self.write(self.shard(key), |shard| shard.remove_evicted(key))
+    }
+
+    pub fn clear(&self) {
+        for mut shard in self.write_all() {
+            shard.clear();
+        }
+        self.used_memory.store(0, Ordering::Relaxed);
+    }
+
+    // set the expire time of the key, or clear it with None, false is returned if there is no such key
+    pub fn set_expire(&self, key: &str, expire_at: Option<u64>) -> bool {
+        self.write(self.shard(key), |shard| shard.set_expire(key, expire_at))
+    }
+
+    // the expire time of the key, None if there is no such key
+    pub fn expire_at(&self, key: &str, now: u64) -> Option<Option<u64>> {
+        self.read(key).expire_at(key, now)
+    }
+
+    // the approximate memory taken by the key
+    pub fn usage(&self, key: &str, now: u64) -> Option<usize> {
+        self.read(key).usage(key, now)
+    }
+
+    // the number of the keys, including the expired ones not deleted yet
+    pub fn len(&self) -> usize {
+        self.read_all().iter().map(|shard| shard.entries.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // the number of the keys with an expire time
+    pub fn expires(&self) -> usize {
+        self.read_all().iter().map(|shard| shard.volatile.len()).sum()
+    }
+
+    pub fn used_memory(&self) -> usize {
+        self.used_memory.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> KeyspaceStats {
+        self.read_all().iter().fold(KeyspaceStats::default(), |total, shard| KeyspaceStats {
+            expired_keys: total.expired_keys + shard.stats.expired_keys,
+            evicted_keys: total.evicted_keys + shard.stats.evicted_keys,
+        })
+    }
+
+    // swap all the keys with the other keyspace, which must share the hasher with this one
+    // the shards of this keyspace are locked before those of the other, so the caller passes the keyspaces in a fixed order
+    pub fn swap(&self, other: &Keyspace) {
+        let mut shards = self.write_all();
+        let mut others = other.write_all();
+        for (shard, other) in shards.iter_mut().zip(others.iter_mut()) {
+            std::mem::swap(&mut **shard, &mut **other);
+        }
+        let used_memory = self.used_memory.load(Ordering::Relaxed);
+        self.used_memory.store(other.used_memory.swap(used_memory, Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    // the keys that have not expired
+    pub fn keys(&self, now: u64) -> Vec<String> {
+        self.read_all()
+            .iter()
+            .flat_map(|shard| shard.entries.iter().filter(|(_, entry)| !entry.is_expired(now)).map(|(key, _)| key.clone()))
+            .collect()
+    }
+
+    // a random key that has not expired, None if none is found after a few tries
+    pub fn random_key(&self, now: u64) -> Option<String> {
+        if self.used_memory() == 0 {
+            return None;
+        }
+        (0..100).find_map(|_| {
+            let shard = self.shards[rand::random::<usize>() % SHARDS].read().unwrap();
+            let key = sample(&shard.keys, 1).find(|key| shard.contains(key, now)).cloned();
+            key
+        })
+    }
+
+    // delete the expired keys found by sampling the keys with an expire time, shard by shard like redis does db by db
+    // a shard is sampled again while a quarter of its sampled keys have expired, until the deadline
+    pub fn expire_sampled(&self, count: usize, deadline: Instant) -> Vec<String> {
+        let mut deleted = Vec::new();
+        for shard in &self.shards {
+            loop {
+                let (sampled, expired) = self.write(shard, |shard| shard.expire_sampled(count, now_ms()));
+                let done = expired.len() * 4 <= sampled;
+                deleted.extend(expired);
+                if done || Instant::now() > deadline {
+                    break;
+                }
+            }
+            if Instant::now() > deadline {
+                break;
+            }
+        }
+        deleted
+    }
+
+    // the key to evict by the policy along with its score, chosen among the given number of keys sampled from the shards like redis
+    // None if the policy does not evict or there is no key to evict
+    pub fn eviction_candidate(&self, policy: MaxmemoryPolicy, samples: usize, now: u64) -> Option<(u64, String)> {
+        let samples = match policy {
+            MaxmemoryPolicy::NoEviction => return None,
+            MaxmemoryPolicy::AllkeysRandom | MaxmemoryPolicy::VolatileRandom => 1,
+            _ => samples,
+        };
+        // take a key from each shard in turn starting at a random one, skipping the shards with nothing to evict
+        let start = rand::random::<usize>();
+        let mut sampled = 0;
+        let mut best: Option<(u64, String)> = None;
+        for index in 0..SHARDS * samples {
+            if sampled == samples {
+                break;
+            }
+            let shard = self.shards[(start + index) % SHARDS].read().unwrap();
+            let pool = match policy.is_volatile() {
+                true => &shard.volatile,
+                false => &shard.keys,
+            };
+            let Some((key, entry)) = sample(pool, 1).find_map(|key| shard.entries.get_key_value(key)) else {
+                continue;
+            };
+            sampled += 1;
+            let score = eviction_score(policy, entry, now);
+            if best.as_ref().is_none_or(|(best, _)| score > *best) {
+                best = Some((score, key.clone()));
+            }
+        }
+        best
+    }
+}
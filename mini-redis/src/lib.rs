@@ -1,19 +1,61 @@
 #![feature(impl_trait_in_assoc_type)]
 use std::{
+    future::Future,
     collections::{HashMap, VecDeque, HashSet},
-    sync::{RwLock, Arc, Mutex},
+    hash::RandomState,
+    pin::Pin,
+    sync::{RwLock, Arc, Mutex, Weak, atomic::Ordering},
     net::SocketAddr,
+    time::{Duration, Instant},
 };
 use tokio::{
     sync::{
         broadcast,
+        mpsc,
+        oneshot,
         Mutex as AsyncMutex,
+        RwLock as AsyncRwLock,
     },
     fs::{File, OpenOptions},
     io::{AsyncWriteExt, AsyncReadExt},
 };
 use anyhow::Error;
 
+// acl, filter, glob, slowlog and tls are shared with redis_proxy, and are kept in common/ at the root of the repository
+#[path = "../../common/acl.rs"]
+mod acl;
+mod client;
+mod config;
+#[path = "../../common/filter.rs"]
+mod filter;
+mod function;
+#[path = "../../common/glob.rs"]
+mod glob;
+mod keyspace;
+mod metrics;
+mod pubsub;
+mod script;
+#[path = "../../common/slowlog.rs"]
+mod slowlog;
+#[path = "../../common/tls.rs"]
+mod tls;
+#[cfg(test)]
+#[path = "../../common/test_util.rs"]
+mod test_util;
+
+pub use acl::{Acl, AclLayer, AclService};
+pub use client::{RedisClient, ClientConfig, CircuitBreaker};
+pub use config::{AppendFsync, Config, KeyspaceEvents, MaxmemoryPolicy};
+pub use filter::{ContentFilter, FilterLayer, FilterMode, FilterService};
+pub use function::{Functions, Library};
+pub use glob::glob_match;
+pub use keyspace::{Keyspace, KeyspaceStats};
+pub use metrics::{serve_metrics, start_ops_sampler, Exposition, Histogram, Metrics, MetricsLayer, MetricsService, ReplicaStats};
+pub use pubsub::{BufferLimits, PubSub, PubSubStats};
+pub use script::{Program, Reply, Scripts};
+pub use slowlog::{SlowLog, SlowLogEntry, SlowLogLayer, SlowLogService};
+pub use tls::{ClientAuth, TlsConfig, TlsIncoming, TlsMakeTransport};
+
 // the enum for opcode
 #[derive(PartialEq, Eq)]
 pub enum OPCode {
@@ -23,11 +65,54 @@ pub enum OPCode {
     PING = 3,
     SUBSCRIBE = 4,
     PUBLISH = 5,
+    SCAN = 6,
+    KEYS = 7,
+    DBSIZE = 8,
+    FLUSHALL = 9,
+    RANDOMKEY = 10,
+    PSUBSCRIBE = 11,
+    PUNSUBSCRIBE = 12,
+    UNSUBSCRIBE = 13,
+    POLL = 14,
+    PUBSUB = 15,
+    CONFIG = 16,
+    EVAL = 17,
+    EVALSHA = 18,
+    SCRIPT = 19,
+    FUNCTION = 20,
+    FCALL = 21,
+    FCALLRO = 22,
+    EXPIRE = 23,
+    TTL = 24,
+    PERSIST = 25,
+    MEMORY = 26,
+    SELECT = 27,
+    MOVE = 28,
+    SWAPDB = 29,
+    FLUSHDB = 30,
+    // AUTH and ACL are handled by the AclLayer in front of the server
+    AUTH = 31,
+    ACL = 32,
+    // FILTER is handled by the FilterLayer behind the AclLayer
+    FILTER = 33,
+    INFO = 34,
+    SLOWLOG = 35,
     SETMASTER = 100,
     DELMASTER = 101,
+    FLUSHMASTER = 102,
+    EXECMASTER = 103,
+    FUNCTIONMASTER = 104,
+    EXPIREMASTER = 105,
     MULTI = 200,
     EXEC = 201,
     WATCH = 202,
+    DISCARD = 203,
+    UNWATCH = 204,
+    // the topology commands are handled by redis_proxy only
+    TOPOLOGY = 300,
+    ADDSLAVE = 301,
+    DELSLAVE = 302,
+    REPLACEMASTER = 303,
     NOTDEFINED = 255,
 }
 
@@ -41,100 +126,445 @@ impl From<i32> for OPCode {
             3 => OPCode::PING,
             4 => OPCode::SUBSCRIBE,
             5 => OPCode::PUBLISH,
+            6 => OPCode::SCAN,
+            7 => OPCode::KEYS,
+            8 => OPCode::DBSIZE,
+            9 => OPCode::FLUSHALL,
+            10 => OPCode::RANDOMKEY,
+            11 => OPCode::PSUBSCRIBE,
+            12 => OPCode::PUNSUBSCRIBE,
+            13 => OPCode::UNSUBSCRIBE,
+            14 => OPCode::POLL,
+            15 => OPCode::PUBSUB,
+            16 => OPCode::CONFIG,
+            17 => OPCode::EVAL,
+            18 => OPCode::EVALSHA,
+            19 => OPCode::SCRIPT,
+            20 => OPCode::FUNCTION,
+            21 => OPCode::FCALL,
+            22 => OPCode::FCALLRO,
+            23 => OPCode::EXPIRE,
+            24 => OPCode::TTL,
+            25 => OPCode::PERSIST,
+            26 => OPCode::MEMORY,
+            27 => OPCode::SELECT,
+            28 => OPCode::MOVE,
+            29 => OPCode::SWAPDB,
+            30 => OPCode::FLUSHDB,
+            31 => OPCode::AUTH,
+            32 => OPCode::ACL,
+            33 => OPCode::FILTER,
+            34 => OPCode::INFO,
+            35 => OPCode::SLOWLOG,
             100 => OPCode::SETMASTER,
             101 => OPCode::DELMASTER,
+            102 => OPCode::FLUSHMASTER,
+            103 => OPCode::EXECMASTER,
+            104 => OPCode::FUNCTIONMASTER,
+            105 => OPCode::EXPIREMASTER,
             200 => OPCode::MULTI,
             201 => OPCode::EXEC,
             202 => OPCode::WATCH,
+            203 => OPCode::DISCARD,
+            204 => OPCode::UNWATCH,
+            300 => OPCode::TOPOLOGY,
+            301 => OPCode::ADDSLAVE,
+            302 => OPCode::DELSLAVE,
+            303 => OPCode::REPLACEMASTER,
             _ => OPCode::NOTDEFINED,
         }
     }
 }
 
-// TxnQueue is used to store the transaction task
+impl OPCode {
+    // the commands that can be safely retried if the node fails
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            OPCode::GET | OPCode::PING | OPCode::SCAN | OPCode::KEYS | OPCode::DBSIZE | OPCode::RANDOMKEY
+                | OPCode::SUBSCRIBE | OPCode::PSUBSCRIBE | OPCode::UNSUBSCRIBE | OPCode::PUNSUBSCRIBE | OPCode::POLL
+                | OPCode::PUBSUB | OPCode::FCALLRO | OPCode::TTL | OPCode::MEMORY | OPCode::SELECT | OPCode::INFO
+                | OPCode::SETMASTER | OPCode::DELMASTER | OPCode::FLUSHMASTER | OPCode::EXECMASTER | OPCode::EXPIREMASTER
+        )
+    }
+
+    // the commands refused once the keys take more than maxmemory, since they may take more memory
+    fn is_denyoom(&self) -> bool {
+        matches!(self, OPCode::SET | OPCode::EVAL | OPCode::EVALSHA | OPCode::FCALL)
+    }
+
+    // the writes replicated by the master node
+    fn is_replicated(&self) -> bool {
+        matches!(
+            self,
+            OPCode::SETMASTER | OPCode::DELMASTER | OPCode::FLUSHMASTER | OPCode::EXECMASTER | OPCode::FUNCTIONMASTER | OPCode::EXPIREMASTER
+        )
+    }
+
+    // the commands changing the databases as a whole or more than one of them, which have no replicated opcode of their own
+    // they run exclusively like the scripts, and are logged and replicated as a block applied by EXECMASTER
+    fn is_replicated_as_block(&self) -> bool {
+        matches!(self, OPCode::MOVE | OPCode::SWAPDB | OPCode::FLUSHDB)
+    }
+
+    // the commands on the key in the key_channal that may write it, or delete it once it has expired
+    fn is_keyed(&self) -> bool {
+        matches!(
+            self,
+            OPCode::GET | OPCode::TTL | OPCode::SET | OPCode::SETMASTER | OPCode::DEL | OPCode::DELMASTER
+                | OPCode::EXPIRE | OPCode::PERSIST | OPCode::EXPIREMASTER
+        )
+    }
+
+    // the commands among them that write the key, the others only delete it if it has expired
+    fn writes_key(&self) -> bool {
+        self.is_keyed() && !matches!(self, OPCode::GET | OPCode::TTL)
+    }
+
+    // the commands that block until something happens, which are not limited by the request timeout
+    pub fn is_blocking(&self) -> bool {
+        matches!(self, OPCode::POLL)
+    }
+
+    // the reason why the command can not be queued in a transaction
+    // EXEC runs with the keyspace locked, so the blocking commands are rejected as well
+    fn txn_error(&self) -> Option<&'static str> {
+        match self {
+            OPCode::NOTDEFINED => Some("ERR unknown command"),
+            OPCode::SUBSCRIBE | OPCode::PSUBSCRIBE | OPCode::UNSUBSCRIBE | OPCode::PUNSUBSCRIBE | OPCode::POLL | OPCode::SCRIPT | OPCode::FUNCTION
+                | OPCode::SETMASTER | OPCode::DELMASTER | OPCode::FLUSHMASTER | OPCode::EXECMASTER | OPCode::FUNCTIONMASTER | OPCode::EXPIREMASTER
+                | OPCode::TOPOLOGY | OPCode::ADDSLAVE | OPCode::DELSLAVE | OPCode::REPLACEMASTER | OPCode::AUTH | OPCode::ACL | OPCode::FILTER
+                | OPCode::INFO | OPCode::SLOWLOG => {
+                Some("ERR the command is not allowed in a transaction")
+            },
+            _ => None,
+        }
+    }
+}
+
+// the interval to look for the idle transactions
+const TXN_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+// the number of the requests kept for a slave that has not received them yet
+// a burst of writes, such as the sets that make the master evict, must not overrun the slaves
+const REPLICATION_BACKLOG: usize = 4096;
+
+// the sections of INFO in the order they are reported
+const INFO_SECTIONS: &[&str] = &["server", "clients", "memory", "persistence", "replication", "stats", "keyspace"];
+
+// TxnQueue is used to store the transaction task, it is created by the first WATCH or by MULTI
 struct TxnQueue {
-    watch_id: Option<String>,   // store the watch_id to check if the watch key has been changed
-    txn_queue: VecDeque<volo_gen::volo::example::GetItemRequest>,
+    session_id: Option<String>, // the session owning the transaction, no other session can use it
+    watched: HashSet<(usize, String)>,  // the keys watched by the transaction, along with their databases
+    dirty: bool,                // set once any watched key has been changed, then EXEC aborts
+    aborted: bool,              // set once a request fails to be queued, then EXEC discards the transaction
+    last_active: Instant,
+    txn_queue: Option<VecDeque<volo_gen::volo::example::GetItemRequest>>,  // the queued requests, None before MULTI
 }
 
 impl TxnQueue {
-    fn new(watch_id: Option<String>) -> TxnQueue {
+    fn new(session_id: Option<String>) -> TxnQueue {
         TxnQueue {
-            watch_id,
-            txn_queue: VecDeque::new(),
+            session_id,
+            watched: HashSet::new(),
+            dirty: false,
+            aborted: false,
+            last_active: Instant::now(),
+            txn_queue: None,
         }
     }
 
+    fn is_multi(&self) -> bool {
+        self.txn_queue.is_some()
+    }
+
     fn push(&mut self, req: volo_gen::volo::example::GetItemRequest) {
-        self.txn_queue.push_back(req);
+        if let Some(ref mut txn_queue) = self.txn_queue {
+            txn_queue.push_back(req);
+        }
     }
 
     fn pop(&mut self) -> Option<volo_gen::volo::example::GetItemRequest> {
-        self.txn_queue.pop_front()
+        self.txn_queue.as_mut().and_then(|txn_queue| txn_queue.pop_front())
+    }
+}
+
+// the txn_id is random, so that a client can not guess the transactions of the others
+fn new_txn_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+// find the transaction of the request, it must belong to the session of the request
+// None is returned if there is no such transaction, for example it has expired
+fn find_txn<'a>(
+    txn_queue: &'a mut HashMap<String, TxnQueue>,
+    txn_id: &str,
+    session_id: Option<&str>,
+) -> Result<Option<&'a mut TxnQueue>, &'static str> {
+    match txn_queue.get_mut(txn_id) {
+        Some(txn) if txn.session_id.as_deref() == session_id => {
+            txn.last_active = Instant::now();
+            Ok(Some(txn))
+        },
+        Some(_) => Err("ERR the transaction belongs to another session"),
+        None => Ok(None),
     }
+}
+
+// stop watching the keys of the transaction
+fn unwatch(watch_keys: &mut WatchKeys, txn_id: &str, txn: &mut TxnQueue) {
+    for key in txn.watched.drain() {
+        if let Some(txn_ids) = watch_keys.get_mut(&key) {
+            txn_ids.remove(txn_id);
+            if txn_ids.is_empty() {
+                watch_keys.remove(&key);
+            }
+        }
+    }
+    txn.dirty = false;
+}
+
+// the txn_ids watching each key, the key is along with its database
+type WatchKeys = HashMap<(usize, String), HashSet<String>>;
+
+// drop the transactions left idle for longer than the timeout periodically, such as those of the crashed clients
+fn start_txn_sweeper(
+    txn_queue: Weak<RwLock<HashMap<String, TxnQueue>>>,
+    watch_keys: Weak<RwLock<WatchKeys>>,
+    timeout: Duration,
+) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(TXN_SWEEP_INTERVAL).await;
+            let (Some(txn_queue), Some(watch_keys)) = (txn_queue.upgrade(), watch_keys.upgrade()) else {
+                break;
+            };
+            let mut txn_queue = txn_queue.write().unwrap();
+            let idle: Vec<String> = txn_queue
+                .iter()
+                .filter(|(_, txn)| txn.last_active.elapsed() > timeout)
+                .map(|(txn_id, _)| txn_id.clone())
+                .collect();
+            let mut watch_keys = watch_keys.write().unwrap();
+            for txn_id in idle {
+                tracing::info!("Drop the idle transaction {}", txn_id);
+                if let Some(mut txn) = txn_queue.remove(&txn_id) {
+                    unwatch(&mut watch_keys, &txn_id, &mut txn);
+                }
+            }
+        }
+    });
+}
+
+// the writes of the requests, which are logged to the AOF and replicated to the slaves once the requests are applied
+#[derive(Default)]
+struct Effects {
+    log: Vec<String>,                                               // the lines of the AOF
+    replicas: Vec<volo_gen::volo::example::GetItemRequest>,         // the requests to send to the slaves
+    db: Option<usize>,                                              // the database selected by the lines of the AOF so far
+}
 
-    fn get_watch_id(&self) -> Option<String> {
-        self.watch_id.clone()
+impl Effects {
+    // log a line applying to the keys of the database, after a SELECT line if the database is not selected yet
+    // so a block of lines always selects its database, and can be applied on its own like EXECMASTER does
+    fn append(&mut self, db: usize, line: String) {
+        if self.db != Some(db) {
+            self.log.push(format!("SELECT {}", db));
+            self.db = Some(db);
+        }
+        self.log.push(line);
     }
 }
 
-// package the redis client
-pub struct RedisClient {
-    client: volo_gen::volo::example::ItemServiceClient,
+// the interval of the fsyncs of the AOF under appendfsync everysec
+const AOF_FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+// the AOF, along with the database selected by its lines so far
+pub struct Aof {
+    pub file: File,
+    db: usize,
+    fsync: AppendFsync,
+    dirty: bool,                // written since the last fsync
+    metrics: Arc<Metrics>,
+}
+
+impl Aof {
+    // flush the writes to the disk, the latency is recorded in the metrics
+    async fn sync(&mut self) {
+        let now = Instant::now();
+        if let Err(e) = self.file.sync_data().await {
+            tracing::error!("Failed to fsync the AOF: {}", e);
+        }
+        self.metrics.fsync.observe(now.elapsed());
+        self.dirty = false;
+    }
+
+    // append the lines, leaving out the SELECT lines of the database already selected
+    async fn append<'a>(&mut self, lines: impl IntoIterator<Item = &'a str>) {
+        let mut buf = String::new();
+        for line in lines {
+            if let Some(db) = line.strip_prefix("SELECT ").and_then(|db| db.parse::<usize>().ok()) {
+                if db == self.db {
+                    continue;
+                }
+                self.db = db;
+            }
+            buf.push_str(line);
+            buf.push('\n');
+        }
+        if self.file.write_all(buf.as_bytes()).await.is_ok() {
+            self.metrics.add_aof_size(buf.len() as u64);
+        }
+        match self.fsync {
+            AppendFsync::Always => self.sync().await,
+            _ => self.dirty = true,
+        }
+    }
 }
 
-impl RedisClient {
-    pub fn new(addr: SocketAddr) -> RedisClient {
-        RedisClient {
-            client: {
-                volo_gen::volo::example::ItemServiceClientBuilder::new("volo-example")
-                    .address(addr)
-                    .build()
+fn start_fsync_cycle(aof: Weak<AsyncMutex<Aof>>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(AOF_FSYNC_INTERVAL).await;
+            let Some(aof) = aof.upgrade() else {
+                break;
+            };
+            let mut aof = aof.lock().await;
+            if aof.dirty {
+                aof.sync().await;
             }
         }
+    });
+}
+
+// swap two databases, the one of the lower index is locked first
+fn swap_dbs(dbs: &[Keyspace], a: usize, b: usize) {
+    if a != b {
+        dbs[a.min(b)].swap(&dbs[a.max(b)]);
     }
+}
+
+// the index of a database given in a request, such as the argument of SELECT
+fn parse_db(index: &str, databases: usize) -> Result<usize, Error> {
+    let index = index.trim().parse::<i64>().map_err(|_| Error::msg("ERR value is not an integer or out of range"))?;
+    match usize::try_from(index) {
+        Ok(index) if index < databases => Ok(index),
+        _ => Err(Error::msg("ERR DB index is out of range")),
+    }
+}
 
-    pub async fn get_item(&self, req: volo_gen::volo::example::GetItemRequest) -> ::core::result::Result<volo_gen::volo::example::GetItemResponse, Error> {
-        match self.client.get_item(req).await {
-            Ok(resp) => {
-                tracing::info!("Get response: {:?}", resp);
-                Ok(resp)
+// apply a line of the AOF to the selected database, or to the functions for the FUNCTION lines
+// the SELECT lines change the selected database for the following lines
+fn apply_log(dbs: &[Keyspace], db: &mut usize, functions: &mut Functions, line: &str) {
+    if let Some(args) = line.strip_prefix("FUNCTION ") {
+        functions.apply_log(args);
+        return;
+    }
+    let kv_pairs = &dbs[*db];
+    let log_item: Vec<&str> = line.splitn(3, ' ').collect();
+    match log_item.as_slice() {
+        ["SELECT", index] => match parse_db(index, dbs.len()) {
+            Ok(index) => *db = index,
+            Err(_) => tracing::warn!("Invalid log item"),
+        },
+        ["SET", key, value] => {
+            kv_pairs.insert(key.to_string(), value.to_string(), keyspace::now_ms());
+        },
+        ["DEL", key] => {
+            kv_pairs.remove(key);
+        },
+        // the expire time is the unix time in milliseconds, so that it stays the same after the recovery
+        ["EXPIREAT", key, expire_at] => match expire_at.parse::<u64>() {
+            Ok(expire_at) => {
+                kv_pairs.set_expire(key, Some(expire_at));
             },
-            Err(e) => {
-                tracing::error!("Get error: {:?}", e);
-                Err(Error::from(e))
+            Err(_) => tracing::warn!("Invalid log item"),
+        },
+        ["PERSIST", key] => {
+            kv_pairs.set_expire(key, None);
+        },
+        ["FLUSHALL"] => {
+            for kv_pairs in dbs {
+                kv_pairs.clear();
             }
+        },
+        ["FLUSHDB"] => {
+            kv_pairs.clear();
+        },
+        ["SWAPDB", a, b] => match (parse_db(a, dbs.len()), parse_db(b, dbs.len())) {
+            (Ok(a), Ok(b)) => swap_dbs(dbs, a, b),
+            _ => tracing::warn!("Invalid log item"),
+        },
+        _ => {
+            tracing::warn!("Invalid log item");
         }
     }
 }
 
-pub struct S {
+// the server, a handle of the state shared with its background tasks
+#[derive(Clone)]
+pub struct S(Arc<State>);
+
+impl std::ops::Deref for S {
+    type Target = State;
+
+    fn deref(&self) -> &State {
+        &self.0
+    }
+}
+
+pub struct State {
     is_master: bool,
-    kv_pairs: Arc<RwLock<HashMap<String, String>>>,                     // store the key-value pairs
-    channels: Arc<RwLock<HashMap<String, broadcast::Sender<String>>>>,  // store the channel and the sender
-    pub op_tx: Option<Arc<Mutex<broadcast::Sender<volo_gen::volo::example::GetItemRequest>>>>,
-    pub log_file: Arc<AsyncMutex<File>>,
-    watch_keys: Arc<RwLock<HashMap<String, HashSet<String>>>>,          // store the watch key and watch_id
-    txn_queue: Arc<RwLock<HashMap<usize, TxnQueue>>>,                   // store the transaction task
+    dbs: Vec<Keyspace>,                                                 // the logical databases, each storing its key-value pairs in independently locked shards
+    pubsub: Arc<Mutex<PubSub>>,                                         // store the subscriptions and the pending messages of each session
+    pub op_tx: Option<broadcast::Sender<volo_gen::volo::example::GetItemRequest>>,           // the writes to replicate to the slaves
+    pub log_file: Arc<AsyncMutex<Aof>>,
+    pub acl: Arc<Acl>,                                                  // the users, checked by the AclLayer in front of the server
+    pub filter: Arc<ContentFilter>,                                     // the filtered words, applied by the FilterLayer behind the AclLayer
+    pub metrics: Arc<Metrics>,                                          // the counters served on the metrics-port, updated by the MetricsLayer as well
+    pub slowlog: Arc<SlowLog>,                                          // the slow commands, recorded by the SlowLogLayer behind the FilterLayer
+    watch_keys: Arc<RwLock<WatchKeys>>, // store the watched key along with its database and the txn_ids watching it
+    txn_queue: Arc<RwLock<HashMap<String, TxnQueue>>>,                  // store the transaction task
+    keyspace_lock: AsyncRwLock<()>,                                     // held by EXEC and the scripts exclusively, and by the other requests shared
+    key_locks: Vec<AsyncRwLock<()>>,                                    // held from writing a key until the write is logged and replicated, by the hash of the key
+    scripts: Arc<Scripts>,                                              // the cached scripts, and the state of the running one
+    functions: RwLock<Functions>,                                       // the libraries of functions, persisted in the AOF
+    config: RwLock<Config>,
 }
 
+// the state is shared by the worker threads of tokio, so it must be Send and Sync by itself
+// the std locks in it guard short synchronous sections only, and clippy rejects holding them across an await
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<State>();
+};
+
 impl S {
-    pub async fn new(slave_addr: Vec<SocketAddr>, log_path: &str) -> S {
+    // an invalid aclfile is reported with its line, and an invalid filter-file with its name, before anything is started
+    pub async fn new(slave_addr: Vec<SocketAddr>, log_path: &str, config: Config) -> Result<S, Error> {
+        let acl = Arc::new(Acl::new(config.requirepass.as_deref(), config.aclfile.as_deref())?);
+        let filter = Arc::new(ContentFilter::new(config.filter_file.as_deref(), config.filter_mode)?);
         let is_master = !slave_addr.is_empty();
-        let kv_pairs = Arc::new(RwLock::new(HashMap::new()));
-        let channels = Arc::new(RwLock::new(HashMap::new()));
+        // the databases share the hasher, so that SWAPDB swaps their shards
+        let hasher = RandomState::new();
+        let dbs: Vec<Keyspace> = (0..config.databases).map(|_| Keyspace::with_hasher(hasher.clone())).collect();
+        let functions = RwLock::new(Functions::default());
+        let pubsub = Arc::new(Mutex::new(PubSub::new(config.pubsub_limits.clone())));
+        pubsub::start_sweeper(Arc::downgrade(&pubsub));
         let op_tx = match is_master {
-            true => Some(Arc::new(Mutex::new(broadcast::channel(16).0))),
+            true => Some(broadcast::channel(REPLICATION_BACKLOG).0),
             false => None,
         };
+        let slowlog = Arc::new(SlowLog::new(config.slowlog_log_slower_than, config.slowlog_max_len));
         let watch_keys = Arc::new(RwLock::new(HashMap::new()));
         let txn_queue = Arc::new(RwLock::new(HashMap::new()));
+        start_txn_sweeper(Arc::downgrade(&txn_queue), Arc::downgrade(&watch_keys), config.txn_idle_timeout);
 
         // check if the log file exists
         if !std::path::Path::new(&log_path).exists() {
             std::fs::create_dir_all("log").unwrap();
         }
-        let log_file = OpenOptions::new()
+        let mut log_file = OpenOptions::new()
             .create(true)   // create the file if it does not exist
             .read(true)
             .append(true)
@@ -145,60 +575,222 @@ impl S {
         tracing::info!("Start recovery from log file");
 
         // recovery from log file
-        let log_file = Arc::new(AsyncMutex::new(log_file));
         let mut buf = String::new();
-        let _ = log_file.clone().lock().await.read_to_string(&mut buf).await;
-        let mut lines = buf.lines();
-        while let Some(line) = lines.next() {
+        let _ = log_file.read_to_string(&mut buf).await;
+        let mut db = 0;     // the database selected by the lines applied so far
+        // the lines between MULTI and EXEC belong to a transaction, and are applied only if the EXEC is logged
+        let mut block: Option<Vec<&str>> = None;
+        let mut block_start = 0;    // the offset of the MULTI line of the open transaction
+        let mut offset = 0;
+        for line in buf.split_inclusive('\n') {
+            let start = offset;
+            offset += line.len();
+            let line = line.trim_end_matches(['\n', '\r']);
             tracing::debug!("Recovery log item: {}", line);
-            let log_item: Vec<&str> = line.split(" ").collect();
-            match log_item[0] {
-                "SET" => {
-                    let key = log_item[1];
-                    let value = log_item[2];
-                    kv_pairs.write().unwrap().insert(key.to_string(), value.to_string());
+            match (line, block.as_mut()) {
+                ("MULTI", _) => {
+                    block = Some(Vec::new());
+                    block_start = start;
                 },
-                "DEL" => {
-                    let key = log_item[1];
-                    kv_pairs.write().unwrap().remove(key);
+                ("EXEC", Some(_)) => {
+                    let mut functions = functions.write().unwrap();
+                    for line in block.take().unwrap_or_default() {
+                        apply_log(&dbs, &mut db, &mut functions, line);
+                    }
                 },
-                _ => {
-                    tracing::warn!("Invalid log item");
-                }
+                (line, Some(block)) => block.push(line),
+                (line, None) => apply_log(&dbs, &mut db, &mut functions.write().unwrap(), line),
             }
         }
+        // the server crashed while logging a transaction, cut it off so that the later lines are not taken as a part of it
+        if block.is_some() {
+            tracing::warn!("Drop the incomplete transaction at the end of the log file");
+            let _ = log_file.set_len(block_start as u64).await;
+        }
+        let metrics = Arc::new(Metrics::default());
+        start_ops_sampler(Arc::downgrade(&metrics));
+        metrics.set_aof_size(log_file.metadata().await.map(|metadata| metadata.len()).unwrap_or(0));
+        let log_file = Arc::new(AsyncMutex::new(Aof {
+            file: log_file,
+            db,
+            fsync: config.appendfsync,
+            dirty: false,
+            metrics: metrics.clone(),
+        }));
+        if config.appendfsync == AppendFsync::EverySec {
+            start_fsync_cycle(Arc::downgrade(&log_file));
+        }
 
         tracing::info!("Complete recovery from log file");
 
         // if it is master node, create the sync task to sync data to slave nodes
         if is_master {
             for addr in slave_addr {
-                let operation_rx = Arc::new(AsyncMutex::new(op_tx.as_ref().unwrap().lock().unwrap().subscribe()));
-                tokio::spawn(S::sync_slave(addr, operation_rx));
+                let operation_rx = op_tx.as_ref().unwrap().subscribe();
+                let stats = metrics.add_replica(addr);
+                tokio::spawn(State::sync_slave(addr, operation_rx, config.client.clone(), stats));
             }
         }
 
-        S {
+        let state = Arc::new(State {
             is_master,
-            kv_pairs,
-            channels,
+            dbs,
+            pubsub,
             op_tx,
             log_file,
+            acl,
+            filter,
+            metrics,
+            slowlog,
             watch_keys,
             txn_queue,
+            keyspace_lock: AsyncRwLock::new(()),
+            key_locks: (0..KEY_LOCKS).map(|_| AsyncRwLock::new(())).collect(),
+            scripts: Arc::new(Scripts::default()),
+            functions,
+            config: RwLock::new(config),
+        });
+        start_expire_cycle(Arc::downgrade(&state));
+        Ok(S(state))
+    }
+}
+
+impl State {
+    // the sections of INFO in the `field:value` format of redis, each headed by `# Section`
+    async fn info(&self, sections: &[&str]) -> String {
+        let mut report = Vec::new();
+        for section in sections {
+            let mut lines: Vec<String> = match *section {
+                "server" => {
+                    let uptime = self.metrics.uptime().as_secs();
+                    vec![
+                        format!("mini_redis_version:{}", env!("CARGO_PKG_VERSION")),
+                        format!("process_id:{}", std::process::id()),
+                        format!("uptime_in_seconds:{}", uptime),
+                        format!("uptime_in_days:{}", uptime / 86400),
+                    ]
+                },
+                "clients" => {
+                    let txn_queue = self.txn_queue.read().unwrap();
+                    vec![
+                        format!("pubsub_clients:{}", self.pubsub.lock().unwrap().num_subscribers()),
+                        format!("watching_clients:{}", txn_queue.values().filter(|txn| !txn.watched.is_empty()).count()),
+                        format!("open_transactions:{}", txn_queue.len()),
+                    ]
+                },
+                "memory" => {
+                    let config = self.config.read().unwrap();
+                    vec![
+                        format!("used_memory:{}", self.used_memory()),
+                        format!("maxmemory:{}", config.maxmemory),
+                        format!("maxmemory_policy:{}", config.maxmemory_policy),
+                    ]
+                },
+                "persistence" => {
+                    let appendfsync = self.config.read().unwrap().appendfsync;
+                    let pending_fsync = self.log_file.lock().await.dirty;
+                    // the AOF is only appended to, so it is reported as never rewritten like redis does before the first rewrite
+                    vec![
+                        "aof_enabled:1".to_string(),
+                        format!("appendfsync:{}", appendfsync),
+                        format!("aof_current_size:{}", self.metrics.aof_size()),
+                        format!("aof_pending_fsync:{}", pending_fsync as u8),
+                        "aof_rewrite_in_progress:0".to_string(),
+                        "aof_last_rewrite_time_sec:-1".to_string(),
+                    ]
+                },
+                "replication" => {
+                    let offset = self.metrics.replication_offset();
+                    let replicas = self.metrics.replicas();
+                    let mut lines = vec![
+                        format!("role:{}", match self.is_master {
+                            true => "master",
+                            false => "slave",
+                        }),
+                        format!("connected_slaves:{}", replicas.len()),
+                    ];
+                    // the lag is the number of writes the slave has not received, including the lost ones it never receives
+                    lines.extend(replicas.iter().enumerate().map(|(index, (addr, synced, lost))| {
+                        format!(
+                            "slave{}:ip={},port={},offset={},lag={},lost={}",
+                            index,
+                            addr.ip(),
+                            addr.port(),
+                            synced,
+                            offset.saturating_sub(*synced),
+                            lost
+                        )
+                    }));
+                    lines.push(format!("master_repl_offset:{}", offset));
+                    lines
+                },
+                "stats" => {
+                    let stats = self.dbs.iter().map(Keyspace::stats).fold(KeyspaceStats::default(), |total, stats| KeyspaceStats {
+                        expired_keys: total.expired_keys + stats.expired_keys,
+                        evicted_keys: total.evicted_keys + stats.evicted_keys,
+                    });
+                    let pubsub = self.pubsub.lock().unwrap();
+                    let pubsub_stats = pubsub.stats();
+                    vec![
+                        format!("total_commands_processed:{}", self.metrics.total_commands()),
+                        format!("instantaneous_ops_per_sec:{}", self.metrics.ops_per_sec()),
+                        format!("total_error_replies:{}", self.metrics.total_errors()),
+                        format!("expired_keys:{}", stats.expired_keys),
+                        format!("evicted_keys:{}", stats.evicted_keys),
+                        format!("pubsub_channels:{}", pubsub.num_channels()),
+                        format!("pubsub_patterns:{}", pubsub.numpat()),
+                        format!("pubsub_dropped_messages:{}", pubsub_stats.dropped_messages),
+                        format!("pubsub_disconnected_subscribers:{}", pubsub_stats.disconnected_subscribers),
+                    ]
+                },
+                // only the databases holding keys are listed, like redis
+                _ => self.dbs
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, db)| !db.is_empty())
+                    .map(|(index, db)| format!("db{}:keys={},expires={}", index, db.len(), db.expires()))
+                    .collect(),
+            };
+            lines.insert(0, format!("# {}{}", section[..1].to_uppercase(), &section[1..]));
+            report.push(lines.join("\n"));
         }
+        report.join("\n\n")
+    }
+
+    // the metrics in the text format of prometheus, the counters along with the gauges read now
+    pub fn metrics_text(&self) -> String {
+        let mut out = Exposition::default();
+        self.metrics.render(&mut out);
+        out.header("mini_redis_keys", "gauge", "The keys in each database");
+        for (index, db) in self.dbs.iter().enumerate() {
+            out.sample("mini_redis_keys", &[("db", index.to_string().as_str())], db.len());
+        }
+        out.header("mini_redis_expiring_keys", "gauge", "The keys with an expire time in each database");
+        for (index, db) in self.dbs.iter().enumerate() {
+            out.sample("mini_redis_expiring_keys", &[("db", index.to_string().as_str())], db.expires());
+        }
+        let pubsub = self.pubsub.lock().unwrap();
+        out.header("mini_redis_pubsub_subscribers", "gauge", "The sessions subscribing to a channel or a pattern");
+        out.sample("mini_redis_pubsub_subscribers", &[], pubsub.num_subscribers());
+        out.header("mini_redis_pubsub_channels", "gauge", "The channels with at least one subscriber");
+        out.sample("mini_redis_pubsub_channels", &[], pubsub.num_channels());
+        out.header("mini_redis_pubsub_patterns", "gauge", "The patterns with at least one subscriber");
+        out.sample("mini_redis_pubsub_patterns", &[], pubsub.numpat());
+        out.into_string()
     }
 
     async fn sync_slave(
         slave_addr: SocketAddr,
-        rx: Arc<AsyncMutex<broadcast::Receiver<volo_gen::volo::example::GetItemRequest>>>,
+        mut rx: broadcast::Receiver<volo_gen::volo::example::GetItemRequest>,
+        client_config: ClientConfig,
+        stats: Arc<ReplicaStats>,
     ) -> Result<(), Error> {
         // create the redis client
-        let slave = RedisClient::new(slave_addr);
+        let slave = RedisClient::with_config(slave_addr, client_config);
         
         loop {
             // receive the request from broadcast channel
-            let req = rx.lock().await.recv().await;
+            let req = rx.recv().await;
             match req {
                 Ok(req) => {
                     // if the opcode is 255, it means the task is closed
@@ -206,215 +798,1091 @@ impl S {
                         break;
                     }
                     // send the request to slave node
-                    let resp = slave.get_item(req).await?;
-                    tracing::info!("Sync response: {:?}", resp);
+                    // keep syncing the following requests even if the slave fails, the failed request is lost
+                    match slave.get_item(req).await {
+                        Ok(resp) if resp.success => {
+                            tracing::info!("Sync response: {:?}", resp);
+                            stats.synced.fetch_add(1, Ordering::Relaxed);
+                        },
+                        Ok(resp) => {
+                            tracing::error!("Sync to slave {} refused: {}", slave_addr, resp.value_message);
+                            stats.lost.fetch_add(1, Ordering::Relaxed);
+                        },
+                        Err(e) => {
+                            tracing::error!("Sync to slave {} failed: {:?}", slave_addr, e);
+                            stats.lost.fetch_add(1, Ordering::Relaxed);
+                        },
+                    }
                 },
-                Err(e) => {
-                    tracing::warn!("Broadcast Error: {:?}", e);
-                }
+                // the slave fell too far behind, and the skipped writes are lost on it
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::error!("Slave {} lagged behind, {} requests are not synced", slave_addr, skipped);
+                    stats.lost.fetch_add(skipped, Ordering::Relaxed);
+                },
+                // the master is dropped without sending the closing request
+                Err(broadcast::error::RecvError::Closed) => break,
             }
         }
 
         tracing::info!("Slave {} sync task is closed", slave_addr);
         Ok(())
     }
-}
 
-unsafe impl Send for S {}
-unsafe impl Sync for S {}
+    // the keys are scanned in the order of their hashes, and the cursor is the hash to continue from
+    // so every key that exists during the whole scan is returned exactly once, however the keyspace changes
+    fn scan(&self, db: usize, cursor: u64, args: &ScanArgs) -> (u64, Vec<String>) {
+        let mut candidates: Vec<(u64, String)> = self.dbs[db]
+            .keys(keyspace::now_ms())
+            .into_iter()
+            .map(|key| (scan_hash(&key), key))
+            .filter(|(hash, _)| *hash >= cursor)
+            .collect();
+        if candidates.len() > args.count {
+            // take the `count` smallest hashes, plus the keys sharing the hash of the last one
+            candidates.select_nth_unstable(args.count - 1);
+            let last = candidates[args.count - 1].0;
+            let (head, tail) = candidates.split_at_mut(args.count);
+            let mut batch = head.to_vec();
+            batch.extend(tail.iter().filter(|(hash, _)| *hash == last).cloned());
+            candidates = batch;
+        }
+        candidates.sort_unstable();
+        let next = match candidates.last() {
+            Some((hash, _)) if candidates.len() >= args.count => hash.wrapping_add(1),
+            _ => 0,
+        };
+        let keys = candidates
+            .into_iter()
+            .map(|(_, key)| key)
+            .filter(|key| args.matches(key))
+            .collect();
+        (next, keys)
+    }
 
-#[volo::async_trait]
-impl volo_gen::volo::example::ItemService for S {
-    async fn get_item(&self, _req: volo_gen::volo::example::GetItemRequest) -> ::core::result::Result<volo_gen::volo::example::GetItemResponse, ::volo_thrift::AnyhowError>{
-        let mut resp = volo_gen::volo::example::GetItemResponse {
-            opcode: _req.opcode,
-            key_channal: _req.key_channal.clone(),
+    // publish the keyspace event of the key if its class is enabled by notify-keyspace-events
+    fn notify_keyspace_event(&self, db: usize, class: char, event: &str, key: &str) {
+        let events = self.config.read().unwrap().notify_keyspace_events.clone();
+        if !events.is_enabled(class) {
+            return;
+        }
+        let mut pubsub = self.pubsub.lock().unwrap();
+        if events.keyspace {
+            pubsub.publish(&format!("__keyspace@{}__:{}", db, key), event);
+        }
+        if events.keyevent {
+            pubsub.publish(&format!("__keyevent@{}__:{}", db, event), key);
+        }
+    }
+
+    // mark the transactions watching the key as dirty, so that their EXEC aborts
+    fn touch(&self, db: usize, key: &str) {
+        let txn_ids = match self.watch_keys.read().unwrap().get(&(db, key.to_string())) {
+            Some(txn_ids) => txn_ids.clone(),
+            None => return,
+        };
+        let mut txn_queue = self.txn_queue.write().unwrap();
+        for txn_id in txn_ids {
+            if let Some(txn) = txn_queue.get_mut(&txn_id) {
+                txn.dirty = true;
+            }
+        }
+    }
+
+    // mark the transactions watching any key of the databases as dirty, for the commands changing the whole databases
+    fn touch_dbs(&self, dbs: &[usize]) {
+        for txn in self.txn_queue.write().unwrap().values_mut() {
+            if txn.watched.iter().any(|(db, _)| dbs.contains(db)) {
+                txn.dirty = true;
+            }
+        }
+    }
+
+    // remove all the keys of all the databases, and invalidate all the watches
+    fn flush_all(&self) {
+        for txn in self.txn_queue.write().unwrap().values_mut() {
+            if !txn.watched.is_empty() {
+                txn.dirty = true;
+            }
+        }
+        for kv_pairs in &self.dbs {
+            kv_pairs.clear();
+        }
+    }
+
+    // the database selected by the request, 0 if it selects none
+    fn select(&self, db: Option<i32>) -> Result<usize, Error> {
+        match db {
+            Some(db) => usize::try_from(db).ok().filter(|db| *db < self.dbs.len()).ok_or(Error::msg("ERR DB index is out of range")),
+            None => Ok(0),
+        }
+    }
+
+    // the memory taken by the keys of all the databases
+    fn used_memory(&self) -> usize {
+        self.dbs.iter().map(Keyspace::used_memory).sum()
+    }
+
+    // log, replicate and notify the deletion of a key removed by the server itself, such as an expired or evicted key
+    // the deletion is replicated as DEL, so that the slaves never expire or evict the keys by themselves
+    fn dropped(&self, db: usize, key: &str, class: char, event: &str, effects: &mut Effects) {
+        effects.append(db, format!("DEL {}", key));
+        self.touch(db, key);
+        self.notify_keyspace_event(db, class, event, key);
+        effects.replicas.push(volo_gen::volo::example::GetItemRequest {
+            opcode: 101,    // set the opcode to 101, which is DELMASTER
+            key_channal: key.to_string().into(),
             value_message: " ".into(),
-            success: false
+            txn_id: None,
+            session_id: None,
+            db: Some(db as i32),
+            auth: None,
+        });
+    }
+
+    // delete the key on the master if it has expired, before a command accesses it
+    // the slaves only hide the expired key until the master replicates the deletion
+    fn expire_if_needed(&self, db: usize, key: &str, effects: &mut Effects) {
+        if self.is_master && self.dbs[db].remove_expired(key, keyspace::now_ms()) {
+            self.dropped(db, key, 'x', "expired", effects);
+        }
+    }
+
+    // delete the expired keys in the background like redis: sample the keys with an expire time, delete the expired ones,
+    // and sample again while a quarter of them have expired, as long as the time budget allows
+    // the keys to delete are only known once they are sampled, so the keyspace is held exclusively instead of locking each key
+    async fn active_expire(&self) {
+        let _exclusive = self.keyspace_lock.write().await;
+        let mut effects = Effects::default();
+        let deadline = Instant::now() + ACTIVE_EXPIRE_BUDGET;
+        for (db, kv_pairs) in self.dbs.iter().enumerate() {
+            for key in kv_pairs.expire_sampled(ACTIVE_EXPIRE_SAMPLES, deadline) {
+                self.dropped(db, &key, 'x', "expired", &mut effects);
+            }
+        }
+        self.commit(effects).await;
+    }
+
+    // whether the keys take more memory than maxmemory, which only limits the master
+    fn is_oom(&self) -> bool {
+        let maxmemory = self.config.read().unwrap().maxmemory;
+        self.is_master && maxmemory > 0 && self.used_memory() > maxmemory
+    }
+
+    // evict the keys by maxmemory-policy until they take no more memory than maxmemory, or nothing can be evicted
+    // each time the best of the candidates of the databases is evicted, as if the keys were in a single keyspace
+    async fn evict(&self) {
+        let (maxmemory, policy, samples) = {
+            let config = self.config.read().unwrap();
+            (config.maxmemory, config.maxmemory_policy, config.maxmemory_samples)
         };
-        let opcode = OPCode::from(_req.opcode);
-        // check if need to push the request to transaction task queue
-        if opcode != OPCode::MULTI
-            && opcode != OPCode::EXEC
-            && opcode != OPCode::WATCH
-            && _req.txn_id.is_some()
-            && _req.txn_id.clone().unwrap().parse::<usize>().is_ok()
-        {
-            let txn_id = _req.txn_id.unwrap();
-            let txn_id = match txn_id.parse::<usize>() {
-                Ok(id) => id,
-                Err(_) => {
-                    resp.success = false;
-                    return Ok(resp);
-                }
+        if !self.is_master || maxmemory == 0 {
+            return;
+        }
+        loop {
+            if self.used_memory() <= maxmemory {
+                break;
+            }
+            let now = keyspace::now_ms();
+            let candidate = self.dbs
+                .iter()
+                .enumerate()
+                .filter(|(_, kv_pairs)| kv_pairs.used_memory() > 0)
+                .filter_map(|(db, kv_pairs)| kv_pairs.eviction_candidate(policy, samples, now).map(|(score, key)| (score, db, key)))
+                .max_by_key(|(score, _, _)| *score);
+            let Some((_, db, key)) = candidate else {
+                break;
             };
-            let mut txn_queue_locked = self.txn_queue.write().unwrap();
-            let txn_queue = txn_queue_locked.get_mut(&txn_id).unwrap();
-            txn_queue.push(volo_gen::volo::example::GetItemRequest {
-                opcode: _req.opcode,
-                key_channal: _req.key_channal,
-                value_message: _req.value_message,
-                txn_id: None,
-            });
-            resp.value_message = "QUEUED".into();
-            resp.success = true;
+            // the key may be written meanwhile, so it is evicted and logged under its lock
+            let _key_lock = self.key_lock(db, &key).write().await;
+            if self.dbs[db].remove_evicted(&key) {
+                let mut effects = Effects::default();
+                self.dropped(db, &key, 'e', "evicted", &mut effects);
+                self.commit(effects).await;
+            }
+        }
+    }
 
-            return Ok(resp);
+    // the lock of the key, shared by the keys of the same hash
+    fn key_lock(&self, db: usize, key: &str) -> &AsyncRwLock<()> {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        (db, key).hash(&mut hasher);
+        &self.key_locks[hasher.finish() as usize % KEY_LOCKS]
+    }
+}
+
+// the number of the locks of the keys, each shared by the keys of the same hash
+const KEY_LOCKS: usize = 64;
+
+// the interval of the active expiration, the number of the keys sampled each time, and the time it may take
+const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+const ACTIVE_EXPIRE_SAMPLES: usize = 20;
+const ACTIVE_EXPIRE_BUDGET: Duration = Duration::from_millis(25);
+
+// delete the expired keys periodically, so that the keys never accessed again do not stay in memory
+fn start_expire_cycle(state: Weak<State>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(ACTIVE_EXPIRE_INTERVAL).await;
+            let Some(state) = state.upgrade() else {
+                break;
+            };
+            if state.is_master {
+                state.active_expire().await;
+            }
         }
-        match opcode {
-            OPCode::GET => {
-                let key: String = _req.key_channal.into();
-                match self.kv_pairs.read().unwrap().get(&key) {
-                    Some(value) => {
-                        resp.value_message = value.clone().into();
-                        resp.success = true;
-                    },
-                    None => {
-                        resp.value_message = "(nil)".into();
-                        resp.success = false;
+    });
+}
+
+fn scan_hash(key: &str) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+// the options of SCAN, given in the value_message as `[MATCH pattern] [COUNT count] [TYPE type]`
+pub struct ScanArgs {
+    pub pattern: Option<String>,
+    pub count: usize,
+    pub key_type: Option<String>,
+}
+
+impl ScanArgs {
+    pub fn parse(args: &str) -> Result<ScanArgs, Error> {
+        let mut scan_args = ScanArgs {
+            pattern: None,
+            count: 10,
+            key_type: None,
+        };
+        let mut iter = args.split_whitespace();
+        while let Some(option) = iter.next() {
+            let value = iter.next().ok_or(Error::msg("ERR syntax error"))?;
+            match option.to_lowercase().as_str() {
+                "match" => scan_args.pattern = Some(value.to_string()),
+                "count" => {
+                    scan_args.count = value.parse().map_err(|_| Error::msg("ERR value is not an integer or out of range"))?;
+                    if scan_args.count == 0 {
+                        return Err(Error::msg("ERR syntax error"));
                     }
-                }
+                },
+                "type" => scan_args.key_type = Some(value.to_lowercase()),
+                _ => return Err(Error::msg("ERR syntax error")),
             }
-            OPCode::SET | OPCode::SETMASTER => {
-                // prevent the slave node from setting the key-value pair
-                // unless the opcode is SETMASTER, which is sent by master node
-                if !self.is_master && opcode == OPCode::SET {
-                    return Err(Error::msg("The server is slave"));
-                }
-                let key: String = _req.clone().key_channal.into();
-                let val: String = _req.clone().value_message.into();
-                let _ = self.log_file.lock().await.write_all(format!("SET {} {}\n", _req.key_channal, _req.value_message).as_bytes()).await;
-                if let Some(watch_ids) = self.watch_keys.write().unwrap().get_mut(&key) {
-                    if _req.txn_id.is_some() && watch_ids.contains(_req.txn_id.clone().unwrap().as_str()) {
-                        watch_ids.retain(|x| x == &_req.txn_id.clone().unwrap().to_string());
-                    } else {
-                        watch_ids.clear();
-                    }
-                }
+        }
+        Ok(scan_args)
+    }
 
-                self.kv_pairs.write().unwrap().insert(key, val);
-                resp.value_message = "OK".into();
-                resp.success = true;
-                
-                if let Some(ref tx) = self.op_tx {
-                    let req = volo_gen::volo::example::GetItemRequest {
-                        opcode: 100,    // set the opcode to 100, which is SETMASTER
-                        key_channal: _req.key_channal.clone(),
-                        value_message: _req.value_message.clone(),
-                        txn_id: None,
-                    };
-                    // send the request to broadcast channel
-                    let _ = tx.lock().unwrap().send(req);
-                }
+    fn matches(&self, key: &str) -> bool {
+        // all the values are strings for now
+        self.key_type.as_ref().is_none_or(|key_type| key_type == "string")
+            && self.pattern.as_ref().is_none_or(|pattern| glob_match(pattern, key))
+    }
+}
+
+impl State {
+    // log and replicate the writes of a request
+    async fn commit(&self, effects: Effects) {
+        if !effects.log.is_empty() {
+            self.log_file.lock().await.append(effects.log.iter().map(String::as_str)).await;
+        }
+        if let Some(ref tx) = self.op_tx {
+            for req in effects.replicas {
+                // send the request to broadcast channel
+                let _ = tx.send(req);
+                self.metrics.replicated();
             }
-            OPCode::DEL | OPCode::DELMASTER=> {
-                // prevent the slave node from deleting the key-value pair
-                if !self.is_master && opcode == OPCode::DEL {
-                    return Err(Error::msg("The server is slave"));
-                }
-                let key: String = _req.clone().key_channal.into();
-                let is_in: bool = self.kv_pairs.read().unwrap().contains_key(&key);
-                match is_in {
-                    true => {
-                        let _ = self.log_file.lock().await.write_all(format!("DEL {}\n", _req.key_channal).as_bytes()).await;
-                        if let Some(watch_ids) = self.watch_keys.write().unwrap().get_mut(&key) {
-                            if _req.txn_id.is_some() && watch_ids.contains(_req.txn_id.clone().unwrap().as_str()) {
-                                watch_ids.retain(|x| x == &_req.txn_id.clone().unwrap().to_string());
-                            } else {
-                                watch_ids.clear();
-                            }
-                        }
-                        
-                        self.kv_pairs.write().unwrap().remove(&key);
-                        resp.value_message = "1".into();
-                        resp.success = true;
+        }
+    }
 
-                        if let Some(ref tx) = self.op_tx {
-                            let req = volo_gen::volo::example::GetItemRequest {
-                                opcode: 101,    // set the opcode to 101, which is DELMASTER
-                                key_channal: _req.key_channal.clone(),
-                                value_message: _req.value_message.clone(),
-                                txn_id: None,
-                            };
-                            // send the request to broadcast channel
-                            let _ = tx.lock().unwrap().send(req);
-                        }
-                    },
-                    false => {
-                        resp.value_message = "0".into();
-                        resp.success = true;
-                    }
+    // log the writes of a transaction as a block between MULTI and EXEC, and replicate them as a single request,
+    // so that both the recovery and the slaves apply the whole transaction or nothing of it
+    async fn commit_block(&self, effects: Effects) {
+        if effects.log.is_empty() {
+            return;
+        }
+        let lines = std::iter::once("MULTI").chain(effects.log.iter().map(String::as_str)).chain(std::iter::once("EXEC"));
+        self.log_file.lock().await.append(lines).await;
+        if let Some(ref tx) = self.op_tx {
+            let req = volo_gen::volo::example::GetItemRequest {
+                opcode: 103,    // set the opcode to 103, which is EXECMASTER
+                key_channal: " ".into(),
+                value_message: effects.log.join("\n").into(),
+                txn_id: None,
+                session_id: None,
+                db: None,
+                auth: None,
+            };
+            // send the request to broadcast channel
+            let _ = tx.send(req);
+            self.metrics.replicated();
+        }
+    }
+
+    // evict the keys before running the request, and refuse it if it may take more memory while the keys still take too much
+    // the transaction is discarded on EXEC if the request is refused while being queued, like the other queue-time errors
+    async fn check_memory(&self, _req: &volo_gen::volo::example::GetItemRequest) -> Option<volo_gen::volo::example::GetItemResponse> {
+        let opcode = OPCode::from(_req.opcode);
+        if opcode.is_replicated() {
+            return None;
+        }
+        self.evict().await;
+        if !opcode.is_denyoom() || !self.is_oom() {
+            return None;
+        }
+        if let Some(txn_id) = &_req.txn_id {
+            let mut txn_queue_locked = self.txn_queue.write().unwrap();
+            if let Ok(Some(txn)) = find_txn(&mut txn_queue_locked, txn_id, _req.session_id.as_deref()) {
+                if txn.is_multi() {
+                    txn.aborted = true;
                 }
             }
-            OPCode::PING => {
-                resp.value_message = _req.value_message;
-                resp.success = true;
-            }
-            OPCode::SUBSCRIBE => {
-                // prevent the slave node from subscribing the channel
-                if !self.is_master {
-                    return Err(Error::msg("The server is slave"));
-                }
-                let key: String = _req.key_channal.into();
-                let (mut _tx, mut rx) = broadcast::channel(500);
-                let mut has_channel: bool = false;
-                {
-                    match {self.channels.read().unwrap().get(&key)} {
-                        Some(get_tx) => {
-                            has_channel = true;
-                            rx = get_tx.subscribe();
-                        },
-                        None => {
-                            
-                        },
+        }
+        Some(volo_gen::volo::example::GetItemResponse {
+            opcode: _req.opcode,
+            key_channal: _req.key_channal.clone(),
+            value_message: "OOM command not allowed when used memory > 'maxmemory'.".into(),
+            success: false,
+        })
+    }
+
+    // run the script of EVAL, or the cached one of EVALSHA, the caller must lock the keyspace exclusively
+    // the commands called by the script are executed one by one, and their writes are collected into the effects
+    async fn eval(
+        &self,
+        opcode: OPCode,
+        db: usize,
+        _req: &volo_gen::volo::example::GetItemRequest,
+        effects: &mut Effects,
+    ) -> Result<Reply, Error> {
+        let body = match opcode {
+            OPCode::EVALSHA => self.scripts.get(&_req.key_channal).ok_or(Error::msg("NOSCRIPT No matching script. Please use EVAL."))?,
+            _ => {
+                // EVAL caches the script as well, so that it can be run by EVALSHA later
+                self.scripts.load(&_req.key_channal)?;
+                _req.key_channal.to_string()
+            },
+        };
+        let (keys, argv) = script::parse_args(&_req.value_message)?;
+        let user = self.acl.user_of(_req)?;
+        self.run_program(Program::Script(body), &user, db, keys, argv, false, effects).await
+    }
+
+    // call the function of FCALL or FCALL_RO, the caller must lock the keyspace exclusively
+    // the functions without the no-writes flag can not be called by FCALL_RO
+    async fn fcall(
+        &self,
+        opcode: OPCode,
+        db: usize,
+        _req: &volo_gen::volo::example::GetItemRequest,
+        effects: &mut Effects,
+    ) -> Result<Reply, Error> {
+        let (code, info) = {
+            let functions = self.functions.read().unwrap();
+            let (library, info) = functions.find(&_req.key_channal).ok_or(Error::msg("ERR Function not found"))?;
+            (library.code.clone(), info.clone())
+        };
+        if opcode == OPCode::FCALLRO && !info.no_writes {
+            return Err(Error::msg("ERR Can not execute a script with write flag using *_ro command."));
+        }
+        let (keys, argv) = script::parse_args(&_req.value_message)?;
+        let user = self.acl.user_of(_req)?;
+        self.run_program(Program::Function { code, name: info.name }, &user, db, keys, argv, info.no_writes, effects).await
+    }
+
+    // the commands called by the program run on the database of the caller, and are checked against the permissions of its user
+    // the lua callbacks are synchronous, so the program runs on a blocking thread and sends the commands it calls over a channel,
+    // then they are executed here on the runtime one at a time, while the program waits for each response
+    async fn run_program(
+        &self,
+        program: Program,
+        user: &str,
+        db: usize,
+        keys: Vec<String>,
+        argv: Vec<String>,
+        read_only: bool,
+        effects: &mut Effects,
+    ) -> Result<Reply, Error> {
+        let (call_tx, mut call_rx) = mpsc::unbounded_channel::<(
+            volo_gen::volo::example::GetItemRequest,
+            oneshot::Sender<Result<volo_gen::volo::example::GetItemResponse, Error>>,
+        )>();
+        let scripts = self.scripts.clone();
+        let program = tokio::task::spawn_blocking(move || {
+            scripts.run(program, keys, argv, read_only, &|req| {
+                let (resp_tx, resp_rx) = oneshot::channel();
+                call_tx.send((req, resp_tx)).map_err(|_| Error::msg("ERR the script is cancelled"))?;
+                resp_rx.blocking_recv().map_err(|_| Error::msg("ERR the script is cancelled"))?
+            })
+        });
+        // the channel is closed once the program returns and drops the sender
+        while let Some((mut req, resp_tx)) = call_rx.recv().await {
+            req.db = Some(db as i32);
+            let resp = match self.acl.check(user, &req) {
+                Ok(()) => self.execute_boxed(req, effects).await,
+                Err(e) => Err(e),
+            };
+            let _ = resp_tx.send(resp);
+        }
+        program.await.map_err(|e| Error::msg(format!("ERR Error running script: {}", e)))?
+    }
+
+    // execute a command called by a program, which is run by execute itself, so the future is boxed to be recursive
+    fn execute_boxed<'a>(
+        &'a self,
+        _req: volo_gen::volo::example::GetItemRequest,
+        effects: &'a mut Effects,
+    ) -> Pin<Box<dyn Future<Output = Result<volo_gen::volo::example::GetItemResponse, Error>> + Send + 'a>> {
+        Box::pin(self.execute(_req, effects))
+    }
+
+    // the subcommands of FUNCTION, the changes of the libraries are logged and replicated as the libraries they result in
+    fn function(&self, _req: &volo_gen::volo::example::GetItemRequest, effects: &mut Effects) -> Result<String, Error> {
+        let args = _req.value_message.trim();
+        let subcommand = _req.key_channal.to_lowercase();
+        if !self.is_master && matches!(subcommand.as_str(), "load" | "delete" | "restore" | "flush") {
+            return Err(Error::msg("The server is slave"));
+        }
+        let mut log = Vec::new();
+        let result = match subcommand.as_str() {
+            // the value_message is `[REPLACE] <code>`, the name of the library is returned
+            "load" => {
+                let (replace, code) = match args.split_once(char::is_whitespace) {
+                    Some((replace, code)) if replace.eq_ignore_ascii_case("replace") => (true, code.trim_start()),
+                    _ => (false, args),
+                };
+                let mut functions = self.functions.write().unwrap();
+                let library = functions.load(code, replace)?;
+                log.push(Functions::log_line(library));
+                library.name.clone()
+            },
+            // the value_message is `[LIBRARYNAME <pattern>] [WITHCODE]`
+            "list" => {
+                let mut pattern = None;
+                let mut with_code = false;
+                let mut iter = args.split_whitespace();
+                while let Some(option) = iter.next() {
+                    match option.to_lowercase().as_str() {
+                        "libraryname" => pattern = Some(iter.next().ok_or(Error::msg("ERR syntax error"))?),
+                        "withcode" => with_code = true,
+                        _ => return Err(Error::msg("ERR syntax error")),
                     }
                 }
-                if has_channel {
-                    let mes = rx.recv().await;
-                    match mes {
-                        Ok(m) => {
-                            resp.value_message = m.clone().into();
-                            resp.success = true;
+                self.functions.read().unwrap().list(pattern, with_code)
+            },
+            "delete" if !args.is_empty() => {
+                self.functions.write().unwrap().delete(args)?;
+                log.push(format!("FUNCTION DELETE {}", args));
+                "OK".to_string()
+            },
+            "dump" if args.is_empty() => self.functions.read().unwrap().dump(),
+            // the value_message is `<payload> [FLUSH|APPEND|REPLACE]`
+            "restore" if !args.is_empty() => {
+                let mut iter = args.split_whitespace();
+                let payload = iter.next().unwrap_or_default();
+                let policy = function::RestorePolicy::parse(iter.next())?;
+                if iter.next().is_some() {
+                    return Err(Error::msg("ERR syntax error"));
+                }
+                let libraries = self.functions.write().unwrap().restore(payload, policy)?;
+                if policy == function::RestorePolicy::Flush {
+                    log.push("FUNCTION FLUSH".to_string());
+                }
+                log.extend(libraries.iter().map(Functions::log_line));
+                "OK".to_string()
+            },
+            "flush" if args.is_empty() => {
+                self.functions.write().unwrap().flush();
+                log.push("FUNCTION FLUSH".to_string());
+                "OK".to_string()
+            },
+            "kill" if args.is_empty() => {
+                self.scripts.kill()?;
+                "OK".to_string()
+            },
+            _ => return Err(Error::msg("ERR unknown subcommand or wrong number of arguments for FUNCTION")),
+        };
+        for line in log {
+            effects.replicas.push(volo_gen::volo::example::GetItemRequest {
+                opcode: 104,    // set the opcode to 104, which is FUNCTIONMASTER
+                key_channal: " ".into(),
+                value_message: line.clone().into(),
+                txn_id: None,
+                session_id: None,
+                db: None,
+                auth: None,
+            });
+            effects.log.push(line);
+        }
+        Ok(result)
+    }
+
+    // take the transaction of the request out if it has started MULTI, and stop watching its keys
+    fn take_multi(&self, _req: &volo_gen::volo::example::GetItemRequest, command: &str) -> Result<TxnQueue, String> {
+        let without_multi = format!("ERR {} without MULTI", command);
+        let txn_id = _req.txn_id.as_ref().ok_or(without_multi.clone())?.to_string();
+        let mut txn_queue_locked = self.txn_queue.write().unwrap();
+        match find_txn(&mut txn_queue_locked, &txn_id, _req.session_id.as_deref()) {
+            Ok(Some(txn)) if txn.is_multi() => {},
+            Ok(_) => return Err(without_multi),
+            Err(e) => return Err(e.to_string()),
+        }
+        let mut txn = txn_queue_locked.remove(&txn_id).ok_or(without_multi)?;
+        unwatch(&mut self.watch_keys.write().unwrap(), &txn_id, &mut txn);
+        Ok(txn)
+    }
+
+    // execute the queued requests of the transaction, no other request runs in the meantime
+    async fn exec(&self, _req: volo_gen::volo::example::GetItemRequest) -> Result<volo_gen::volo::example::GetItemResponse, Error> {
+        let mut resp = volo_gen::volo::example::GetItemResponse {
+            opcode: _req.opcode,
+            key_channal: _req.key_channal.clone(),
+            value_message: " ".into(),
+            success: false
+        };
+        // prevent the slave node from executing the transaction
+        if !self.is_master {
+            return Err(Error::msg("The server is slave"));
+        }
+        let _exclusive = self.keyspace_lock.write().await;
+        self.evict().await;
+
+        // message is used to collect the messages of each request in the transaction
+        let mut message = String::new();
+        resp.success = true;
+
+        // take the transaction out, and stop watching its keys whether it runs or not
+        let mut txn_queue_todo = match self.take_multi(&_req, "EXEC") {
+            Ok(txn) => txn,
+            Err(e) => {
+                resp.success = false;
+                resp.value_message = e.into();
+                return Ok(resp);
+            }
+        };
+
+        // execute the transaction
+        let mut effects = Effects::default();
+        match (txn_queue_todo.aborted, txn_queue_todo.dirty) {
+            (true, _) => {
+                message = "EXECABORT Transaction discarded because of previous errors.".into();
+                resp.success = false;
+            }
+            (false, false) => {
+                while let Some(req) = txn_queue_todo.pop() {
+                    let result = self.execute(req, &mut effects).await;
+                    match result {
+                        Ok(info) => {
+                            message = format!("{}\n{}", message, info.value_message);
                         },
-                        Err(_e) => {
+                        Err(e) => {
+                            message = format!("{}\n{}", message, e);
                             resp.success = false;
+                            break;
                         }
                     }
-                } else {
-                    {
-                        self.channels.write().unwrap().insert(key, _tx);
+                }
+            }
+            (false, true) => {
+                message = "The watch key has been changed".into();
+                resp.success = false;
+            }
+        }
+        self.commit_block(effects).await;
+        message = message.trim().into();
+        resp.value_message = message.clone().into();
+        Ok(resp)
+    }
+
+    // execute a request, its writes are collected into the effects instead of being logged and replicated
+    async fn execute(
+        &self,
+        _req: volo_gen::volo::example::GetItemRequest,
+        effects: &mut Effects,
+    ) -> Result<volo_gen::volo::example::GetItemResponse, Error> {
+        let mut resp = volo_gen::volo::example::GetItemResponse {
+            opcode: _req.opcode,
+            key_channal: _req.key_channal.clone(),
+            value_message: " ".into(),
+            success: false
+        };
+        let opcode = OPCode::from(_req.opcode);
+        // the database selected by the session, which the queued request keeps as well
+        let db = match self.select(_req.db) {
+            Ok(db) => db,
+            Err(e) => {
+                resp.value_message = e.to_string().into();
+                return Ok(resp);
+            }
+        };
+        let kv_pairs = &self.dbs[db];
+        // check if need to push the request to transaction task queue
+        // the requests between WATCH and MULTI also carry the txn_id, but they are executed at once
+        let txn_id = _req.txn_id.as_ref().map(|id| id.to_string());
+        let session_id = _req.session_id.as_ref().map(|id| id.to_string());
+        if let Some(txn_id) = txn_id.as_ref().filter(|_| {
+            !matches!(opcode, OPCode::MULTI | OPCode::EXEC | OPCode::WATCH | OPCode::DISCARD)
+        }) {
+            let mut txn_queue_locked = self.txn_queue.write().unwrap();
+            match find_txn(&mut txn_queue_locked, txn_id, session_id.as_deref()) {
+                Ok(Some(txn_queue)) if txn_queue.is_multi() => {
+                    // the request is rejected, and the whole transaction is discarded on EXEC
+                    if let Some(e) = opcode.txn_error() {
+                        txn_queue.aborted = true;
+                        resp.value_message = e.into();
+                        return Ok(resp);
                     }
-                    let mes = rx.recv().await;
-                    match mes {
-                        Ok(m) => {
-                            resp.value_message = m.clone().into();
-                            resp.success = true;
-                        },
-                        Err(_e) => {
-                            resp.success = false;
+                    // the session selects the database once SELECT is queued, so the index is checked at once
+                    if opcode == OPCode::SELECT {
+                        if let Err(e) = parse_db(&_req.key_channal, self.dbs.len()) {
+                            txn_queue.aborted = true;
+                            resp.value_message = e.to_string().into();
+                            return Ok(resp);
                         }
                     }
+                    txn_queue.push(volo_gen::volo::example::GetItemRequest {
+                        opcode: _req.opcode,
+                        key_channal: _req.key_channal,
+                        value_message: _req.value_message,
+                        txn_id: None,
+                        session_id: None,
+                        db: _req.db,
+                        auth: None,
+                    });
+                    resp.value_message = "QUEUED".into();
+                    resp.success = true;
+                    return Ok(resp);
+                },
+                Ok(Some(_)) => {},
+                Ok(None) => {
+                    resp.value_message = "ERR no such transaction".into();
+                    return Ok(resp);
+                },
+                Err(e) => {
+                    resp.value_message = e.into();
+                    return Ok(resp);
+                },
+            }
+        }
+        match opcode {
+            OPCode::GET => {
+                let key: String = _req.key_channal.into();
+                self.expire_if_needed(db, &key, effects);
+                match kv_pairs.get(&key, keyspace::now_ms()) {
+                    Some(value) => {
+                        resp.value_message = value.into();
+                        resp.success = true;
+                    },
+                    None => {
+                        resp.value_message = "(nil)".into();
+                        resp.success = false;
+                    }
                 }
             }
-            OPCode::PUBLISH => {
-                // prevent the slave node from publishing the message
+            OPCode::SET | OPCode::SETMASTER => {
+                // prevent the slave node from setting the key-value pair
+                // unless the opcode is SETMASTER, which is sent by master node
+                if !self.is_master && opcode == OPCode::SET {
+                    return Err(Error::msg("The server is slave"));
+                }
+                let key: String = _req.clone().key_channal.into();
+                let val: String = _req.clone().value_message.into();
+                effects.append(db, format!("SET {} {}", _req.key_channal, _req.value_message));
+                self.touch(db, &key);
+
+                kv_pairs.insert(key.clone(), val, keyspace::now_ms());
+                self.notify_keyspace_event(db, '$', "set", &key);
+                resp.value_message = "OK".into();
+                resp.success = true;
+                
+                effects.replicas.push(volo_gen::volo::example::GetItemRequest {
+                    opcode: 100,    // set the opcode to 100, which is SETMASTER
+                    key_channal: _req.key_channal.clone(),
+                    value_message: _req.value_message.clone(),
+                    txn_id: None,
+                    session_id: None,
+                    db: Some(db as i32),
+                    auth: None,
+                });
+            }
+            OPCode::DEL | OPCode::DELMASTER=> {
+                // prevent the slave node from deleting the key-value pair
+                if !self.is_master && opcode == OPCode::DEL {
+                    return Err(Error::msg("The server is slave"));
+                }
+                let key: String = _req.clone().key_channal.into();
+                // an expired key is deleted as expired, and DEL finds nothing to delete then
+                self.expire_if_needed(db, &key, effects);
+                let is_in: bool = kv_pairs.remove(&key);
+                match is_in {
+                    true => {
+                        effects.append(db, format!("DEL {}", _req.key_channal));
+                        self.touch(db, &key);
+                        self.notify_keyspace_event(db, 'g', "del", &key);
+                        resp.value_message = "1".into();
+                        resp.success = true;
+
+                        effects.replicas.push(volo_gen::volo::example::GetItemRequest {
+                            opcode: 101,    // set the opcode to 101, which is DELMASTER
+                            key_channal: _req.key_channal.clone(),
+                            value_message: _req.value_message.clone(),
+                            txn_id: None,
+                            session_id: None,
+                            db: Some(db as i32),
+                            auth: None,
+                        });
+                    },
+                    false => {
+                        resp.value_message = "0".into();
+                        resp.success = true;
+                    }
+                }
+            }
+            OPCode::EXPIRE | OPCode::PERSIST => {
+                // prevent the slave node from changing the expire time
                 if !self.is_master {
                     return Err(Error::msg("The server is slave"));
                 }
-                let key: String = _req.key_channal.into();
-                if let Some(tx) = {self.channels.read().unwrap().get(&key)} {
-                    let info = tx.send(_req.value_message.into_string());
-                    match info {
-                        Ok(num) => {
+                // the key is in the key_channal, and the seconds to live of EXPIRE are in the value_message
+                // 1 is returned if the expire time is changed, otherwise 0
+                let key: String = _req.key_channal.to_string();
+                self.expire_if_needed(db, &key, effects);
+                let seconds = match opcode {
+                    OPCode::EXPIRE => match _req.value_message.trim().parse::<i64>() {
+                        Ok(seconds) => Some(seconds),
+                        Err(_) => {
+                            resp.value_message = "ERR value is not an integer or out of range".into();
+                            return Ok(resp);
+                        }
+                    },
+                    _ => None,
+                };
+                let now = keyspace::now_ms();
+                let expire_at = match kv_pairs.expire_at(&key, now) {
+                    Some(expire_at) => expire_at,
+                    None => {
+                        resp.value_message = "0".into();
+                        resp.success = true;
+                        return Ok(resp);
+                    }
+                };
+                match seconds {
+                    // a key with a non-positive time to live is deleted at once
+                    Some(seconds) if seconds <= 0 => {
+                        kv_pairs.remove(&key);
+                        self.dropped(db, &key, 'g', "del", effects);
+                    },
+                    Some(seconds) => {
+                        let expire_at = now.saturating_add((seconds as u64).saturating_mul(1000));
+                        kv_pairs.set_expire(&key, Some(expire_at));
+                        effects.append(db, format!("EXPIREAT {} {}", key, expire_at));
+                        self.touch(db, &key);
+                        self.notify_keyspace_event(db, 'g', "expire", &key);
+                        effects.replicas.push(volo_gen::volo::example::GetItemRequest {
+                            opcode: 105,    // set the opcode to 105, which is EXPIREMASTER
+                            key_channal: key.clone().into(),
+                            value_message: expire_at.to_string().into(),
+                            txn_id: None,
+                            session_id: None,
+                            db: Some(db as i32),
+                            auth: None,
+                        });
+                    },
+                    None if expire_at.is_none() => {
+                        resp.value_message = "0".into();
+                        resp.success = true;
+                        return Ok(resp);
+                    },
+                    None => {
+                        kv_pairs.set_expire(&key, None);
+                        effects.append(db, format!("PERSIST {}", key));
+                        self.touch(db, &key);
+                        self.notify_keyspace_event(db, 'g', "persist", &key);
+                        effects.replicas.push(volo_gen::volo::example::GetItemRequest {
+                            opcode: 105,    // set the opcode to 105, which is EXPIREMASTER
+                            key_channal: key.clone().into(),
+                            value_message: "-1".into(),
+                            txn_id: None,
+                            session_id: None,
+                            db: Some(db as i32),
+                            auth: None,
+                        });
+                    },
+                }
+                resp.value_message = "1".into();
+                resp.success = true;
+            }
+            OPCode::EXPIREMASTER => {
+                // the expire time replicated by the master node, the value_message is the unix time in milliseconds, or -1 for PERSIST
+                let key = _req.key_channal.to_string();
+                let expire_at = _req.value_message.parse::<u64>().ok();
+                kv_pairs.set_expire(&key, expire_at);
+                effects.append(db, match expire_at {
+                    Some(expire_at) => format!("EXPIREAT {} {}", key, expire_at),
+                    None => format!("PERSIST {}", key),
+                });
+                resp.value_message = "OK".into();
+                resp.success = true;
+            }
+            OPCode::TTL => {
+                // the seconds to live of the key, -1 if it has no expire time, and -2 if there is no such key
+                let key = _req.key_channal.to_string();
+                self.expire_if_needed(db, &key, effects);
+                let now = keyspace::now_ms();
+                let ttl: i64 = match kv_pairs.expire_at(&key, now) {
+                    Some(Some(expire_at)) => (expire_at.saturating_sub(now).div_ceil(1000)) as i64,
+                    Some(None) => -1,
+                    None => -2,
+                };
+                resp.value_message = ttl.to_string().into();
+                resp.success = true;
+            }
+            OPCode::MEMORY => {
+                // the subcommand USAGE or STATS is in the key_channal, and the key of USAGE is in the value_message
+                // STATS returns the names and the values separated by lines, summed over all the databases
+                let args: Vec<&str> = _req.value_message.split_whitespace().collect();
+                match (_req.key_channal.to_lowercase().as_str(), args.as_slice()) {
+                    ("usage", [key]) => match kv_pairs.usage(key, keyspace::now_ms()) {
+                        Some(bytes) => {
+                            resp.value_message = bytes.to_string().into();
                             resp.success = true;
-                            resp.value_message = num.to_string().into();
                         },
-                        Err(_) => {
-                            resp.success = false;
+                        None => {
+                            resp.value_message = "(nil)".into();
+                        }
+                    },
+                    ("stats", []) => {
+                        let config = self.config.read().unwrap();
+                        let stats = self.dbs.iter().map(Keyspace::stats).fold(KeyspaceStats::default(), |total, stats| KeyspaceStats {
+                            expired_keys: total.expired_keys + stats.expired_keys,
+                            evicted_keys: total.evicted_keys + stats.evicted_keys,
+                        });
+                        let keys: usize = self.dbs.iter().map(Keyspace::len).sum();
+                        let expires: usize = self.dbs.iter().map(Keyspace::expires).sum();
+                        resp.value_message = format!(
+                            "used_memory\n{}\nmaxmemory\n{}\nmaxmemory_policy\n{}\nkeys\n{}\nexpires\n{}\nexpired_keys\n{}\nevicted_keys\n{}",
+                            self.used_memory(), config.maxmemory, config.maxmemory_policy,
+                            keys, expires, stats.expired_keys, stats.evicted_keys
+                        ).into();
+                        resp.success = true;
+                    },
+                    _ => {
+                        resp.value_message = "ERR unknown subcommand or wrong number of arguments for MEMORY".into();
+                    }
+                }
+            }
+            OPCode::PING => {
+                resp.value_message = _req.value_message;
+                resp.success = true;
+            }
+            OPCode::SUBSCRIBE | OPCode::PSUBSCRIBE | OPCode::UNSUBSCRIBE | OPCode::PUNSUBSCRIBE => {
+                // prevent the slave node from subscribing the channel
+                if !self.is_master {
+                    return Err(Error::msg("The server is slave"));
+                }
+                // the subscriptions belong to the session, the channels (or patterns) are separated by spaces in the key_channal
+                // the number of the subscriptions of the session is returned
+                let session_id = match _req.session_id {
+                    Some(session_id) => session_id.to_string(),
+                    None => {
+                        resp.value_message = "ERR the pub/sub commands need a session".into();
+                        return Ok(resp);
+                    }
+                };
+                let names: Vec<String> = _req.key_channal.split_whitespace().map(|name| name.to_string()).collect();
+                let pattern = opcode == OPCode::PSUBSCRIBE || opcode == OPCode::PUNSUBSCRIBE;
+                let (count, _) = match opcode == OPCode::SUBSCRIBE || opcode == OPCode::PSUBSCRIBE {
+                    true => {
+                        if names.is_empty() {
+                            resp.value_message = "ERR wrong number of arguments".into();
+                            return Ok(resp);
                         }
+                        self.pubsub.lock().unwrap().subscribe(&session_id, &names, pattern)
+                    },
+                    false => self.pubsub.lock().unwrap().unsubscribe(&session_id, &names, pattern),
+                };
+                resp.value_message = count.to_string().into();
+                resp.success = true;
+            }
+            OPCode::POLL => {
+                // the key_channal is the sequence number of the last received message, which acknowledges it and all the earlier ones
+                // the next message is returned with its sequence number in the key_channal,
+                // and the success is false if no message arrives in time
+                let session_id = match _req.session_id {
+                    Some(session_id) => session_id.to_string(),
+                    None => {
+                        resp.value_message = "ERR the pub/sub commands need a session".into();
+                        return Ok(resp);
+                    }
+                };
+                let ack = _req.key_channal.parse::<u64>().unwrap_or(0);
+                match pubsub::poll(&self.pubsub, &session_id, ack).await {
+                    Ok(Some((seq, message))) => {
+                        resp.key_channal = seq.to_string().into();
+                        resp.value_message = message.into();
+                        resp.success = true;
+                    },
+                    Ok(None) => {},
+                    Err(e) => {
+                        resp.value_message = e.to_string().into();
                     }
                 }
-                else {
+            }
+            OPCode::PUBSUB => {
+                // the subcommand is in the key_channal, and its arguments are separated by spaces in the value_message
+                // the channels of CHANNELS, and the channels along with their numbers of subscribers of NUMSUB, are separated by lines
+                let args: Vec<String> = _req.value_message.split_whitespace().map(|arg| arg.to_string()).collect();
+                let pubsub = self.pubsub.lock().unwrap();
+                let result = match _req.key_channal.to_lowercase().as_str() {
+                    "channels" if args.len() <= 1 => Some(pubsub.channels(args.first().map(|pattern| pattern.as_str())).join("\n")),
+                    "numsub" => Some(
+                        pubsub.numsub(&args)
+                            .into_iter()
+                            .map(|(channel, num)| format!("{}\n{}", channel, num))
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    ),
+                    "numpat" if args.is_empty() => Some(pubsub.numpat().to_string()),
+                    // the messages dropped for the slow subscribers and the subscribers disconnected for it
+                    "stats" if args.is_empty() => {
+                        let stats = pubsub.stats();
+                        Some(format!(
+                            "dropped_messages\n{}\ndisconnected_subscribers\n{}",
+                            stats.dropped_messages, stats.disconnected_subscribers
+                        ))
+                    },
+                    _ => None,
+                };
+                match result {
+                    Some(result) => {
+                        resp.value_message = result.into();
+                        resp.success = true;
+                    },
+                    None => {
+                        resp.value_message = "ERR unknown subcommand or wrong number of arguments for PUBSUB".into();
+                    }
+                }
+            }
+            OPCode::CONFIG => {
+                // the subcommand GET or SET is in the key_channal, and the name and the value are in the value_message
+                // GET returns the name and the value separated by a line
+                let args: Vec<&str> = _req.value_message.split_whitespace().collect();
+                let result = match (_req.key_channal.to_lowercase().as_str(), args.as_slice()) {
+                    ("get", [name]) => self.config.read().unwrap().get(name).map(|value| format!("{}\n{}", name, value)),
+                    ("set", [name, value]) if config::RUNTIME_OPTIONS.contains(name) => {
+                        self.config.write().unwrap().set(name, value).map(|_| "OK".to_string())
+                    },
+                    // an option without value is cleared, such as turning off the keyspace events
+                    ("set", [name]) if config::RUNTIME_OPTIONS.contains(name) => {
+                        self.config.write().unwrap().set(name, "").map(|_| "OK".to_string())
+                    },
+                    ("set", [name, ..]) => Err(Error::msg(format!("ERR the option {} can only be given on the command line", name))),
+                    _ => Err(Error::msg("ERR unknown subcommand or wrong number of arguments for CONFIG")),
+                };
+                if result.is_ok() && _req.key_channal.eq_ignore_ascii_case("set") {
+                    let config = self.config.read().unwrap();
+                    self.pubsub.lock().unwrap().set_limits(config.pubsub_limits.clone());
+                    self.slowlog.set_limits(config.slowlog_log_slower_than, config.slowlog_max_len);
+                }
+                match result {
+                    Ok(message) => {
+                        resp.value_message = message.into();
+                        resp.success = true;
+                    },
+                    Err(e) => {
+                        resp.value_message = e.to_string().into();
+                    }
+                }
+            }
+            OPCode::EVAL | OPCode::EVALSHA => {
+                // the script (or its sha1 for EVALSHA) is in the key_channal, and the value_message is `numkeys [key ...] [arg ...]`
+                // the type of the reply is returned in the key_channal, and the elements of an array are separated by lines
+                match self.eval(opcode, db, &_req, effects).await {
+                    Ok(reply) => {
+                        resp.key_channal = reply.kind().into();
+                        resp.value_message = reply.render().into();
+                        resp.success = true;
+                    },
+                    Err(e) => {
+                        resp.value_message = e.to_string().into();
+                    }
+                }
+            }
+            OPCode::FCALL | OPCode::FCALLRO => {
+                // prevent the slave node from calling the functions that may write, FCALL_RO is allowed
+                if !self.is_master && opcode == OPCode::FCALL {
+                    return Err(Error::msg("The server is slave"));
+                }
+                // the function name is in the key_channal, and the value_message is `numkeys [key ...] [arg ...]` as EVAL
+                match self.fcall(opcode, db, &_req, effects).await {
+                    Ok(reply) => {
+                        resp.key_channal = reply.kind().into();
+                        resp.value_message = reply.render().into();
+                        resp.success = true;
+                    },
+                    Err(e) => {
+                        resp.value_message = e.to_string().into();
+                    }
+                }
+            }
+            OPCode::FUNCTION => {
+                // the subcommand is in the key_channal, and its arguments are in the value_message
+                match self.function(&_req, effects) {
+                    Ok(message) => {
+                        resp.value_message = message.into();
+                        resp.success = true;
+                    },
+                    Err(e) => {
+                        resp.value_message = e.to_string().into();
+                    }
+                }
+            }
+            OPCode::FUNCTIONMASTER => {
+                // a change of the libraries replicated by the master node, the value_message is the line of the AOF
+                self.functions.write().unwrap().apply_log(_req.value_message.strip_prefix("FUNCTION ").unwrap_or_default());
+                effects.log.push(_req.value_message.to_string());
+                resp.value_message = "OK".into();
+                resp.success = true;
+            }
+            OPCode::SCRIPT => {
+                // the subcommand is in the key_channal, the script of LOAD and the sha1s of EXISTS are in the value_message
+                // EXISTS returns 1 or 0 for each sha1, separated by lines
+                let args = _req.value_message.trim().to_string();
+                let result = match _req.key_channal.to_lowercase().as_str() {
+                    "load" if !args.is_empty() => self.scripts.load(&args),
+                    "exists" if !args.is_empty() => Ok(
+                        args.split_whitespace()
+                            .map(|sha| (self.scripts.exists(sha) as i32).to_string())
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    ),
+                    "flush" if args.is_empty() => {
+                        self.scripts.flush();
+                        Ok("OK".to_string())
+                    },
+                    "kill" if args.is_empty() => self.scripts.kill().map(|_| "OK".to_string()),
+                    _ => Err(Error::msg("ERR unknown subcommand or wrong number of arguments for SCRIPT")),
+                };
+                match result {
+                    Ok(message) => {
+                        resp.value_message = message.into();
+                        resp.success = true;
+                    },
+                    Err(e) => {
+                        resp.value_message = e.to_string().into();
+                    }
+                }
+            }
+            OPCode::PUBLISH => {
+                // prevent the slave node from publishing the message
+                if !self.is_master {
+                    return Err(Error::msg("The server is slave"));
+                }
+                let num = self.pubsub.lock().unwrap().publish(&_req.key_channal, &_req.value_message);
+                if num > 0 {
+                    resp.success = true;
+                    resp.value_message = num.to_string().into();
+                } else {
                     resp.success = false;
                 }
             }
@@ -424,116 +1892,310 @@ impl volo_gen::volo::example::ItemService for S {
                     return Err(Error::msg("The server is slave"));
                 }
 
-                // check if the txn_id is valid
-                // if so, it means the transaction has some key watched
-                let watch_id: Option<String> = match _req.txn_id {
-                    Some(txn_id) => Some(txn_id.into()),
-                    None => None,
-                };
-
-                // define the txn_id by the txn_queue
-                // if the txn_queue is empty, the txn_id is 0
-                // else the txn_id is the max txn_id plus 1
+                // if the txn_id is given, it is returned by WATCH and the transaction keeps the watched keys
+                // otherwise a new txn_id is allocated
                 let mut txn_queue_locked = self.txn_queue.write().unwrap();
-                let txn_id = match txn_queue_locked.len() {
-                    0 => 0,
-                    _ => {
-                        let the_max = self.txn_queue.read().unwrap();
-                        let the_max = the_max.keys().max().unwrap();
-                        *the_max + 1
-                    }
+                let txn_id = match txn_id {
+                    Some(txn_id) => match find_txn(&mut txn_queue_locked, &txn_id, session_id.as_deref()) {
+                        Ok(Some(txn)) if txn.is_multi() => {
+                            resp.value_message = "ERR MULTI calls can not be nested".into();
+                            return Ok(resp);
+                        },
+                        Ok(Some(_)) => txn_id,
+                        Ok(None) => {
+                            resp.value_message = "ERR no such transaction".into();
+                            return Ok(resp);
+                        },
+                        Err(e) => {
+                            resp.value_message = e.into();
+                            return Ok(resp);
+                        },
+                    },
+                    None => {
+                        let txn_id = new_txn_id();
+                        txn_queue_locked.insert(txn_id.clone(), TxnQueue::new(session_id));
+                        txn_id
+                    },
                 };
-                txn_queue_locked.insert(txn_id, TxnQueue::new(watch_id));
+                if let Some(txn) = txn_queue_locked.get_mut(&txn_id) {
+                    txn.txn_queue = Some(VecDeque::new());
+                }
 
-                resp.key_channal = txn_id.to_string().into();
+                resp.key_channal = txn_id.into();
                 resp.value_message = "OK".into();
                 resp.success = true;
             }
             OPCode::EXEC => {
-                // prevent the slave node from executing the transaction
+                // EXEC is never queued, and get_item runs it by exec()
+            }
+            OPCode::DISCARD => {
+                // drop the queued requests, and stop watching the keys of the transaction
+                match self.take_multi(&_req, "DISCARD") {
+                    Ok(_) => {
+                        resp.value_message = "OK".into();
+                        resp.success = true;
+                    },
+                    Err(e) => {
+                        resp.value_message = e.into();
+                    }
+                }
+            }
+            OPCode::WATCH => {
+                // prevent the slave node from watching the key
                 if !self.is_master {
                     return Err(Error::msg("The server is slave"));
                 }
 
-                // message is used to collect the messages of each request in the transaction
-                let mut message = String::new();
-                resp.success = true;
-
-                // check if the txn_id is valid
-                if _req.txn_id.is_none() {
-                    resp.success = false;
-                    resp.value_message = "The txn_id is none".into();
+                // the keys are separated by spaces in the key_channal
+                // the first WATCH allocates a txn_id, and the later ones with the txn_id add keys to the same transaction
+                let keys: Vec<String> = _req.key_channal.split_whitespace().map(|key| key.to_string()).collect();
+                if keys.is_empty() {
+                    resp.value_message = "ERR wrong number of arguments for WATCH".into();
                     return Ok(resp);
                 }
-                let txn_id = _req.txn_id.unwrap().parse::<usize>().unwrap();
-                let mut txn_queue_todo = self.txn_queue.write().unwrap().remove(&txn_id).unwrap();
-                let mut can_run = true;
-
-                // check if the watch key has been changed
-                if let Some(watch_id) = txn_queue_todo.get_watch_id() {
-                    let watch_key = watch_id.split("_").collect::<Vec<_>>()[0].to_string();
-                    can_run = self.watch_keys.write().unwrap().get_mut(&watch_key).unwrap().remove(&watch_id);
+                let mut txn_queue_locked = self.txn_queue.write().unwrap();
+                let (txn_id, txn) = match txn_id {
+                    Some(txn_id) => match find_txn(&mut txn_queue_locked, &txn_id, session_id.as_deref()) {
+                        Ok(Some(txn)) if txn.is_multi() => {
+                            resp.value_message = "ERR WATCH inside MULTI is not allowed".into();
+                            return Ok(resp);
+                        },
+                        Ok(Some(txn)) => (txn_id, txn),
+                        Ok(None) => {
+                            resp.value_message = "ERR no such transaction".into();
+                            return Ok(resp);
+                        },
+                        Err(e) => {
+                            resp.value_message = e.into();
+                            return Ok(resp);
+                        },
+                    },
+                    None => {
+                        let txn_id = new_txn_id();
+                        let txn = txn_queue_locked.entry(txn_id.clone()).or_insert(TxnQueue::new(session_id));
+                        (txn_id, txn)
+                    },
+                };
+                let mut watch_keys = self.watch_keys.write().unwrap();
+                for key in keys {
+                    watch_keys.entry((db, key.clone())).or_default().insert(txn_id.clone());
+                    txn.watched.insert((db, key));
                 }
 
-                // execute the transaction
-                match can_run {
-                    true => {
-                        while let Some(req) = txn_queue_todo.pop() {
-                            let result = self.get_item(req).await;
-                            match result {
-                                Ok(info) => {
-                                    message = format!("{}\n{}", message, info.value_message).into();
-                                },
-                                Err(e) => {
-                                    message = format!("{}\n{}", message, e.to_string()).into();
-                                    resp.success = false;
-                                    break;
-                                }
-                            }
-                        }
+                resp.key_channal = txn_id.into();
+                resp.value_message = "OK".into();
+                resp.success = true;
+            }
+            OPCode::UNWATCH => {
+                // forget the watched keys, and drop the transaction that has not started MULTI
+                // inside MULTI it is queued like the other requests, which does nothing on EXEC
+                if let Some(txn_id) = txn_id {
+                    // nothing to forget if the transaction has expired
+                    let mut txn_queue_locked = self.txn_queue.write().unwrap();
+                    if let Err(e) = find_txn(&mut txn_queue_locked, &txn_id, session_id.as_deref()) {
+                        resp.value_message = e.into();
+                        return Ok(resp);
                     }
-                    false => {
-                        message = "The watch key has been changed".into();
+                    if let Some(mut txn) = txn_queue_locked.remove(&txn_id) {
+                        unwatch(&mut self.watch_keys.write().unwrap(), &txn_id, &mut txn);
+                    }
+                }
+                resp.value_message = "OK".into();
+                resp.success = true;
+            }
+            OPCode::SCAN => {
+                // the cursor is in the key_channal, the options are in the value_message
+                // the next cursor is returned in the key_channal, and the keys are separated by lines
+                let cursor = match _req.key_channal.parse::<u64>() {
+                    Ok(cursor) => cursor,
+                    Err(_) => {
+                        resp.value_message = "ERR invalid cursor".into();
+                        return Ok(resp);
+                    }
+                };
+                match ScanArgs::parse(&_req.value_message) {
+                    Ok(args) => {
+                        let (next, keys) = self.scan(db, cursor, &args);
+                        resp.key_channal = next.to_string().into();
+                        resp.value_message = keys.join("\n").into();
+                        resp.success = true;
+                    },
+                    Err(e) => {
+                        resp.value_message = e.to_string().into();
+                    }
+                }
+            }
+            OPCode::KEYS => {
+                let pattern = _req.key_channal.to_string();
+                let mut keys: Vec<String> = kv_pairs
+                    .keys(keyspace::now_ms())
+                    .into_iter()
+                    .filter(|key| glob_match(&pattern, key))
+                    .collect();
+                keys.sort();
+                resp.value_message = keys.join("\n").into();
+                resp.success = true;
+            }
+            OPCode::DBSIZE => {
+                resp.value_message = kv_pairs.len().to_string().into();
+                resp.success = true;
+            }
+            OPCode::RANDOMKEY => {
+                match kv_pairs.random_key(keyspace::now_ms()) {
+                    Some(key) => {
+                        resp.value_message = key.into();
+                        resp.success = true;
+                    },
+                    None => {
+                        resp.value_message = "(nil)".into();
                         resp.success = false;
                     }
                 }
-                message = message.trim().into();
-                resp.value_message = message.clone().into();
             }
-            OPCode::WATCH => {
-                // prevent the slave node from watching the key
+            OPCode::SELECT => {
+                // the index of the database is in the key_channal
+                // the session keeps the selected database and sends it with its requests, so it is only checked here
+                match parse_db(&_req.key_channal, self.dbs.len()) {
+                    Ok(_) => {
+                        resp.value_message = "OK".into();
+                        resp.success = true;
+                    },
+                    Err(e) => {
+                        resp.value_message = e.to_string().into();
+                    }
+                }
+            }
+            OPCode::FLUSHDB => {
+                // prevent the slave node from flushing the keys
                 if !self.is_master {
                     return Err(Error::msg("The server is slave"));
                 }
-
-                // determine the watch_id
-                // for example, to watch key "demo",
-                // if no other watcher, the watch_id is "demo_0"
-                // if there are already some watchers, the watch_id is "demo_n" where n is the max number plus 1
-                let key = _req.key_channal.clone().to_string();
-                let is_contain = self.watch_keys.read().unwrap().contains_key(&key) && self.watch_keys.read().unwrap().get(&key).unwrap().len() > 0;
-                let watch_id = match is_contain {
-                    true => {
-                        let the_max = self.watch_keys.read().unwrap();
-                        let the_max = the_max.get(&key).unwrap().iter().max().unwrap();
-                        let the_max: Vec<&str> = the_max.split("_").collect();
-                        let the_max = the_max[1].parse::<usize>().unwrap();
-                        format!("{}_{}", key, the_max + 1)
+                effects.append(db, "FLUSHDB".to_string());
+                self.touch_dbs(&[db]);
+                kv_pairs.clear();
+                resp.value_message = "OK".into();
+                resp.success = true;
+            }
+            OPCode::SWAPDB => {
+                // prevent the slave node from swapping the databases
+                if !self.is_master {
+                    return Err(Error::msg("The server is slave"));
+                }
+                // the indexes of the two databases are in the key_channal and the value_message
+                let (a, b) = match (parse_db(&_req.key_channal, self.dbs.len()), parse_db(&_req.value_message, self.dbs.len())) {
+                    (Ok(a), Ok(b)) => (a, b),
+                    (Err(e), _) | (_, Err(e)) => {
+                        resp.value_message = e.to_string().into();
+                        return Ok(resp);
                     }
-                    false => format!("{}_0", key),
                 };
+                effects.log.push(format!("SWAPDB {} {}", a, b));
+                self.touch_dbs(&[a, b]);
+                swap_dbs(&self.dbs, a, b);
+                resp.value_message = "OK".into();
+                resp.success = true;
+            }
+            OPCode::MOVE => {
+                // prevent the slave node from moving the key
+                if !self.is_master {
+                    return Err(Error::msg("The server is slave"));
+                }
+                // the key is in the key_channal, and the index of the database to move it to is in the value_message
+                // 1 is returned if the key is moved, and 0 if there is no such key or the other database has the key already
+                let key = _req.key_channal.to_string();
+                let to = match parse_db(&_req.value_message, self.dbs.len()) {
+                    Ok(to) if to == db => {
+                        resp.value_message = "ERR source and destination objects are the same".into();
+                        return Ok(resp);
+                    },
+                    Ok(to) => to,
+                    Err(e) => {
+                        resp.value_message = e.to_string().into();
+                        return Ok(resp);
+                    }
+                };
+                self.expire_if_needed(db, &key, effects);
+                self.expire_if_needed(to, &key, effects);
+                let now = keyspace::now_ms();
+                resp.value_message = "0".into();
+                resp.success = true;
+                let (Some(value), false) = (kv_pairs.get(&key, now), self.dbs[to].contains(&key, now)) else {
+                    return Ok(resp);
+                };
+                // the key keeps its expire time in the other database
+                let expire_at = kv_pairs.expire_at(&key, now).flatten();
+                kv_pairs.remove(&key);
+                effects.append(db, format!("DEL {}", key));
+                self.dbs[to].insert(key.clone(), value.clone(), now);
+                effects.append(to, format!("SET {} {}", key, value));
+                if let Some(expire_at) = expire_at {
+                    self.dbs[to].set_expire(&key, Some(expire_at));
+                    effects.append(to, format!("EXPIREAT {} {}", key, expire_at));
+                }
+                self.touch(db, &key);
+                self.touch(to, &key);
+                self.notify_keyspace_event(db, 'g', "move_from", &key);
+                self.notify_keyspace_event(to, 'g', "move_to", &key);
+                resp.value_message = "1".into();
+            }
+            OPCode::FLUSHALL | OPCode::FLUSHMASTER => {
+                // prevent the slave node from flushing the keys
+                if !self.is_master && opcode == OPCode::FLUSHALL {
+                    return Err(Error::msg("The server is slave"));
+                }
+                effects.log.push("FLUSHALL".to_string());
+                self.flush_all();
+                resp.value_message = "OK".into();
+                resp.success = true;
 
-                self.watch_keys
-                    .write()
-                    .unwrap()
-                    .entry(key.clone())
-                    .or_insert(HashSet::new())
-                    .insert(watch_id.clone());
-
-                resp.key_channal = watch_id.into();
+                effects.replicas.push(volo_gen::volo::example::GetItemRequest {
+                    opcode: 102,    // set the opcode to 102, which is FLUSHMASTER
+                    key_channal: " ".into(),
+                    value_message: " ".into(),
+                    txn_id: None,
+                    session_id: None,
+                    db: None,
+                    auth: None,
+                });
+            }
+            OPCode::EXECMASTER => {
+                // a transaction replicated by the master node, the AOF lines are separated by lines in the value_message
+                // it is applied and logged as a whole, under the keyspace lock held exclusively by get_item
+                // the block selects the databases of its lines by itself, so it starts from the database 0
+                let log: Vec<String> = _req.value_message.lines().map(|line| line.to_string()).collect();
+                {
+                    let mut functions = self.functions.write().unwrap();
+                    let mut db = 0;
+                    for line in &log {
+                        apply_log(&self.dbs, &mut db, &mut functions, line);
+                    }
+                }
+                self.commit_block(Effects { log, ..Effects::default() }).await;
                 resp.value_message = "OK".into();
                 resp.success = true;
             }
+            OPCode::TOPOLOGY | OPCode::ADDSLAVE | OPCode::DELSLAVE | OPCode::REPLACEMASTER => {
+                return Err(Error::msg("The topology commands are only supported by the proxy"));
+            }
+            OPCode::AUTH | OPCode::ACL => {
+                return Err(Error::msg("AUTH and ACL are handled by the AclLayer"));
+            }
+            OPCode::FILTER => {
+                return Err(Error::msg("FILTER is handled by the FilterLayer"));
+            }
+            OPCode::SLOWLOG => {
+                return Err(Error::msg("SLOWLOG is handled by the SlowLogLayer"));
+            }
+            OPCode::INFO => {
+                // the section is in the key_channal, all the sections are reported without it, an unknown section reports nothing
+                let section = _req.key_channal.trim().to_lowercase();
+                let sections: Vec<&str> = match section.as_str() {
+                    "" | "all" | "default" | "everything" => INFO_SECTIONS.to_vec(),
+                    section => INFO_SECTIONS.iter().copied().filter(|name| *name == section).collect(),
+                };
+                resp.value_message = self.info(&sections).await.into();
+                resp.success = true;
+            }
             OPCode::NOTDEFINED => {
                 tracing::warn!("Invalic opcode");
             }
@@ -542,6 +2204,77 @@ impl volo_gen::volo::example::ItemService for S {
     }
 }
 
+#[volo::async_trait]
+impl volo_gen::volo::example::ItemService for S {
+    async fn get_item(&self, _req: volo_gen::volo::example::GetItemRequest) -> ::core::result::Result<volo_gen::volo::example::GetItemResponse, ::volo_thrift::AnyhowError>{
+        let opcode = OPCode::from(_req.opcode);
+        if opcode == OPCode::EXEC {
+            return self.exec(_req).await;
+        }
+        // the blocking requests never touch the keys, and must not hold off EXEC while waiting
+        // SCRIPT does not touch the keys either, and SCRIPT KILL (or FUNCTION KILL) must get through while a script holds the keyspace
+        if opcode.is_blocking() || opcode == OPCode::SCRIPT || (opcode == OPCode::FUNCTION && _req.key_channal.eq_ignore_ascii_case("kill")) {
+            return self.execute(_req, &mut Effects::default()).await;
+        }
+        // a script running for too long holds off the clients, except the writes replicated by the master
+        let time_limit = self.config.read().unwrap().lua_time_limit;
+        if !opcode.is_replicated() && self.scripts.is_busy(time_limit) {
+            return Ok(volo_gen::volo::example::GetItemResponse {
+                opcode: _req.opcode,
+                key_channal: _req.key_channal,
+                value_message: "BUSY Redis is busy running a script. You can only call SCRIPT KILL.".into(),
+                success: false,
+            });
+        }
+        // a script runs atomically like a transaction, and its writes are logged and replicated as a block
+        // MOVE, SWAPDB and FLUSHDB only produce log lines, so they are replicated as a block as well
+        // this holds after WATCH too, and a request queued into MULTI only takes the lock to be queued, committing nothing
+        let is_script = matches!(opcode, OPCode::EVAL | OPCode::EVALSHA | OPCode::FCALL | OPCode::FCALLRO);
+        if is_script || opcode.is_replicated_as_block() {
+            let _exclusive = self.keyspace_lock.write().await;
+            if let Some(resp) = self.check_memory(&_req).await {
+                return Ok(resp);
+            }
+            let mut effects = Effects::default();
+            let resp = self.execute(_req, &mut effects).await?;
+            self.commit_block(effects).await;
+            return Ok(resp);
+        }
+        // the keys are locked shard by shard, so a transaction replicated by the master is applied exclusively to be seen as a whole
+        if opcode == OPCode::EXECMASTER {
+            let _exclusive = self.keyspace_lock.write().await;
+            return self.execute(_req, &mut Effects::default()).await;
+        }
+        // FLUSHALL must not be logged between a write to a key and the log line of that write, so it runs exclusively too
+        if matches!(opcode, OPCode::FLUSHALL | OPCode::FLUSHMASTER) {
+            let _exclusive = self.keyspace_lock.write().await;
+            let mut effects = Effects::default();
+            let resp = self.execute(_req, &mut effects).await?;
+            self.commit(effects).await;
+            return Ok(resp);
+        }
+        let _shared = self.keyspace_lock.read().await;
+        if let Some(resp) = self.check_memory(&_req).await {
+            return Ok(resp);
+        }
+        // the writes to a key are applied, logged and replicated under its lock one at a time,
+        // so that the AOF and the slaves see them in the order they were applied to the shard
+        let key_lock = match self.select(_req.db) {
+            Ok(db) if opcode.is_keyed() => Some(self.key_lock(db, &_req.key_channal)),
+            _ => None,
+        };
+        let (_key_write, _key_read) = match key_lock {
+            Some(lock) if opcode.writes_key() => (Some(lock.write().await), None),
+            Some(lock) => (None, Some(lock.read().await)),
+            None => (None, None),
+        };
+        let mut effects = Effects::default();
+        let resp = self.execute(_req, &mut effects).await?;
+        self.commit(effects).await;
+        Ok(resp)
+    }
+}
+
 pub struct LogLayer;
 
 impl<S> volo::Layer<S> for LogLayer {
@@ -568,23 +2301,64 @@ where
     async fn call(&self, cx: &mut Cx, req: Req) -> Result<S::Response, S::Error> {
         let now = std::time::Instant::now();
         tracing::debug!("Received request {:?}", &req);
-        // 中间件希望过滤掉kv对中涉及的不文明词汇，由于不文明词汇的判断比较复杂，此处演示过滤词汇"傻逼"
-        let info: Vec<char> = format!("{req:?}").chars().collect();
-        let mut can_send: bool = true;
-        for i in 0..(info.len() - 1) {
-            if info[i] == '傻' && info[i + 1] == '逼' {
-                can_send = false;
-                break;
-            }
-        }
-        if can_send {
-            let resp = self.0.call(cx, req).await;
-            tracing::debug!("Sent response {:?}", &resp);
-            tracing::info!("Request took {}ms", now.elapsed().as_millis());    
-            return resp;
-        }
-        // panic!("命令中有敏感词“傻逼");
-        Err(S::Error::from(Error::msg("命令中有敏感词'傻逼'")))
+        let resp = self.0.call(cx, req).await;
+        tracing::debug!("Sent response {:?}", &resp);
+        tracing::info!("Request took {}ms", now.elapsed().as_millis());
+        resp
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use volo_gen::volo::example::{GetItemRequest, ItemService};
+
+    use super::*;
+    use crate::test_util::request;
+
+    // a node with an empty AOF in the temporary directory, a master if it has slaves
+    async fn node(name: &str, slave_addr: Vec<SocketAddr>, config: Config) -> S {
+        let path = std::env::temp_dir().join(format!("mini-redis-{}-{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        S::new(slave_addr, path.to_str().unwrap(), config).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn scripts_call_the_commands_as_their_user() {
+        let aclfile = std::env::temp_dir().join(format!("mini-redis-scripts-{}.acl", std::process::id()));
+        std::fs::write(&aclfile, "user reader on >secret ~allowed* +@all\n").unwrap();
+        let config = Config { aclfile: Some(aclfile.to_str().unwrap().to_string()), ..Config::default() };
+        let node = node("scripts", Vec::new(), config).await;
+        std::fs::remove_file(&aclfile).unwrap();
+
+        let eval = |script: &str| GetItemRequest { auth: Some("reader secret".into()), ..request(OPCode::EVAL, script, "0") };
+        let resp = node.get_item(eval("return redis.call('get', 'allowed')")).await.unwrap();
+        assert!(resp.success, "{}", resp.value_message);
+        // the key is not given in KEYS, so only the check of the called command denies it
+        let resp = node.get_item(eval("return redis.call('get', 'denied')")).await.unwrap();
+        assert!(!resp.success);
+        assert!(resp.value_message.contains("NOPERM"), "{}", resp.value_message);
+        let resp = node.get_item(eval("return redis.pcall('get', 'denied')['err']")).await.unwrap();
+        assert!(resp.value_message.starts_with("NOPERM"), "{}", resp.value_message);
+    }
+
+    #[tokio::test]
+    async fn info_reports_the_writes_lost_on_a_slave_that_is_down() {
+        // nothing listens on the port, so each write sent to the slave fails
+        let slave: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let node = node("slave-down", vec![slave], Config::default()).await;
+        let resp = node.get_item(request(OPCode::SET, "key", "value")).await.unwrap();
+        assert!(resp.success, "{}", resp.value_message);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let info = loop {
+            let info = node.get_item(request(OPCode::INFO, "replication", " ")).await.unwrap().value_message.to_string();
+            if info.contains("lost=1") || Instant::now() > deadline {
+                break info;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        };
+        assert!(info.contains("slave0:ip=127.0.0.1,port=1,offset=0,lag=1,lost=1"), "{}", info);
+        assert!(info.contains("master_repl_offset:1"), "{}", info);
+    }
+}
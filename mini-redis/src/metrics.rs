@@ -0,0 +1,356 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, Weak, atomic::{AtomicU64, Ordering}},
+    time::{Duration, Instant},
+};
+use anyhow::Error;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use volo_gen::volo::example::{ItemServiceGetItemResultSend, ItemServiceRequestRecv, ItemServiceResponseSend};
+
+use crate::acl::COMMANDS;
+
+// the upper bounds of the buckets of the latency histograms, in seconds
+const BUCKETS: &[f64] = &[0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+// a scrape sending no complete request in time is dropped
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+// the longest request of a scrape, which only needs the request line
+const MAX_SCRAPE_REQUEST: usize = 8192;
+// the interval the instantaneous ops per second are measured over
+const OPS_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+// a latency histogram, the buckets are cumulated when rendered
+pub struct Histogram {
+    buckets: Vec<AtomicU64>,    // the observations within each bound and above the previous one
+    sum_us: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram {
+            buckets: BUCKETS.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum_us: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_us.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut Exposition, name: &str, labels: &[(&str, &str)]) {
+        let mut cumulated = 0;
+        for (bound, bucket) in BUCKETS.iter().zip(self.buckets.iter()) {
+            cumulated += bucket.load(Ordering::Relaxed);
+            let le = bound.to_string();
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", le.as_str()));
+            out.sample(&format!("{}_bucket", name), &bucket_labels, cumulated);
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let mut bucket_labels = labels.to_vec();
+        bucket_labels.push(("le", "+Inf"));
+        out.sample(&format!("{}_bucket", name), &bucket_labels, count);
+        out.sample(&format!("{}_sum", name), labels, self.sum_us.load(Ordering::Relaxed) as f64 / 1e6);
+        out.sample(&format!("{}_count", name), labels, count);
+    }
+}
+
+// the text exposition format of prometheus
+#[derive(Default)]
+pub struct Exposition(String);
+
+impl Exposition {
+    pub fn header(&mut self, name: &str, kind: &str, help: &str) {
+        self.0.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.0.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+                .collect();
+            self.0.push_str(&format!("{{{}}}", labels.join(",")));
+        }
+        self.0.push_str(&format!(" {}\n", value));
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+#[derive(Default)]
+struct CommandStats {
+    calls: AtomicU64,
+    errors: AtomicU64,
+    latency: Histogram,
+}
+
+// the writes sent to a slave, which the sync task of the slave counts
+#[derive(Default)]
+pub struct ReplicaStats {
+    pub synced: AtomicU64,      // the writes the slave has applied
+    pub lost: AtomicU64,        // the writes failed on the slave or skipped as it lagged behind, which it never receives
+}
+
+// Metrics keeps the counters updated as the node runs, the gauges such as the number of the keys are read on each scrape
+// the commands are counted in a table built once, so that recording a request takes no lock
+pub struct Metrics {
+    started: Instant,
+    commands: Vec<(&'static str, CommandStats)>,
+    index: HashMap<i32, usize>,                                 // the opcode -> the position in commands
+    pub fsync: Histogram,                                       // the latency of the fsyncs of the AOF
+    aof_size: AtomicU64,
+    replication_offset: AtomicU64,                              // the writes sent to the slaves
+    replicas: Mutex<BTreeMap<SocketAddr, Arc<ReplicaStats>>>,   // the writes each slave has received or lost
+    ops_per_sec: AtomicU64,                                     // the requests in the last OPS_SAMPLE_INTERVAL
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        let mut commands: Vec<(&'static str, CommandStats)> = COMMANDS.iter().map(|(_, name, _)| (*name, CommandStats::default())).collect();
+        let index = COMMANDS.iter().enumerate().map(|(position, (opcode, _, _))| (*opcode, position)).collect();
+        commands.push(("unknown", CommandStats::default()));
+        Metrics {
+            started: Instant::now(),
+            commands,
+            index,
+            fsync: Histogram::default(),
+            aof_size: AtomicU64::new(0),
+            replication_offset: AtomicU64::new(0),
+            replicas: Mutex::new(BTreeMap::new()),
+            ops_per_sec: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn record(&self, opcode: i32, elapsed: Duration, error: bool) {
+        let position = self.index.get(&opcode).copied().unwrap_or(self.commands.len() - 1);
+        let stats = &self.commands[position].1;
+        stats.calls.fetch_add(1, Ordering::Relaxed);
+        if error {
+            stats.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        stats.latency.observe(elapsed);
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn total_commands(&self) -> u64 {
+        self.commands.iter().map(|(_, stats)| stats.calls.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn total_errors(&self) -> u64 {
+        self.commands.iter().map(|(_, stats)| stats.errors.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn ops_per_sec(&self) -> u64 {
+        self.ops_per_sec.load(Ordering::Relaxed)
+    }
+
+    pub fn aof_size(&self) -> u64 {
+        self.aof_size.load(Ordering::Relaxed)
+    }
+
+    pub fn set_aof_size(&self, size: u64) {
+        self.aof_size.store(size, Ordering::Relaxed);
+    }
+
+    pub fn add_aof_size(&self, size: u64) {
+        self.aof_size.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub fn replicated(&self) {
+        self.replication_offset.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // the counters of the writes sent to the slave, the writes before the slave is added count as synced
+    pub fn add_replica(&self, addr: SocketAddr) -> Arc<ReplicaStats> {
+        let stats = Arc::new(ReplicaStats::default());
+        stats.synced.store(self.replication_offset.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.replicas.lock().unwrap().insert(addr, stats.clone());
+        stats
+    }
+
+    pub fn replication_offset(&self) -> u64 {
+        self.replication_offset.load(Ordering::Relaxed)
+    }
+
+    // the address of each slave along with the writes it has received and the writes it has lost
+    pub fn replicas(&self) -> Vec<(SocketAddr, u64, u64)> {
+        self.replicas
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(addr, stats)| (*addr, stats.synced.load(Ordering::Relaxed), stats.lost.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    pub fn render(&self, out: &mut Exposition) {
+        out.header("mini_redis_commands_total", "counter", "The requests of each command");
+        for (name, stats) in self.commands.iter() {
+            out.sample("mini_redis_commands_total", &[("command", *name)], stats.calls.load(Ordering::Relaxed));
+        }
+        out.header("mini_redis_command_errors_total", "counter", "The requests of each command answered with an error");
+        for (name, stats) in self.commands.iter() {
+            out.sample("mini_redis_command_errors_total", &[("command", *name)], stats.errors.load(Ordering::Relaxed));
+        }
+        out.header("mini_redis_command_duration_seconds", "histogram", "The latency of the requests of each command");
+        for (name, stats) in self.commands.iter().filter(|(_, stats)| stats.calls.load(Ordering::Relaxed) > 0) {
+            stats.latency.render(out, "mini_redis_command_duration_seconds", &[("command", *name)]);
+        }
+        out.header("mini_redis_aof_size_bytes", "gauge", "The size of the AOF");
+        out.sample("mini_redis_aof_size_bytes", &[], self.aof_size.load(Ordering::Relaxed));
+        out.header("mini_redis_aof_fsync_duration_seconds", "histogram", "The latency of the fsyncs of the AOF");
+        self.fsync.render(out, "mini_redis_aof_fsync_duration_seconds", &[]);
+        let offset = self.replication_offset();
+        out.header("mini_redis_replication_offset", "counter", "The writes the master has sent to the slaves");
+        out.sample("mini_redis_replication_offset", &[], offset);
+        let replicas = self.replicas();
+        out.header("mini_redis_replication_lag", "gauge", "The writes each slave has not received, including the lost ones");
+        for (addr, synced, _) in replicas.iter() {
+            out.sample("mini_redis_replication_lag", &[("slave", addr.to_string().as_str())], offset.saturating_sub(*synced));
+        }
+        out.header("mini_redis_replication_lost_total", "counter", "The writes each slave failed or skipped as it lagged behind");
+        for (addr, _, lost) in replicas.iter() {
+            out.sample("mini_redis_replication_lost_total", &[("slave", addr.to_string().as_str())], *lost);
+        }
+    }
+}
+
+// measure the requests of each interval, which INFO reports as the instantaneous ops per second
+pub fn start_ops_sampler(metrics: Weak<Metrics>) {
+    tokio::spawn(async move {
+        let mut last = 0;
+        loop {
+            tokio::time::sleep(OPS_SAMPLE_INTERVAL).await;
+            let Some(metrics) = metrics.upgrade() else {
+                break;
+            };
+            let total = metrics.total_commands();
+            let ops = (total - last) as f64 / OPS_SAMPLE_INTERVAL.as_secs_f64();
+            metrics.ops_per_sec.store(ops as u64, Ordering::Relaxed);
+            last = total;
+        }
+    });
+}
+
+// MetricsLayer counts the requests of each command along with their latency and errors
+// it is put in front of the AclLayer, so that the refused requests are counted as well
+pub struct MetricsLayer(pub Arc<Metrics>);
+
+impl<S> volo::Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        MetricsService { inner, metrics: self.0 }
+    }
+}
+
+#[derive(Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+#[volo::service]
+impl<S> volo::Service<volo_thrift::context::ServerContext, ItemServiceRequestRecv> for MetricsService<S>
+where
+    S: volo::Service<volo_thrift::context::ServerContext, ItemServiceRequestRecv, Response = ItemServiceResponseSend, Error = Error>
+        + Send
+        + Sync
+        + 'static,
+{
+    async fn call(
+        &self,
+        cx: &mut volo_thrift::context::ServerContext,
+        req: ItemServiceRequestRecv,
+    ) -> Result<ItemServiceResponseSend, Error> {
+        let ItemServiceRequestRecv::GetItem(args) = &req;
+        let opcode = args.req.opcode;
+        let now = Instant::now();
+        let result = self.inner.call(cx, req).await;
+        // (nil) answers a missing key, which is not an error
+        let error = match &result {
+            Ok(ItemServiceResponseSend::GetItem(ItemServiceGetItemResultSend::Ok(resp))) => !resp.success && resp.value_message != "(nil)",
+            _ => true,
+        };
+        self.metrics.record(opcode, now.elapsed(), error);
+        result
+    }
+}
+
+// serve the metrics rendered by `render` over http on GET /metrics
+pub async fn serve_metrics<F>(addr: SocketAddr, render: F) -> io::Result<()>
+where
+    F: Fn() -> String + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("Serving metrics on http://{}/metrics", addr);
+    let render = Arc::new(render);
+    loop {
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::warn!("Failed to accept a scrape on {}: {}", addr, e);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                continue;
+            },
+        };
+        let render = render.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(&mut stream, &*render).await {
+                tracing::debug!("Failed to serve the scrape of {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+// only the request line is looked at, and the connection is closed after the response
+async fn respond(stream: &mut TcpStream, render: &(dyn Fn() -> String + Send + Sync)) -> io::Result<()> {
+    let mut request = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < MAX_SCRAPE_REQUEST {
+        let read = tokio::time::timeout(SCRAPE_TIMEOUT, stream.read(&mut chunk))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "the scrape timed out"))??;
+        if read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&chunk[..read]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next().and_then(|target| target.split('?').next())) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render()),
+        (Some("GET"), _) => ("404 Not Found", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
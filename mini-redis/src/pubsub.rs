@@ -0,0 +1,365 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Mutex, Weak},
+    time::{Duration, Instant},
+};
+use anyhow::Error;
+use tokio::sync::watch;
+
+use crate::glob_match;
+
+// a subscriber that has not polled for so long is considered gone, and its subscriptions are dropped
+const SUBSCRIBER_IDLE: Duration = Duration::from_secs(60);
+// the interval to look for the idle subscribers
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+// the longest time a poll waits for a message, the client polls again if nothing arrives
+pub const POLL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// the limits of the pending messages of a subscriber, in bytes, 0 means no limit
+// the messages over the hard limit are dropped, and the subscriber is told how many of them are lost
+// a subscriber staying over the soft limit for longer than soft_duration is disconnected
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BufferLimits {
+    pub hard: usize,
+    pub soft: usize,
+    pub soft_duration: Duration,
+}
+
+impl Default for BufferLimits {
+    fn default() -> BufferLimits {
+        BufferLimits {
+            hard: 32 * 1024 * 1024,
+            soft: 8 * 1024 * 1024,
+            soft_duration: Duration::from_secs(60),
+        }
+    }
+}
+
+// the counters of the slow subscribers since the server started
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PubSubStats {
+    pub dropped_messages: u64,
+    pub disconnected_subscribers: u64,
+}
+
+// an entry in the queue of a subscriber
+enum Pending {
+    Message(String),
+    Dropped(u64),       // the number of the messages dropped at this point, sent as "dropped\n<count>"
+}
+
+// the subscriptions and the pending messages of a session
+struct Subscriber {
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+    queue: VecDeque<(u64, Pending)>,    // the messages not acknowledged yet, with their sequence numbers
+    bytes: usize,                       // the size of the queued messages
+    over_soft_since: Option<Instant>,   // when the queue went over the soft limit
+    delivered: u64,                     // the sequence number of the last message returned by poll
+    seq_tx: watch::Sender<u64>,         // the sequence number of the last queued message, wakes up the waiting polls
+    last_poll: Instant,
+}
+
+impl Subscriber {
+    fn new() -> Subscriber {
+        Subscriber {
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            queue: VecDeque::new(),
+            bytes: 0,
+            over_soft_since: None,
+            delivered: 0,
+            seq_tx: watch::channel(0).0,
+            last_poll: Instant::now(),
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    fn enqueue(&mut self, seq: u64, pending: Pending) {
+        if let Pending::Message(message) = &pending {
+            self.bytes += message.len();
+        }
+        self.queue.push_back((seq, pending));
+        self.seq_tx.send_replace(seq);
+    }
+
+    // add the dropped messages to the notice at the end of the queue,
+    // or queue a new notice if there is none or the client has already seen it
+    fn drop_messages(&mut self, next_seq: &mut u64, count: u64) {
+        if let Some((seq, Pending::Dropped(num))) = self.queue.back_mut() {
+            if *seq > self.delivered {
+                *num += count;
+                return;
+            }
+        }
+        *next_seq += 1;
+        self.enqueue(*next_seq, Pending::Dropped(count));
+    }
+
+    fn update_soft_limit(&mut self, limits: &BufferLimits) {
+        match limits.soft > 0 && self.bytes > limits.soft {
+            true => {
+                self.over_soft_since.get_or_insert_with(Instant::now);
+            },
+            false => self.over_soft_since = None,
+        }
+    }
+
+    fn is_slow(&self, limits: &BufferLimits) -> bool {
+        self.over_soft_since.is_some_and(|since| since.elapsed() > limits.soft_duration)
+    }
+}
+
+// the result of polling the messages of a session
+pub enum Poll {
+    Message(u64, String),
+    Wait(watch::Receiver<u64>),     // no message yet, poll again when the receiver changes
+    NotSubscribed,
+    Disconnected,                   // the session was dropped for staying over the buffer limit
+}
+
+// the subscriptions of all the sessions
+// the messages are queued per session until the session acknowledges them, so none is lost between two polls
+// a message is encoded as "message\n<channel>\n<payload>" or "pmessage\n<pattern>\n<channel>\n<payload>",
+// and the messages dropped for a slow subscriber are noticed as "dropped\n<count>"
+#[derive(Default)]
+pub struct PubSub {
+    channels: HashMap<String, HashSet<String>>,     // channel -> the subscribed sessions
+    patterns: HashMap<String, HashSet<String>>,     // pattern -> the subscribed sessions
+    subscribers: HashMap<String, Subscriber>,
+    next_seq: u64,                                  // shared by all the sessions, so that a stale ack never covers new messages
+    limits: BufferLimits,
+    disconnected: HashMap<String, Instant>,         // the slow sessions dropped recently, told on their next poll
+    stats: PubSubStats,
+}
+
+impl PubSub {
+    pub fn new(limits: BufferLimits) -> PubSub {
+        PubSub {
+            limits,
+            ..PubSub::default()
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: BufferLimits) {
+        self.limits = limits;
+    }
+
+    pub fn stats(&self) -> PubSubStats {
+        self.stats
+    }
+
+    // subscribe the session to the channels (or patterns), return the number of its subscriptions
+    // and the channels (or patterns) that had no subscriber before
+    pub fn subscribe(&mut self, session: &str, names: &[String], pattern: bool) -> (usize, Vec<String>) {
+        self.disconnected.remove(session);
+        let subscriber = self.subscribers.entry(session.to_string()).or_insert_with(Subscriber::new);
+        let (mine, all) = match pattern {
+            true => (&mut subscriber.patterns, &mut self.patterns),
+            false => (&mut subscriber.channels, &mut self.channels),
+        };
+        let mut created = Vec::new();
+        for name in names {
+            mine.insert(name.clone());
+            all.entry(name.clone())
+                .or_insert_with(|| {
+                    created.push(name.clone());
+                    HashSet::new()
+                })
+                .insert(session.to_string());
+        }
+        (subscriber.count(), created)
+    }
+
+    // unsubscribe the session from the channels (or patterns), or from all of them if none is given,
+    // return the number of its remaining subscriptions and the channels (or patterns) left without subscriber
+    // the session is dropped along with its pending messages when it has no subscription left
+    pub fn unsubscribe(&mut self, session: &str, names: &[String], pattern: bool) -> (usize, Vec<String>) {
+        let Some(subscriber) = self.subscribers.get_mut(session) else {
+            return (0, Vec::new());
+        };
+        let (mine, all) = match pattern {
+            true => (&mut subscriber.patterns, &mut self.patterns),
+            false => (&mut subscriber.channels, &mut self.channels),
+        };
+        let names: Vec<String> = match names.is_empty() {
+            true => mine.iter().cloned().collect(),
+            false => names.to_vec(),
+        };
+        let mut removed = Vec::new();
+        for name in names {
+            if !mine.remove(&name) {
+                continue;
+            }
+            if let Some(sessions) = all.get_mut(&name) {
+                sessions.remove(session);
+                if sessions.is_empty() {
+                    all.remove(&name);
+                    removed.push(name);
+                }
+            }
+        }
+        let count = subscriber.count();
+        if count == 0 {
+            // dropping the sender wakes up the waiting polls of the session
+            self.subscribers.remove(session);
+        }
+        (count, removed)
+    }
+
+    // drop the sessions that stopped polling and those staying over the soft limit,
+    // return the channels and the patterns left without subscriber
+    pub fn sweep(&mut self) -> (Vec<String>, Vec<String>) {
+        let (mut channels, mut patterns) = (Vec::new(), Vec::new());
+        let mut dropped = Vec::new();
+        for (session, subscriber) in self.subscribers.iter() {
+            if subscriber.last_poll.elapsed() > SUBSCRIBER_IDLE {
+                tracing::info!("Drop the idle subscriber {}", session);
+                dropped.push(session.clone());
+            } else if subscriber.is_slow(&self.limits) {
+                tracing::warn!("Disconnect the slow subscriber {} with {} bytes pending", session, subscriber.bytes);
+                self.stats.disconnected_subscribers += 1;
+                self.disconnected.insert(session.clone(), Instant::now());
+                dropped.push(session.clone());
+            }
+        }
+        for session in dropped {
+            channels.extend(self.unsubscribe(&session, &[], false).1);
+            patterns.extend(self.unsubscribe(&session, &[], true).1);
+        }
+        // a disconnected session that never polls again is forgotten like an idle one
+        self.disconnected.retain(|_, since| since.elapsed() <= SUBSCRIBER_IDLE);
+        (channels, patterns)
+    }
+
+    // queue the message for the subscribers of the channel and of the matching patterns, return the number of receivers
+    pub fn publish(&mut self, channel: &str, message: &str) -> usize {
+        let mut targets = Vec::new();
+        if let Some(sessions) = self.channels.get(channel) {
+            for session in sessions {
+                targets.push((session.clone(), format!("message\n{}\n{}", channel, message)));
+            }
+        }
+        for (pattern, sessions) in self.patterns.iter() {
+            if glob_match(pattern, channel) {
+                for session in sessions {
+                    targets.push((session.clone(), format!("pmessage\n{}\n{}\n{}", pattern, channel, message)));
+                }
+            }
+        }
+        let num = targets.len();
+        for (session, message) in targets {
+            self.push(&session, message);
+        }
+        num
+    }
+
+    // the channels with at least one subscriber, optionally only those matching the pattern
+    pub fn channels(&self, pattern: Option<&str>) -> Vec<String> {
+        let mut channels: Vec<String> = self.channels
+            .keys()
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+            .cloned()
+            .collect();
+        channels.sort();
+        channels
+    }
+
+    // the number of subscribers of each channel, the pattern subscribers are not counted
+    pub fn numsub(&self, channels: &[String]) -> Vec<(String, usize)> {
+        channels
+            .iter()
+            .map(|channel| (channel.clone(), self.channels.get(channel).map_or(0, |sessions| sessions.len())))
+            .collect()
+    }
+
+    // the number of the patterns with at least one subscriber
+    pub fn numpat(&self) -> usize {
+        self.patterns.len()
+    }
+
+    // the number of the channels with at least one subscriber
+    pub fn num_channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    // the number of the sessions subscribing to a channel or a pattern
+    pub fn num_subscribers(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    // queue the message for the session, or drop it if the queue would go over the hard limit
+    fn push(&mut self, session: &str, message: String) {
+        let Some(subscriber) = self.subscribers.get_mut(session) else {
+            return;
+        };
+        if self.limits.hard > 0 && subscriber.bytes + message.len() > self.limits.hard {
+            self.stats.dropped_messages += 1;
+            subscriber.drop_messages(&mut self.next_seq, 1);
+            return;
+        }
+        self.next_seq += 1;
+        subscriber.enqueue(self.next_seq, Pending::Message(message));
+        subscriber.update_soft_limit(&self.limits);
+    }
+
+    // acknowledge the messages of the session up to `ack`, and return the first message after them
+    pub fn poll(&mut self, session: &str, ack: u64) -> Poll {
+        let Some(subscriber) = self.subscribers.get_mut(session) else {
+            return match self.disconnected.remove(session) {
+                Some(_) => Poll::Disconnected,
+                None => Poll::NotSubscribed,
+            };
+        };
+        subscriber.last_poll = Instant::now();
+        while subscriber.queue.front().is_some_and(|(seq, _)| *seq <= ack) {
+            if let Some((_, Pending::Message(message))) = subscriber.queue.pop_front() {
+                subscriber.bytes -= message.len();
+            }
+        }
+        subscriber.update_soft_limit(&self.limits);
+        let (seq, message) = match subscriber.queue.front() {
+            Some((seq, Pending::Message(message))) => (*seq, message.clone()),
+            Some((seq, Pending::Dropped(num))) => (*seq, format!("dropped\n{}", num)),
+            None => return Poll::Wait(subscriber.seq_tx.subscribe()),
+        };
+        subscriber.delivered = seq;
+        Poll::Message(seq, message)
+    }
+}
+
+// drop the idle and the slow subscribers periodically, so that the channels of the disconnected clients do not pile up
+pub fn start_sweeper(pubsub: Weak<Mutex<PubSub>>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(SWEEP_INTERVAL).await;
+            match pubsub.upgrade() {
+                Some(pubsub) => {
+                    pubsub.lock().unwrap().sweep();
+                },
+                None => break,
+            }
+        }
+    });
+}
+
+// wait for the next message of the session after `ack`, return None if nothing arrives in POLL_TIMEOUT
+pub async fn poll(pubsub: &Mutex<PubSub>, session: &str, ack: u64) -> Result<Option<(u64, String)>, Error> {
+    let deadline = tokio::time::Instant::now() + POLL_TIMEOUT;
+    loop {
+        let poll = pubsub.lock().unwrap().poll(session, ack);
+        let mut rx = match poll {
+            Poll::Message(seq, message) => return Ok(Some((seq, message))),
+            Poll::Wait(rx) => rx,
+            Poll::NotSubscribed => return Err(Error::msg("ERR not subscribed")),
+            Poll::Disconnected => return Err(Error::msg("ERR disconnected for staying over the pub/sub output buffer limit")),
+        };
+        // the receiver also returns when the session is dropped, and the next poll tells it
+        if tokio::time::timeout_at(deadline, rx.changed()).await.is_err() {
+            return Ok(None);
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}},
+    time::{Duration, Instant},
+};
+use mlua::{Function, HookTriggers, Lua, LuaOptions, StdLib, Table, Value, Variadic};
+use anyhow::Error;
+
+use crate::{OPCode, function::{self, FunctionInfo}};
+
+// how many lua instructions run between two checks of SCRIPT KILL
+const KILL_CHECK_INSTRUCTIONS: u32 = 1000;
+
+// a library must register its functions within the time, so that FUNCTION LOAD never hangs the server
+const LOAD_TIMEOUT: Duration = Duration::from_millis(500);
+
+const KILLED: &str = "ERR Script killed by user with SCRIPT KILL...";
+
+const FLAGS: &[&str] = &["no-writes", "allow-oom", "allow-stale", "no-cluster", "allow-cross-slot-keys"];
+
+// the program to run, either the script of EVAL or a function of a library called by FCALL
+// it owns its code, since it is run on a blocking thread
+pub enum Program {
+    Script(String),
+    Function { code: String, name: String },
+}
+
+// the reply of a script, or of a command called by the script
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Nil,
+    Integer(i64),
+    Status(String),
+    Bulk(String),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    // the type of the reply, returned in the key_channal so that the client can tell a string from an array of it
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Reply::Nil => "nil",
+            Reply::Integer(_) => "integer",
+            Reply::Status(_) => "status",
+            Reply::Bulk(_) => "string",
+            Reply::Array(_) => "array",
+        }
+    }
+
+    // the elements of an array are separated by lines, and the nested arrays are flattened
+    pub fn render(&self) -> String {
+        match self {
+            Reply::Nil => "(nil)".to_string(),
+            Reply::Integer(num) => num.to_string(),
+            Reply::Status(message) | Reply::Bulk(message) => message.clone(),
+            Reply::Array(items) => items.iter().map(|item| item.render()).collect::<Vec<_>>().join("\n"),
+        }
+    }
+
+    fn into_lua(self, lua: &Lua) -> mlua::Result<Value<'_>> {
+        Ok(match self {
+            // nil is converted to false as redis does, so that it can be stored in a table
+            Reply::Nil => Value::Boolean(false),
+            Reply::Integer(num) => Value::Integer(num),
+            Reply::Status(message) => {
+                let table = lua.create_table()?;
+                table.set("ok", message)?;
+                Value::Table(table)
+            },
+            Reply::Bulk(message) => Value::String(lua.create_string(&message)?),
+            Reply::Array(items) => {
+                let table = lua.create_table()?;
+                for (index, item) in items.into_iter().enumerate() {
+                    table.set(index + 1, item.into_lua(lua)?)?;
+                }
+                Value::Table(table)
+            },
+        })
+    }
+
+    // a table with the err field is an error, and a table with the ok field is a status
+    // the array part of the other tables is taken up to the first nil
+    fn from_lua(value: Value) -> Result<Reply, String> {
+        Ok(match value {
+            Value::Nil | Value::Boolean(false) => Reply::Nil,
+            Value::Boolean(true) => Reply::Integer(1),
+            Value::Integer(num) => Reply::Integer(num),
+            Value::Number(num) => Reply::Integer(num as i64),
+            Value::String(message) => Reply::Bulk(message.to_string_lossy().to_string()),
+            Value::Table(table) => {
+                if let Ok(Value::String(message)) = table.raw_get::<_, Value>("err") {
+                    return Err(message.to_string_lossy().to_string());
+                }
+                if let Ok(Value::String(message)) = table.raw_get::<_, Value>("ok") {
+                    return Ok(Reply::Status(message.to_string_lossy().to_string()));
+                }
+                let mut items = Vec::new();
+                for value in table.sequence_values::<Value>() {
+                    items.push(Reply::from_lua(value.map_err(|e| e.to_string())?)?);
+                }
+                Reply::Array(items)
+            },
+            _ => Reply::Nil,
+        })
+    }
+}
+
+// the script being run, SCRIPT KILL stops it only if it has not written the keyspace yet
+struct Running {
+    started: Instant,
+    wrote: AtomicBool,
+    killed: AtomicBool,
+}
+
+// the cached scripts, and the state of the running one
+#[derive(Default)]
+pub struct Scripts {
+    cache: Mutex<HashMap<String, String>>,          // the sha1 of the script to its body
+    running: Mutex<Option<Arc<Running>>>,
+}
+
+pub fn sha1_hex(body: &str) -> String {
+    sha1_smol::Sha1::from(body).digest().to_string()
+}
+
+// split the value_message of EVAL and EVALSHA, which is `numkeys [key ...] [arg ...]`, into KEYS and ARGV
+pub fn parse_args(args: &str) -> Result<(Vec<String>, Vec<String>), Error> {
+    let mut args = args.split_whitespace().map(|arg| arg.to_string());
+    let numkeys = args
+        .next()
+        .ok_or(Error::msg("ERR wrong number of arguments for EVAL"))?
+        .parse::<i64>()
+        .map_err(|_| Error::msg("ERR value is not an integer or out of range"))?;
+    if numkeys < 0 {
+        return Err(Error::msg("ERR Number of keys can't be negative"));
+    }
+    let mut argv: Vec<String> = args.collect();
+    if numkeys as usize > argv.len() {
+        return Err(Error::msg("ERR Number of keys can't be greater than number of args"));
+    }
+    let keys = argv.drain(..numkeys as usize).collect();
+    Ok((keys, argv))
+}
+
+// the requests of a command called by the script
+// the commands that block, change the session or the transaction, swap the databases, or run another script are not allowed
+fn to_requests(args: &[String]) -> Result<(OPCode, Vec<volo_gen::volo::example::GetItemRequest>), String> {
+    let name = args.first().ok_or("ERR Please specify at least one argument for this redis lib call")?.to_lowercase();
+    let args = &args[1..];
+    let request = |opcode: OPCode, key: &str, value: &str| volo_gen::volo::example::GetItemRequest {
+        opcode: opcode as i32,
+        key_channal: key.to_string().into(),
+        value_message: value.to_string().into(),
+        txn_id: None,
+        session_id: None,
+        db: None,
+        auth: None,
+    };
+    let wrong_args = || "ERR Wrong number of args calling Redis command from script".to_string();
+    let (opcode, reqs) = match (name.as_str(), args) {
+        ("get", [key]) => (OPCode::GET, vec![request(OPCode::GET, key, " ")]),
+        ("set", [key, value]) => (OPCode::SET, vec![request(OPCode::SET, key, value)]),
+        ("del", keys) if !keys.is_empty() => (OPCode::DEL, keys.iter().map(|key| request(OPCode::DEL, key, " ")).collect()),
+        ("ping", []) => (OPCode::PING, vec![request(OPCode::PING, " ", "PONG")]),
+        ("ping", [message]) => (OPCode::PING, vec![request(OPCode::PING, " ", message)]),
+        ("publish", [channel, message]) => (OPCode::PUBLISH, vec![request(OPCode::PUBLISH, channel, message)]),
+        ("keys", [pattern]) => (OPCode::KEYS, vec![request(OPCode::KEYS, pattern, " ")]),
+        ("dbsize", []) => (OPCode::DBSIZE, vec![request(OPCode::DBSIZE, " ", " ")]),
+        ("randomkey", []) => (OPCode::RANDOMKEY, vec![request(OPCode::RANDOMKEY, " ", " ")]),
+        ("flushall", []) => (OPCode::FLUSHALL, vec![request(OPCode::FLUSHALL, " ", " ")]),
+        ("expire", [key, seconds]) => (OPCode::EXPIRE, vec![request(OPCode::EXPIRE, key, seconds)]),
+        ("ttl", [key]) => (OPCode::TTL, vec![request(OPCode::TTL, key, " ")]),
+        ("persist", [key]) => (OPCode::PERSIST, vec![request(OPCode::PERSIST, key, " ")]),
+        ("move", [key, db]) => (OPCode::MOVE, vec![request(OPCode::MOVE, key, db)]),
+        ("flushdb", []) => (OPCode::FLUSHDB, vec![request(OPCode::FLUSHDB, " ", " ")]),
+        (
+            "get" | "set" | "del" | "ping" | "publish" | "keys" | "dbsize" | "randomkey" | "flushall" | "expire" | "ttl" | "persist"
+                | "move" | "flushdb",
+            _,
+        ) => {
+            return Err(wrong_args());
+        },
+        (
+            "subscribe" | "psubscribe" | "unsubscribe" | "punsubscribe" | "pubsub" | "config" | "scan" | "memory" | "select" | "swapdb"
+                | "multi" | "exec" | "watch" | "discard" | "unwatch" | "eval" | "evalsha" | "script",
+            _,
+        ) => return Err("ERR This Redis command is not allowed from script".to_string()),
+        _ => return Err("ERR Unknown Redis command called from script".to_string()),
+    };
+    Ok((opcode, reqs))
+}
+
+// the reply of a command called by the script, built from the responses of its requests
+fn to_reply(opcode: &OPCode, resps: Vec<volo_gen::volo::example::GetItemResponse>, ping_message: bool) -> Reply {
+    let first = || resps.first().map(|resp| resp.value_message.to_string()).unwrap_or_default();
+    match opcode {
+        OPCode::GET | OPCode::RANDOMKEY => match resps.first() {
+            Some(resp) if resp.success => Reply::Bulk(resp.value_message.to_string()),
+            _ => Reply::Nil,
+        },
+        OPCode::DEL => Reply::Integer(resps.iter().map(|resp| resp.value_message.parse::<i64>().unwrap_or(0)).sum()),
+        OPCode::PUBLISH | OPCode::DBSIZE | OPCode::EXPIRE | OPCode::TTL | OPCode::PERSIST | OPCode::MOVE => Reply::Integer(first().parse().unwrap_or(0)),
+        OPCode::KEYS => Reply::Array(first().lines().filter(|key| !key.is_empty()).map(|key| Reply::Bulk(key.to_string())).collect()),
+        OPCode::PING if ping_message => Reply::Bulk(first()),
+        _ => Reply::Status(first()),
+    }
+}
+
+fn to_string_args(args: Variadic<Value>) -> Result<Vec<String>, String> {
+    args.into_iter()
+        .map(|arg| match arg {
+            Value::String(arg) => Ok(arg.to_string_lossy().to_string()),
+            Value::Integer(num) => Ok(num.to_string()),
+            Value::Number(num) => Ok(num.to_string()),
+            _ => Err("ERR Lua redis lib command arguments must be strings or integers".to_string()),
+        })
+        .collect()
+}
+
+// the message of the error raised in the script, without the traceback
+fn error_message(e: &mlua::Error) -> String {
+    match e {
+        mlua::Error::CallbackError { cause, .. } => error_message(cause),
+        mlua::Error::RuntimeError(message) => message.clone(),
+        mlua::Error::SyntaxError { message, .. } => message.clone(),
+        e => e.to_string(),
+    }
+}
+
+// the arguments of redis.register_function, which are either `(name, callback)`
+// or a table with the function_name, callback and the optional flags fields
+fn register_args(args: Variadic<Value>) -> mlua::Result<(String, Function, bool)> {
+    let wrong_args = || mlua::Error::RuntimeError("ERR wrong arguments given to redis.register_function".to_string());
+    let mut args = args.into_iter();
+    let (name, callback, flags) = match (args.next(), args.next(), args.next()) {
+        (Some(Value::String(name)), Some(Value::Function(callback)), None) => (name.to_str()?.to_string(), callback, None),
+        (Some(Value::Table(table)), None, None) => (
+            table.get::<_, String>("function_name").map_err(|_| wrong_args())?,
+            table.get::<_, Function>("callback").map_err(|_| wrong_args())?,
+            table.get::<_, Option<Table>>("flags").map_err(|_| wrong_args())?,
+        ),
+        _ => return Err(wrong_args()),
+    };
+    if !function::is_valid_name(&name) {
+        return Err(mlua::Error::RuntimeError(
+            "ERR Function names can only contain letters, numbers, or underscores(_) and must be at least one character long".to_string(),
+        ));
+    }
+    let mut no_writes = false;
+    for flag in flags.map(|flags| flags.sequence_values::<String>().collect::<mlua::Result<Vec<_>>>()).transpose()?.unwrap_or_default() {
+        if !FLAGS.contains(&flag.as_str()) {
+            return Err(mlua::Error::RuntimeError("ERR unknown flag given".to_string()));
+        }
+        no_writes |= flag == "no-writes";
+    }
+    Ok((name, callback, no_writes))
+}
+
+// run the code of a library, the callbacks registered by redis.register_function are collected into the returned table
+fn load_library<'lua>(lua: &'lua Lua, redis: &Table<'lua>, code: &str) -> mlua::Result<(Table<'lua>, Vec<FunctionInfo>)> {
+    let registered = lua.create_table()?;
+    let functions = RefCell::new(Vec::new());
+    lua.scope(|scope| {
+        redis.set("register_function", scope.create_function(|_, args: Variadic<Value>| {
+            let (name, callback, no_writes) = register_args(args)?;
+            if registered.contains_key(name.as_str())? {
+                return Err(mlua::Error::RuntimeError("ERR Function already exists in the library".to_string()));
+            }
+            registered.set(name.as_str(), callback)?;
+            functions.borrow_mut().push(FunctionInfo { name, no_writes });
+            Ok(())
+        })?)?;
+        // the first line is the metadata rather than lua, keep it as an empty line so that the line numbers are right
+        let body = code.split_once('\n').map(|(_, body)| body).unwrap_or("");
+        lua.load(format!("\n{}", body)).set_name("@user_function").exec()
+    })?;
+    redis.set("register_function", Value::Nil)?;
+    Ok((registered, functions.into_inner()))
+}
+
+// the name of the library and the functions it registers, the library is run without redis.call
+pub fn parse_library(code: &str) -> Result<(String, Vec<FunctionInfo>), Error> {
+    let name = function::library_name(code)?;
+    let result = (|| {
+        let lua = Lua::new_with(StdLib::STRING | StdLib::TABLE | StdLib::MATH, LuaOptions::new())?;
+        let started = Instant::now();
+        lua.set_hook(HookTriggers::new().every_nth_instruction(KILL_CHECK_INSTRUCTIONS), move |_, _| {
+            match started.elapsed() >= LOAD_TIMEOUT {
+                true => Err(mlua::Error::RuntimeError("FUNCTION LOAD timeout".to_string())),
+                false => Ok(()),
+            }
+        });
+        let redis = lua.create_table()?;
+        lua.globals().set("redis", redis.clone())?;
+        load_library(&lua, &redis, code).map(|(_, functions)| functions)
+    })();
+    let functions = result.map_err(|e| Error::msg(format!("ERR Error registering functions: {}", error_message(&e))))?;
+    if functions.is_empty() {
+        return Err(Error::msg("ERR No functions registered"));
+    }
+    Ok((name, functions))
+}
+
+impl Scripts {
+    // cache the script, it must compile
+    pub fn load(&self, body: &str) -> Result<String, Error> {
+        let lua = Lua::new_with(StdLib::NONE, LuaOptions::new()).map_err(|e| Error::msg(e.to_string()))?;
+        lua.load(body)
+            .set_name("@user_script")
+            .into_function()
+            .map_err(|e| Error::msg(format!("ERR Error compiling script: {}", error_message(&e))))?;
+        let sha = sha1_hex(body);
+        self.cache.lock().unwrap().insert(sha.clone(), body.to_string());
+        Ok(sha)
+    }
+
+    pub fn get(&self, sha: &str) -> Option<String> {
+        self.cache.lock().unwrap().get(&sha.to_lowercase()).cloned()
+    }
+
+    pub fn exists(&self, sha: &str) -> bool {
+        self.cache.lock().unwrap().contains_key(&sha.to_lowercase())
+    }
+
+    pub fn flush(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    // whether a script has been running for longer than the time limit, the other requests are refused meanwhile
+    pub fn is_busy(&self, time_limit: Duration) -> bool {
+        self.running.lock().unwrap().as_ref().is_some_and(|running| running.started.elapsed() >= time_limit)
+    }
+
+    // stop the running script at its next check, the writes of a script can not be undone so such a script is not stopped
+    pub fn kill(&self) -> Result<(), Error> {
+        match self.running.lock().unwrap().as_ref() {
+            None => Err(Error::msg("NOTBUSY No scripts in execution right now.")),
+            Some(running) if running.wrote.load(Ordering::SeqCst) => Err(Error::msg(
+                "UNKILLABLE Sorry the script already executed write commands against the dataset. \
+                 You can either wait the script termination or kill the server in a hard way.",
+            )),
+            Some(running) => {
+                running.killed.store(true, Ordering::SeqCst);
+                Ok(())
+            },
+        }
+    }
+
+    // run the script with the KEYS and ARGV tables, or the function with the keys and the args as its arguments
+    // redis.call and redis.pcall run the commands by the call function, and the read-only programs can not write
+    // the program must be run with the keyspace locked exclusively, so only one program runs at a time
+    pub fn run(
+        &self,
+        program: Program,
+        keys: Vec<String>,
+        argv: Vec<String>,
+        read_only: bool,
+        call: &dyn Fn(volo_gen::volo::example::GetItemRequest) -> Result<volo_gen::volo::example::GetItemResponse, Error>,
+    ) -> Result<Reply, Error> {
+        let running = Arc::new(Running {
+            started: Instant::now(),
+            wrote: AtomicBool::new(false),
+            killed: AtomicBool::new(false),
+        });
+        *self.running.lock().unwrap() = Some(running.clone());
+        let result = Scripts::run_lua(program, keys, argv, read_only, call, running.clone());
+        *self.running.lock().unwrap() = None;
+        match result {
+            _ if running.killed.load(Ordering::SeqCst) => Err(Error::msg(KILLED)),
+            Ok(Ok(reply)) => Ok(reply),
+            // the error reply returned by the script
+            Ok(Err(message)) => Err(Error::msg(message)),
+            Err(e) => Err(Error::msg(format!("ERR Error running script: {}", error_message(&e)))),
+        }
+    }
+
+    fn run_lua(
+        program: Program,
+        keys: Vec<String>,
+        argv: Vec<String>,
+        read_only: bool,
+        call: &dyn Fn(volo_gen::volo::example::GetItemRequest) -> Result<volo_gen::volo::example::GetItemResponse, Error>,
+        running: Arc<Running>,
+    ) -> mlua::Result<Result<Reply, String>> {
+        // a new state for each script, so that the scripts can not leave anything to the later ones
+        let lua = Lua::new_with(StdLib::STRING | StdLib::TABLE | StdLib::MATH, LuaOptions::new())?;
+        let killed = running.clone();
+        lua.set_hook(HookTriggers::new().every_nth_instruction(KILL_CHECK_INSTRUCTIONS), move |_, _| {
+            match killed.killed.load(Ordering::SeqCst) {
+                true => Err(mlua::Error::RuntimeError(KILLED.to_string())),
+                false => Ok(()),
+            }
+        });
+        let globals = lua.globals();
+        let redis = lua.create_table()?;
+        globals.set("redis", redis.clone())?;
+        let (function, args) = match program {
+            Program::Script(body) => {
+                globals.set("KEYS", keys)?;
+                globals.set("ARGV", argv)?;
+                (lua.load(&body).set_name("@user_script").into_function()?, Variadic::new())
+            },
+            Program::Function { code, name } => {
+                let (registered, _) = load_library(&lua, &redis, &code)?;
+                let args = Variadic::from_iter([Value::Table(lua.create_sequence_from(keys)?), Value::Table(lua.create_sequence_from(argv)?)]);
+                (registered.get::<_, Function>(name.as_str())?, args)
+            },
+        };
+
+        // run a command, the error is returned as the error message
+        let command = |args: Variadic<Value>| -> Result<Reply, String> {
+            let args = to_string_args(args)?;
+            let (opcode, reqs) = to_requests(&args)?;
+            let writes = matches!(
+                opcode,
+                OPCode::SET | OPCode::DEL | OPCode::FLUSHALL | OPCode::EXPIRE | OPCode::PERSIST | OPCode::MOVE | OPCode::FLUSHDB
+            );
+            if read_only && writes {
+                return Err("ERR Write commands are not allowed from read-only scripts.".to_string());
+            }
+            let ping_message = args.len() > 1;
+            let mut resps = Vec::new();
+            for req in reqs {
+                let resp = call(req).map_err(|e| e.to_string())?;
+                if !resp.success && resp.value_message.starts_with("ERR") {
+                    return Err(resp.value_message.to_string());
+                }
+                resps.push(resp);
+            }
+            if writes {
+                running.wrote.store(true, Ordering::SeqCst);
+            }
+            Ok(to_reply(&opcode, resps, ping_message))
+        };
+
+        lua.scope(|scope| {
+            // redis.call raises the error of the command in the script
+            redis.set("call", scope.create_function(|lua, args: Variadic<Value>| {
+                command(args).map_err(mlua::Error::RuntimeError)?.into_lua(lua)
+            })?)?;
+            // redis.pcall returns the error of the command as a table with the err field
+            redis.set("pcall", scope.create_function(|lua, args: Variadic<Value>| {
+                match command(args) {
+                    Ok(reply) => reply.into_lua(lua),
+                    Err(message) => {
+                        let table: Table = lua.create_table()?;
+                        table.set("err", message)?;
+                        Ok(Value::Table(table))
+                    },
+                }
+            })?)?;
+            redis.set("status_reply", lua.create_function(|lua, message: String| {
+                let table = lua.create_table()?;
+                table.set("ok", message)?;
+                Ok(table)
+            })?)?;
+            redis.set("error_reply", lua.create_function(|lua, message: String| {
+                let table = lua.create_table()?;
+                table.set("err", message)?;
+                Ok(table)
+            })?)?;
+            redis.set("sha1hex", lua.create_function(|_, body: String| Ok(sha1_hex(&body)))?)?;
+            let value: Value = function.call(args)?;
+            Ok(Reply::from_lua(value))
+        })
+    }
+}
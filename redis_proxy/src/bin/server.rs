@@ -1,16 +1,16 @@
-#![feature(impl_trait_in_assoc_type)]
-
 use std::net::SocketAddr;
-use redis_proxy::LogLayer;
+use redis_proxy::{LogLayer, AclLayer, FilterLayer, MetricsLayer, SlowLogLayer, TlsIncoming};
 use std::env;
 
-use redis_proxy::S;
+use redis_proxy::{S, Config};
 
 #[volo::main]
 async fn main() {
     tracing_subscriber::fmt::init();
     // 获得命令行参数
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    // 取出以 `--name value` 形式给出的配置项
+    let config = Config::from_args(&mut args).unwrap();
     
     // 获得本机的ip地址
     let proxy_addr = args[1].clone();
@@ -24,67 +24,74 @@ async fn main() {
     let mut index: usize = 2;
     while index < args.len() {
         if args[index] == "-n" {
-            index = index + 1;
+            index += 1;
         }
         // 获得主节点的ip
         master_ip.push(args[index].clone());
-        index = index + 1;
+        index += 1;
 
         // 获得这个主节点对应的从节点ip
         slave_ip.push(Vec::new());
         while index < args.len() && args[index] != "-n" {
             slave_ip[master_ip.len() - 1].push(args[index].clone());
-            index = index + 1;
+            index += 1;
         }
     }
     
+    // 给出证书后，客户端需要使用 TLS 连接代理
+    let acceptor = config.client.tls.acceptor().unwrap();
+    let metrics_port = config.metrics_port;
+
     // 创建一个新的服务
-    let server = S::new();
+    let server = match S::new(config) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("启动代理失败: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // 根据ip创建客户端，并将其存入server中
-    for ip in master_ip {
-        {
-            server.masters.write().unwrap().push({
-                {
-                    let addr: SocketAddr = ip.parse().unwrap();
-                    volo_gen::volo::example::ItemServiceClientBuilder::new("volo-example")
-                        .layer_outer(LogLayer)
-                        .address(addr)
-                        .build()
-                }
-            });
-        }
+    for (master, slaves) in master_ip.iter().zip(slave_ip.iter()) {
+        server.add_shard(
+            master.parse().unwrap(),
+            slaves.iter().map(|ip| ip.parse().unwrap()).collect(),
+        );
     }
 
-    let mut index: usize = 0;
-    for slave in slave_ip {
-        // 为这个主节点创建从节点客户端向量
-        { server.slaves.write().unwrap().push(Vec::new()) } ;
-        // 将所有从节点客户端加到上述向量中
-        for ip in slave {
-            
-            {
-                server.slaves.write().unwrap()[index].push({
-                    let addr: SocketAddr = ip.parse().unwrap();
-                    volo_gen::volo::example::ItemServiceClientBuilder::new("volo-example")
-                        .layer_outer(LogLayer)
-                        .address(addr)
-                        .build()
-                });
-            }
-        }
-        index = index + 1;
-    }
+    let addr: SocketAddr = proxy_addr.parse().unwrap();
 
+    // 客户端的权限由 AclLayer 在请求到达代理之前检查
+    let acl = server.acl.clone();
+    // 之后由 FilterLayer 检查请求的内容
+    let filter = server.filter.clone();
+    // 通过检查的请求由 SlowLogLayer 统计耗时
+    let slowlog = server.slowlog.clone();
+    // 所有请求（包括被拒绝的请求）都由 MetricsLayer 统计
+    let metrics = server.metrics.clone();
 
-    let addr: SocketAddr = proxy_addr.parse().unwrap();
-    let addr = volo::net::Address::from(addr);
+    // 在代理的地址上为 prometheus 提供指标
+    if metrics_port != 0 {
+        let state = server.clone();
+        let metrics_addr = SocketAddr::new(addr.ip(), metrics_port);
+        tokio::spawn(async move {
+            if let Err(e) = redis_proxy::serve_metrics(metrics_addr, move || state.metrics_text()).await {
+                tracing::error!("Failed to serve the metrics on {}: {}", metrics_addr, e);
+            }
+        });
+    }
 
-    volo_gen::volo::example::ItemServiceServer::new(server)
-        .layer_front(LogLayer)
-        .run(addr)
-        .await
-        .unwrap();
+    let server = volo_gen::volo::example::ItemServiceServer::new(server)
+        .layer_front(SlowLogLayer(slowlog))
+        .layer_front(FilterLayer(filter))
+        .layer_front(AclLayer(acl))
+        .layer_front(MetricsLayer(metrics))
+        .layer_front(LogLayer);
+    match acceptor {
+        Some(acceptor) => server.run(TlsIncoming::new(addr, acceptor)).await,
+        None => server.run(volo::net::Address::from(addr)).await,
+    }
+    .unwrap();
 
     println!("Server listening on {}", proxy_addr);
 }
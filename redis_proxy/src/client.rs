@@ -0,0 +1,98 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use rand::Rng;
+
+use crate::TlsConfig;
+
+// 代理连接后端节点时使用的客户端配置
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+	pub connect_timeout: Duration,
+	pub request_timeout: Duration,		// POLL 等阻塞的命令的超时时间为节点最长的等待时间再加上该时间
+	pub max_retries: usize,				// 只有幂等的命令才会重试
+	pub retry_backoff: Duration,		// 第一次重试前的等待时间，之后每次翻倍
+	pub pool_max_idle: usize,			// 连接池中保留的最大空闲连接数
+	pub pool_idle_timeout: Duration,
+	pub breaker_threshold: usize,		// 连续失败这么多次后熔断
+	pub breaker_cooldown: Duration,		// 熔断持续的时间，之后允许请求试探节点是否恢复
+	pub auth_user: String,				// 给出 auth_password 后，以该用户的身份访问后端节点
+	pub auth_password: Option<String>,
+	pub tls: TlsConfig,					// 代理的监听端口也使用其中的证书，见 TlsConfig
+}
+
+impl Default for ClientConfig {
+	fn default() -> ClientConfig {
+		ClientConfig {
+			connect_timeout: Duration::from_secs(1),
+			request_timeout: Duration::from_secs(3),
+			max_retries: 2,
+			retry_backoff: Duration::from_millis(50),
+			pool_max_idle: 64,
+			pool_idle_timeout: Duration::from_secs(15),
+			breaker_threshold: 5,
+			breaker_cooldown: Duration::from_secs(5),
+			auth_user: "default".to_string(),
+			auth_password: None,
+			tls: TlsConfig::default(),
+		}
+	}
+}
+
+impl ClientConfig {
+	// 第 retry 次重试（从 0 开始）前的等待时间，加入随机抖动以避免大量请求同时重试
+	pub fn backoff(&self, retry: usize) -> Duration {
+		let backoff = self.retry_backoff.saturating_mul(1 << retry.min(16));
+		backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+	}
+
+	// 每个请求携带的 `<user> <password>`，由后端节点的 AclLayer 检查
+	pub fn credentials(&self) -> Option<String> {
+		self.auth_password.as_ref().map(|password| format!("{} {}", self.auth_user, password))
+	}
+}
+
+// 熔断器，节点持续出错时让请求快速失败
+// 关闭：正常发送请求，并统计连续失败的次数
+// 打开：在冷却时间结束前，请求直接失败
+// 半开：冷却时间结束后允许请求通过，只要再失败一次就重新打开
+pub struct CircuitBreaker {
+	threshold: usize,
+	cooldown: Duration,
+	failures: AtomicUsize,
+	open_until: Mutex<Option<Instant>>,
+}
+
+impl CircuitBreaker {
+	pub fn new(threshold: usize, cooldown: Duration) -> CircuitBreaker {
+		CircuitBreaker {
+			threshold,
+			cooldown,
+			failures: AtomicUsize::new(0),
+			open_until: Mutex::new(None),
+		}
+	}
+
+	pub fn allow(&self) -> bool {
+		match *self.open_until.lock().unwrap() {
+			Some(until) => Instant::now() >= until,
+			None => true,
+		}
+	}
+
+	pub fn is_open(&self) -> bool {
+		!self.allow()
+	}
+
+	pub fn on_success(&self) {
+		self.failures.store(0, Ordering::Relaxed);
+		*self.open_until.lock().unwrap() = None;
+	}
+
+	pub fn on_failure(&self) {
+		let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+		if failures >= self.threshold {
+			*self.open_until.lock().unwrap() = Some(Instant::now() + self.cooldown);
+		}
+	}
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+use anyhow::Error;
+
+use crate::{BufferLimits, ClientAuth, ClientConfig, FilterMode};
+
+// 代理的配置，在命令行中以 `--name value` 的形式给出
+#[derive(Clone, Debug)]
+pub struct Config {
+	pub client: ClientConfig,			// 连接后端节点的客户端配置，以及 TLS 的配置
+	pub pubsub_limits: BufferLimits,	// 代理上每个订阅者的缓冲区限制
+	pub txn_idle_timeout: Duration,		// 事务空闲多久后被丢弃
+	pub requirepass: Option<String>,	// 代理上 default 用户的密码，默认不需要密码
+	pub aclfile: Option<String>,		// 启动时加载的代理上的用户，见 Acl::new
+	pub filter_file: Option<String>,	// FilterLayer 使用的过滤词，见 ContentFilter
+	pub filter_mode: FilterMode,
+	pub metrics_port: u16,				// 在代理的地址上提供 prometheus 指标的端口，0 表示不提供
+	pub slowlog_log_slower_than: i64,	// 耗时达到这么多微秒的命令被记录到慢日志中，负数表示不记录
+	pub slowlog_max_len: usize,			// 慢日志保留的命令数
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			client: ClientConfig::default(),
+			pubsub_limits: BufferLimits::default(),
+			txn_idle_timeout: Duration::from_secs(300),
+			requirepass: None,
+			aclfile: None,
+			filter_file: None,
+			filter_mode: FilterMode::default(),
+			metrics_port: 0,
+			slowlog_log_slower_than: 10000,
+			slowlog_max_len: 128,
+		}
+	}
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, Error> {
+	value.parse::<T>().map_err(|_| Error::msg(format!("Invalid value for {}: {}", name, value)))
+}
+
+fn parse_millis(name: &str, value: &str) -> Result<Duration, Error> {
+	Ok(Duration::from_millis(parse(name, value)?))
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, Error> {
+	match value.to_lowercase().as_str() {
+		"yes" => Ok(true),
+		"no" => Ok(false),
+		_ => Err(Error::msg(format!("Invalid value for {}: {}", name, value))),
+	}
+}
+
+impl Config {
+	// 从命令行参数中取出配置项，剩下的参数保留在 args 中
+	pub fn from_args(args: &mut Vec<String>) -> Result<Config, Error> {
+		let mut config = Config::default();
+		let mut positional = Vec::new();
+		let mut iter = args.drain(..);
+		while let Some(arg) = iter.next() {
+			match arg.strip_prefix("--") {
+				Some(name) => {
+					let value = iter.next().ok_or(Error::msg(format!("Missing value for option --{}", name)))?;
+					config.set(name, &value)?;
+				},
+				None => positional.push(arg),
+			}
+		}
+		drop(iter);
+		*args = positional;
+		config.client.tls.validate()?;
+		Ok(config)
+	}
+
+	pub fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+		match name {
+			"connect-timeout-ms" => self.client.connect_timeout = parse_millis(name, value)?,
+			"request-timeout-ms" => self.client.request_timeout = parse_millis(name, value)?,
+			"max-retries" => self.client.max_retries = parse(name, value)?,
+			"retry-backoff-ms" => self.client.retry_backoff = parse_millis(name, value)?,
+			"pool-max-idle" => self.client.pool_max_idle = parse(name, value)?,
+			"pool-idle-timeout-ms" => self.client.pool_idle_timeout = parse_millis(name, value)?,
+			"breaker-threshold" => self.client.breaker_threshold = parse(name, value)?,
+			"breaker-cooldown-ms" => self.client.breaker_cooldown = parse_millis(name, value)?,
+			"auth-user" => self.client.auth_user = value.to_string(),
+			"auth-password" => self.client.auth_password = Some(value.to_string()),
+			"tls" => self.client.tls.connect = parse_bool(name, value)?,
+			"tls-cert-file" => self.client.tls.cert_file = Some(value.to_string()),
+			"tls-key-file" => self.client.tls.key_file = Some(value.to_string()),
+			"tls-ca-cert-file" => self.client.tls.ca_cert_file = Some(value.to_string()),
+			"tls-auth-clients" => self.client.tls.auth_clients = ClientAuth::parse(value)?,
+			"tls-server-name" => self.client.tls.server_name = Some(value.to_string()),
+			"pubsub-hard-limit" => self.pubsub_limits.hard = parse(name, value)?,
+			"pubsub-soft-limit" => self.pubsub_limits.soft = parse(name, value)?,
+			"pubsub-soft-seconds" => self.pubsub_limits.soft_duration = Duration::from_secs(parse(name, value)?),
+			"txn-idle-timeout-ms" => self.txn_idle_timeout = parse_millis(name, value)?,
+			"requirepass" => self.requirepass = Some(value.to_string()),
+			"aclfile" => self.aclfile = Some(value.to_string()),
+			"filter-file" => self.filter_file = Some(value.to_string()),
+			"filter-mode" => self.filter_mode = FilterMode::parse(value)?,
+			"metrics-port" => self.metrics_port = parse(name, value)?,
+			"slowlog-log-slower-than" => self.slowlog_log_slower_than = parse(name, value)?,
+			"slowlog-max-len" => self.slowlog_max_len = parse(name, value)?,
+			_ => return Err(Error::msg(format!("Unknown option: {}", name))),
+		}
+		Ok(())
+	}
+}
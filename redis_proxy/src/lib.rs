@@ -1,102 +1,1547 @@
 #![feature(impl_trait_in_assoc_type)]
-use std::sync::RwLock;
+use std::sync::{RwLock, Mutex, Weak};
 use std::sync::Arc;
-use volo_gen::volo::example::ItemServiceClient;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use volo_gen::volo::example::{GetItemRequest, GetItemResponse, ItemServiceClient};
+use std::collections::HashMap;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use rand::Rng;
-use log;
 
 
 use anyhow::{Error, Ok};
 
+// acl、filter、glob、slowlog 与 tls 与 mini-redis 共用，位于仓库根目录的 common/ 中
+#[path = "../../common/acl.rs"]
+mod acl;
+mod client;
+mod config;
+#[path = "../../common/filter.rs"]
+mod filter;
+#[path = "../../common/glob.rs"]
+mod glob;
+mod metrics;
+mod pubsub;
+#[path = "../../common/slowlog.rs"]
+mod slowlog;
+#[path = "../../common/tls.rs"]
+mod tls;
+#[cfg(test)]
+#[path = "../../common/test_util.rs"]
+mod test_util;
+
+pub use acl::{Acl, AclLayer, AclService};
+pub use client::{ClientConfig, CircuitBreaker};
+pub use config::Config;
+pub use filter::{ContentFilter, FilterLayer, FilterMode, FilterService};
+pub use glob::glob_match;
+pub use metrics::{serve_metrics, start_ops_sampler, Exposition, Histogram, Metrics, MetricsLayer, MetricsService};
+pub use pubsub::{BufferLimits, PubSub, PubSubStats};
+pub use slowlog::{SlowLog, SlowLogEntry, SlowLogLayer, SlowLogService};
+pub use tls::{ClientAuth, TlsConfig, TlsIncoming, TlsMakeTransport};
+
 // pub const DEFAULT_ADDR: &str = "[::]:8080";
 
+// 健康检查时 ping 节点的超时时间
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+// 向主节点订阅失败后重试的间隔，也是代理上没有订阅时转发任务检查的间隔
+const SUBSCRIPTION_RETRY: Duration = Duration::from_secs(1);
+// 检查事务是否空闲超时的间隔
+const TXN_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+// INFO 的各个段，按照输出的顺序排列
+const INFO_SECTIONS: &[&str] = &["server", "clients", "memory", "persistence", "replication", "stats", "keyspace"];
+// 由所有分片的主节点汇总的段
+const BACKEND_SECTIONS: &[&str] = &["memory", "persistence", "stats", "keyspace"];
+// 各分片相同的标志与时间，汇总时不求和
+const UNSUMMED_FIELDS: &[&str] = &["aof_enabled", "aof_rewrite_in_progress", "aof_last_rewrite_time_sec"];
+
+// 操作码，与 mini-redis 中的定义保持一致
+#[derive(PartialEq, Eq)]
+pub enum OPCode {
+	GET = 0,
+	SET = 1,
+	DEL = 2,
+	PING = 3,
+	SUBSCRIBE = 4,
+	PUBLISH = 5,
+	SCAN = 6,
+	KEYS = 7,
+	DBSIZE = 8,
+	FLUSHALL = 9,
+	RANDOMKEY = 10,
+	PSUBSCRIBE = 11,
+	PUNSUBSCRIBE = 12,
+	UNSUBSCRIBE = 13,
+	POLL = 14,
+	PUBSUB = 15,
+	CONFIG = 16,
+	EVAL = 17,
+	EVALSHA = 18,
+	SCRIPT = 19,
+	FUNCTION = 20,
+	FCALL = 21,
+	FCALLRO = 22,
+	EXPIRE = 23,
+	TTL = 24,
+	PERSIST = 25,
+	MEMORY = 26,
+	SELECT = 27,
+	MOVE = 28,
+	SWAPDB = 29,
+	FLUSHDB = 30,
+	// AUTH 与 ACL 由代理前的 AclLayer 处理
+	AUTH = 31,
+	ACL = 32,
+	// FILTER 由 AclLayer 之后的 FilterLayer 处理
+	FILTER = 33,
+	INFO = 34,
+	// SLOWLOG 由 FilterLayer 之后的 SlowLogLayer 处理
+	SLOWLOG = 35,
+	SETMASTER = 100,
+	DELMASTER = 101,
+	FLUSHMASTER = 102,
+	EXECMASTER = 103,
+	FUNCTIONMASTER = 104,
+	EXPIREMASTER = 105,
+	MULTI = 200,
+	EXEC = 201,
+	WATCH = 202,
+	DISCARD = 203,
+	UNWATCH = 204,
+	TOPOLOGY = 300,
+	ADDSLAVE = 301,
+	DELSLAVE = 302,
+	REPLACEMASTER = 303,
+	NOTDEFINED = 255,
+}
+
+impl OPCode {
+	// 节点出错时可以安全重试的命令
+	pub fn is_idempotent(&self) -> bool {
+		matches!(
+			self,
+			OPCode::GET | OPCode::PING | OPCode::SCAN | OPCode::KEYS | OPCode::DBSIZE | OPCode::RANDOMKEY
+				| OPCode::SUBSCRIBE | OPCode::PSUBSCRIBE | OPCode::UNSUBSCRIBE | OPCode::PUNSUBSCRIBE | OPCode::POLL
+				| OPCode::PUBSUB | OPCode::FCALLRO | OPCode::TTL | OPCode::MEMORY | OPCode::SELECT | OPCode::INFO
+		)
+	}
+
+	// 会一直阻塞直到有事件发生的命令，不受请求超时的限制
+	pub fn is_blocking(&self) -> bool {
+		matches!(self, OPCode::POLL)
+	}
+}
+
+impl From<i32> for OPCode {
+	fn from(item: i32) -> Self {
+		match item {
+			0 => OPCode::GET,
+			1 => OPCode::SET,
+			2 => OPCode::DEL,
+			3 => OPCode::PING,
+			4 => OPCode::SUBSCRIBE,
+			5 => OPCode::PUBLISH,
+			6 => OPCode::SCAN,
+			7 => OPCode::KEYS,
+			8 => OPCode::DBSIZE,
+			9 => OPCode::FLUSHALL,
+			10 => OPCode::RANDOMKEY,
+			11 => OPCode::PSUBSCRIBE,
+			12 => OPCode::PUNSUBSCRIBE,
+			13 => OPCode::UNSUBSCRIBE,
+			14 => OPCode::POLL,
+			15 => OPCode::PUBSUB,
+			16 => OPCode::CONFIG,
+			17 => OPCode::EVAL,
+			18 => OPCode::EVALSHA,
+			19 => OPCode::SCRIPT,
+			20 => OPCode::FUNCTION,
+			21 => OPCode::FCALL,
+			22 => OPCode::FCALLRO,
+			23 => OPCode::EXPIRE,
+			24 => OPCode::TTL,
+			25 => OPCode::PERSIST,
+			26 => OPCode::MEMORY,
+			27 => OPCode::SELECT,
+			28 => OPCode::MOVE,
+			29 => OPCode::SWAPDB,
+			30 => OPCode::FLUSHDB,
+			31 => OPCode::AUTH,
+			32 => OPCode::ACL,
+			33 => OPCode::FILTER,
+			34 => OPCode::INFO,
+			35 => OPCode::SLOWLOG,
+			100 => OPCode::SETMASTER,
+			101 => OPCode::DELMASTER,
+			102 => OPCode::FLUSHMASTER,
+			103 => OPCode::EXECMASTER,
+			104 => OPCode::FUNCTIONMASTER,
+			105 => OPCode::EXPIREMASTER,
+			200 => OPCode::MULTI,
+			201 => OPCode::EXEC,
+			202 => OPCode::WATCH,
+			203 => OPCode::DISCARD,
+			204 => OPCode::UNWATCH,
+			300 => OPCode::TOPOLOGY,
+			301 => OPCode::ADDSLAVE,
+			302 => OPCode::DELSLAVE,
+			303 => OPCode::REPLACEMASTER,
+			_ => OPCode::NOTDEFINED,
+		}
+	}
+}
+
+// 节点的健康状态，转发请求和健康检查时都会更新
+pub struct NodeHealth {
+	down: AtomicBool,			// 最近一次访问是否失败
+	errors: AtomicU64,			// 累计失败次数
+	latency_ms: AtomicU64,		// 最近一次成功访问的耗时
+	breaker: CircuitBreaker,	// 节点持续出错时熔断
+}
+
+impl NodeHealth {
+	fn new(config: &ClientConfig) -> NodeHealth {
+		NodeHealth {
+			down: AtomicBool::new(false),
+			errors: AtomicU64::new(0),
+			latency_ms: AtomicU64::new(0),
+			breaker: CircuitBreaker::new(config.breaker_threshold, config.breaker_cooldown),
+		}
+	}
+
+	fn record_ok(&self, latency: Duration) {
+		self.down.store(false, Ordering::Relaxed);
+		self.latency_ms.store(latency.as_millis() as u64, Ordering::Relaxed);
+		self.breaker.on_success();
+	}
+
+	fn record_err(&self) {
+		self.down.store(true, Ordering::Relaxed);
+		self.errors.fetch_add(1, Ordering::Relaxed);
+		self.breaker.on_failure();
+	}
+
+	pub fn is_down(&self) -> bool {
+		self.down.load(Ordering::Relaxed)
+	}
+}
+
+// 后端节点，保存节点地址、客户端以及健康状态
+#[derive(Clone)]
+pub struct Node {
+	pub addr: SocketAddr,
+	pub client: ItemServiceClient,
+	pub health: Arc<NodeHealth>,
+	config: ClientConfig,
+}
+
+impl Node {
+	pub fn new(addr: SocketAddr, config: &ClientConfig) -> Node {
+		Node {
+			addr,
+			client: {
+				let builder = volo_gen::volo::example::ItemServiceClientBuilder::new("volo-example")
+					.layer_outer(LogLayer)
+					.address(addr)
+					.connect_timeout(Some(config.connect_timeout))
+					.pool_config(volo_thrift::transport::pool::Config::new(config.pool_max_idle, config.pool_idle_timeout));
+				match config.tls.connect {
+					true => builder.make_transport(TlsMakeTransport::new(&config.tls)).build(),
+					false => builder.build(),
+				}
+			},
+			health: Arc::new(NodeHealth::new(config)),
+			config: config.clone(),
+		}
+	}
+
+	// 发送一次请求，并根据结果更新节点的健康状态
+	// volo 会把连接错误作为 application error 返回，因此所有的错误都被视为节点出错
+	// 客户端的权限已经由代理检查过，请求以代理自己的 auth-user 的身份发送给后端节点
+	async fn try_get_item(&self, mut req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		let now = std::time::Instant::now();
+		req.auth = self.config.credentials().map(|credentials| credentials.into());
+		// 节点最多等待 POLL_TIMEOUT 才回复 POLL（节点与代理的等待时间相同），因此 POLL 的超时时间要再加上这段时间
+		let timeout = match OPCode::from(req.opcode).is_blocking() {
+			true => pubsub::POLL_TIMEOUT + self.config.request_timeout,
+			false => self.config.request_timeout,
+		};
+		let result = match tokio::time::timeout(timeout, self.client.get_item(req)).await {
+			::core::result::Result::Ok(result) => result,
+			::core::result::Result::Err(_) => {
+				self.health.record_err();
+				return Err(Error::msg(format!("Request to {} timed out", self.addr)));
+			},
+		};
+		match result {
+			::core::result::Result::Ok(resp) => {
+				self.health.record_ok(now.elapsed());
+				Ok(resp)
+			},
+			::core::result::Result::Err(e) => {
+				self.health.record_err();
+				Err(Error::msg(e))
+			}
+		}
+	}
+
+	// 转发请求，节点熔断时直接失败，幂等的命令在节点出错时会退避重试
+	async fn get_item(&self, req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		let retries = match OPCode::from(req.opcode).is_idempotent() {
+			true => self.config.max_retries,
+			false => 0,
+		};
+		let mut retry = 0;
+		loop {
+			if !self.health.breaker.allow() {
+				return Err(Error::msg(format!("Circuit breaker of {} is open", self.addr)));
+			}
+			match self.try_get_item(req.clone()).await {
+				::core::result::Result::Ok(resp) => return Ok(resp),
+				::core::result::Result::Err(e) if retry < retries => {
+					let backoff = self.config.backoff(retry);
+					tracing::warn!("Request to {} failed: {}, retry in {}ms", self.addr, e, backoff.as_millis());
+					tokio::time::sleep(backoff).await;
+					retry += 1;
+				},
+				::core::result::Result::Err(e) => return Err(e),
+			}
+		}
+	}
+
+	// ping 节点以检查其是否存活，健康检查不受熔断的限制，成功后会关闭熔断
+	async fn check(&self) {
+		let req = GetItemRequest {
+			opcode: OPCode::PING as i32,
+			key_channal: " ".into(),
+			value_message: "pong".into(),
+			txn_id: None,
+			session_id: None,
+			db: None,
+			auth: None,
+		};
+		if tokio::time::timeout(HEALTH_CHECK_TIMEOUT, self.try_get_item(req)).await.is_err() {
+			self.health.record_err();
+		}
+	}
+
+	// 通过 INFO replication 查询节点的角色，以及主节点列出的从节点地址（slave<n>:ip=<ip>,port=<port>,...）
+	async fn replication(&self) -> Result<(String, Vec<SocketAddr>), Error> {
+		let resp = self.get_item(GetItemRequest {
+			opcode: OPCode::INFO as i32,
+			key_channal: "replication".into(),
+			value_message: " ".into(),
+			txn_id: None,
+			session_id: None,
+			db: None,
+			auth: None,
+		}).await?;
+		if !resp.success {
+			return Err(Error::msg(format!("Failed to check the role of {}: {}", self.addr, resp.value_message)));
+		}
+		let mut role = String::new();
+		let mut slaves = Vec::new();
+		for line in resp.value_message.lines() {
+			let Some((name, value)) = line.split_once(':') else {
+				continue;
+			};
+			if name == "role" {
+				role = value.to_string();
+			} else if name.strip_prefix("slave").is_some_and(|index| index.parse::<usize>().is_ok()) {
+				let field = |field: &str| value.split(',').find_map(|pair| pair.strip_prefix(field)?.strip_prefix('='));
+				let ip = field("ip").and_then(|ip| ip.parse::<std::net::IpAddr>().ok());
+				let port = field("port").and_then(|port| port.parse::<u16>().ok());
+				if let (Some(ip), Some(port)) = (ip, port) {
+					slaves.push(SocketAddr::new(ip, port));
+				}
+			}
+		}
+		Ok((role, slaves))
+	}
+
+	fn describe(&self) -> String {
+		let breaker = match self.health.breaker.is_open() {
+			true => " breaker=open",
+			false => "",
+		};
+		match self.health.is_down() {
+			true => format!("{} down errors={}{}", self.addr, self.health.errors.load(Ordering::Relaxed), breaker),
+			false => format!(
+				"{} up {}ms errors={}{}",
+				self.addr,
+				self.health.latency_ms.load(Ordering::Relaxed),
+				self.health.errors.load(Ordering::Relaxed),
+				breaker,
+			),
+		}
+	}
+}
+
+// 获得 key 用于路由的部分，若 key 中含有非空的 hash tag，如 "{user1}.name"，则只使用 "user1" 路由，
+// 以便让多个 key 落在同一个分片上
+fn route_key(key: &str) -> &str {
+	if let Some(start) = key.find('{') {
+		if let Some(len) = key[start + 1..].find('}') {
+			if len > 0 {
+				return &key[start + 1..start + 1 + len];
+			}
+		}
+	}
+	key
+}
+
+// 经过代理的事务，由第一次 WATCH 或者 MULTI 创建，事务中所有的命令都会被固定到同一个分片的主节点上执行
+#[derive(Clone)]
+struct Txn {
+	session_id: Option<String>,			// 开启事务的客户端会话
+	multi: bool,						// 是否已经 MULTI，之前只 watch 了 key
+	shard: Option<usize>,				// 事务固定的分片，由 watch 的 key 或者第一个命令的 key 决定
+	backend_txn_id: Option<String>,		// 该分片主节点返回的 txn_id
+	aborted: bool,						// 有命令排队失败，EXEC 时放弃整个事务
+	last_active: Instant,				// 最后一次使用该事务的时间
+}
+
+// 定期丢弃空闲超时的事务，主节点上对应的事务由主节点自己超时丢弃
+async fn expire_txns(txns: Weak<RwLock<HashMap<String, Txn>>>, timeout: Duration) {
+	loop {
+		tokio::time::sleep(TXN_SWEEP_INTERVAL).await;
+		let Some(txns) = txns.upgrade() else {
+			break;
+		};
+		txns.write().unwrap().retain(|txn_id, txn| {
+			let alive = txn.last_active.elapsed() < timeout;
+			if !alive {
+				tracing::info!("Transaction {} expired", txn_id);
+			}
+			alive
+		});
+	}
+}
+
+// 向节点发送订阅相关的命令，names 为以空格分隔的频道（或模式）
+async fn send_subscription(node: &Node, opcode: OPCode, names: &[String], session_id: &str) -> Result<GetItemResponse, Error> {
+	node.get_item(GetItemRequest {
+		opcode: opcode as i32,
+		key_channal: names.join(" ").into(),
+		value_message: " ".into(),
+		txn_id: None,
+		session_id: Some(session_id.to_string().into()),
+		db: None,
+		auth: None,
+	}).await
+}
+
+// 将代理上订阅的变化发送到所有分片的主节点，失败的分片由转发任务之后重新订阅
+async fn sync_subscription(masters: &RwLock<Vec<Node>>, session_id: &str, opcode: OPCode, names: Vec<String>) {
+	if names.is_empty() {
+		return;
+	}
+	let masters = { masters.read().unwrap().clone() };
+	let opcode = opcode as i32;
+	let tasks = masters
+		.into_iter()
+		.map(|node| {
+			let names = names.clone();
+			let session_id = session_id.to_string();
+			tokio::spawn(async move {
+				if let ::core::result::Result::Err(e) = send_subscription(&node, OPCode::from(opcode), &names, &session_id).await {
+					tracing::warn!("Sync subscription to {} failed: {}", node.addr, e);
+				}
+			})
+		})
+		.collect::<Vec<_>>();
+	for task in tasks {
+		let _ = task.await;
+	}
+}
+
+// 定期删除已经断开的会话，并取消主节点上不再有订阅者的频道和模式
+async fn sweep(masters: Arc<RwLock<Vec<Node>>>, pubsub: Weak<Mutex<PubSub>>, session_id: String) {
+	loop {
+		tokio::time::sleep(pubsub::SWEEP_INTERVAL).await;
+		let (channels, patterns) = match pubsub.upgrade() {
+			Some(pubsub) => pubsub.lock().unwrap().sweep(),
+			None => break,
+		};
+		sync_subscription(&masters, &session_id, OPCode::UNSUBSCRIBE, channels).await;
+		sync_subscription(&masters, &session_id, OPCode::PUNSUBSCRIBE, patterns).await;
+	}
+}
+
+// 以代理自身的会话从某个分片的主节点拉取消息，并放入代理上订阅者的队列中
+// 主节点发生变化或者丢失了订阅时，向当前的主节点重新订阅代理上所有的频道和模式
+async fn relay(masters: Arc<RwLock<Vec<Node>>>, shard: usize, pubsub: Arc<Mutex<PubSub>>, session_id: String) {
+	let mut subscribed: Option<SocketAddr> = None;		// 已经同步了订阅的主节点
+	let mut ack: u64 = 0;
+	loop {
+		let (channels, patterns) = { pubsub.lock().unwrap().names() };
+		if channels.is_empty() && patterns.is_empty() {
+			tokio::time::sleep(SUBSCRIPTION_RETRY).await;
+			continue;
+		}
+		// 每次循环重新获取主节点，以便拓扑变化后向新的主节点订阅
+		let master = masters.read().unwrap()[shard].clone();
+		if subscribed != Some(master.addr) {
+			let mut result = Ok(());
+			for (opcode, names) in [(OPCode::SUBSCRIBE, channels), (OPCode::PSUBSCRIBE, patterns)] {
+				if !names.is_empty() {
+					result = result.and(send_subscription(&master, opcode, &names, &session_id).await.map(|_| ()));
+				}
+			}
+			if let ::core::result::Result::Err(e) = result {
+				tracing::warn!("Subscribe on shard {} failed: {}", shard, e);
+				tokio::time::sleep(SUBSCRIPTION_RETRY).await;
+				continue;
+			}
+			tracing::info!("Relay messages from shard {} master {}", shard, master.addr);
+			// 新的订阅的消息序号与之前无关
+			subscribed = Some(master.addr);
+			ack = 0;
+		}
+		let resp = master.get_item(GetItemRequest {
+			opcode: OPCode::POLL as i32,
+			key_channal: ack.to_string().into(),
+			value_message: " ".into(),
+			txn_id: None,
+			session_id: Some(session_id.clone().into()),
+			db: None,
+			auth: None,
+		}).await;
+		match resp {
+			::core::result::Result::Ok(resp) if resp.success => {
+				ack = resp.key_channal.parse().unwrap_or(ack);
+				pubsub.lock().unwrap().deliver(&resp.value_message);
+			},
+			// 主节点上没有代理的订阅，例如主节点重启过
+			::core::result::Result::Ok(resp) if resp.value_message.starts_with("ERR") => subscribed = None,
+			::core::result::Result::Ok(_) => {},
+			::core::result::Result::Err(e) => {
+				tracing::warn!("Poll on shard {} failed: {}", shard, e);
+				tokio::time::sleep(SUBSCRIPTION_RETRY).await;
+			},
+		}
+	}
+}
+
+#[derive(Default, Clone)]
 pub struct S {
-	pub masters: Arc<RwLock<Vec<ItemServiceClient>>>,
-	pub slaves: Arc<RwLock<Vec<Vec<ItemServiceClient>>>>,
+	pub masters: Arc<RwLock<Vec<Node>>>,
+	pub slaves: Arc<RwLock<Vec<Vec<Node>>>>,
+	txns: Arc<RwLock<HashMap<String, Txn>>>,						// 代理的 txn_id 到事务的映射
+	pubsub: Arc<Mutex<PubSub>>,										// 代理上客户端会话的订阅以及未读的消息
+	pubsub_session: String,											// 代理向主节点订阅时使用的会话
+	pub acl: Arc<Acl>,												// 代理上的用户，由代理前的 AclLayer 检查
+	pub filter: Arc<ContentFilter>,									// 过滤词，由 AclLayer 之后的 FilterLayer 检查
+	pub metrics: Arc<Metrics>,										// 在 metrics-port 上提供的计数器，也由 MetricsLayer 更新
+	pub slowlog: Arc<SlowLog>,										// 慢日志，由 FilterLayer 之后的 SlowLogLayer 记录
+	config: Config,
 }
 
+// S 由 tokio 的多个工作线程共享，必须自身即为 Send 和 Sync
+// 其中的标准库锁只保护短小的同步代码，clippy 会拒绝跨越 await 持有它们
+const _: fn() = || {
+	fn assert_send_sync<T: Send + Sync>() {}
+	assert_send_sync::<S>();
+};
+
 impl S {
-	pub fn new() -> S {
-		S {
-			masters: Arc::new(RwLock::new(Vec::new())),
-			slaves: Arc::new(RwLock::new(Vec::new())),
+	// aclfile 或 filter-file 有误时返回错误，其中包含出错的文件名，aclfile 还包含出错的行号
+	pub fn new(config: Config) -> Result<S, Error> {
+		let server = S {
+			pubsub: Arc::new(Mutex::new(PubSub::new(config.pubsub_limits.clone()))),
+			acl: Arc::new(Acl::new(config.requirepass.as_deref(), config.aclfile.as_deref())?),
+			filter: Arc::new(ContentFilter::new(config.filter_file.as_deref(), config.filter_mode)?),
+			slowlog: Arc::new(SlowLog::new(config.slowlog_log_slower_than, config.slowlog_max_len)),
+			config,
+			pubsub_session: format!("proxy-{:032x}", rand::random::<u128>()),
+			..S::default()
+		};
+		tokio::spawn(sweep(server.masters.clone(), Arc::downgrade(&server.pubsub), server.pubsub_session.clone()));
+		tokio::spawn(expire_txns(Arc::downgrade(&server.txns), server.config.txn_idle_timeout));
+		start_ops_sampler(Arc::downgrade(&server.metrics));
+		Ok(server)
+	}
+
+	// prometheus 文本格式的指标，包括计数器以及此时读取的后端节点健康状态与订阅数量
+	pub fn metrics_text(&self) -> String {
+		let mut out = Exposition::default();
+		self.metrics.render(&mut out);
+		let masters = { self.masters.read().unwrap().clone() };
+		let slaves = { self.slaves.read().unwrap().clone() };
+		let mut nodes = Vec::new();
+		for (shard, master) in masters.iter().enumerate() {
+			nodes.push((shard, "master", master));
+			nodes.extend(slaves[shard].iter().map(|slave| (shard, "slave", slave)));
+		}
+		let gauges: [(&str, &str, &str, fn(&NodeHealth) -> u64); 4] = [
+			("redis_proxy_backend_up", "gauge", "Whether the last request to the backend succeeded", |health| !health.is_down() as u64),
+			("redis_proxy_backend_errors_total", "counter", "The failed requests to the backend", |health| health.errors.load(Ordering::Relaxed)),
+			("redis_proxy_backend_breaker_open", "gauge", "Whether the circuit breaker of the backend is open", |health| health.breaker.is_open() as u64),
+			("redis_proxy_backend_latency_milliseconds", "gauge", "The latency of the last successful request to the backend", |health| health.latency_ms.load(Ordering::Relaxed)),
+		];
+		for (name, kind, help, value) in gauges {
+			out.header(name, kind, help);
+			for (shard, role, node) in nodes.iter() {
+				let labels = [("shard", shard.to_string()), ("role", role.to_string()), ("addr", node.addr.to_string())];
+				let labels: Vec<(&str, &str)> = labels.iter().map(|(label, value)| (*label, value.as_str())).collect();
+				out.sample(name, &labels, value(&node.health));
+			}
 		}
+		let pubsub = self.pubsub.lock().unwrap();
+		out.header("redis_proxy_pubsub_subscribers", "gauge", "The sessions subscribing to a channel or a pattern on the proxy");
+		out.sample("redis_proxy_pubsub_subscribers", &[], pubsub.num_subscribers());
+		out.header("redis_proxy_pubsub_channels", "gauge", "The channels with at least one subscriber on the proxy");
+		out.sample("redis_proxy_pubsub_channels", &[], pubsub.num_channels());
+		out.header("redis_proxy_pubsub_patterns", "gauge", "The patterns with at least one subscriber on the proxy");
+		out.sample("redis_proxy_pubsub_patterns", &[], pubsub.numpat());
+		out.into_string()
 	}
-}
 
-unsafe impl Send for S {}
-unsafe impl Sync for S {}
+	// 添加一个分片，第一个地址为主节点，其余为从节点，并开始从该分片转发订阅的消息
+	pub fn add_shard(&self, master: SocketAddr, slaves: Vec<SocketAddr>) {
+		let shard = {
+			let mut masters = self.masters.write().unwrap();
+			masters.push(Node::new(master, &self.config.client));
+			masters.len() - 1
+		};
+		self.slaves.write().unwrap().push(slaves.into_iter().map(|addr| Node::new(addr, &self.config.client)).collect());
+		tokio::spawn(relay(self.masters.clone(), shard, self.pubsub.clone(), self.pubsub_session.clone()));
+	}
 
-#[volo::async_trait]
-impl volo_gen::volo::example::ItemService for S {
-	async fn get_item(&self, _req: volo_gen::volo::example::GetItemRequest) -> ::core::result::Result<volo_gen::volo::example::GetItemResponse, ::volo_thrift::AnyhowError>{
+	// 检查所有节点的健康状态，并输出当前的集群拓扑
+	async fn topology(&self) -> String {
+		let masters = { self.masters.read().unwrap().clone() };
+		let slaves = { self.slaves.read().unwrap().clone() };
+
+		// 并发地对所有节点做健康检查
+		let checks = masters
+			.iter()
+			.chain(slaves.iter().flatten())
+			.map(|node| {
+				let node = node.clone();
+				tokio::spawn(async move { node.check().await })
+			})
+			.collect::<Vec<_>>();
+		for check in checks {
+			let _ = check.await;
+		}
+
+		let mut lines = Vec::new();
+		for (shard, master) in masters.iter().enumerate() {
+			lines.push(format!("shard {} master {}", shard, master.describe()));
+			for slave in slaves[shard].iter() {
+				lines.push(format!("shard {} slave {}", shard, slave.describe()));
+			}
+		}
+		lines.join("\n")
+	}
+
+	// 解析管理命令的参数，key_channal 为分片编号，value_message 为节点地址
+	fn parse_admin_args(&self, req: &GetItemRequest) -> Result<(usize, SocketAddr), Error> {
+		let shard = req.key_channal.parse::<usize>()
+			.map_err(|_| Error::msg(format!("Invalid shard id: {}", req.key_channal)))?;
+		if shard >= self.masters.read().unwrap().len() {
+			return Err(Error::msg(format!("The shard {} does not exist", shard)));
+		}
+		let addr = req.value_message.parse::<SocketAddr>()
+			.map_err(|_| Error::msg(format!("Invalid address: {}", req.value_message)))?;
+		Ok((shard, addr))
+	}
+
+	// 为分片添加从节点，节点必须已经以该分片主节点的从节点身份启动
+	// 从节点并不知道自己的主节点，主从关系由主节点的启动参数决定，因此检查节点的 role 为 slave，并且出现在主节点列出的从节点中
+	async fn add_slave(&self, req: &GetItemRequest) -> Result<String, Error> {
+		let (shard, addr) = self.parse_admin_args(req)?;
+		let in_cluster = self.masters.read().unwrap().iter().any(|node| node.addr == addr)
+			|| self.slaves.read().unwrap().iter().flatten().any(|node| node.addr == addr);
+		if in_cluster {
+			return Err(Error::msg(format!("The node {} is already in the cluster", addr)));
+		}
+		let node = Node::new(addr, &self.config.client);
+		let (role, _) = node.replication().await?;
+		if role != "slave" {
+			return Err(Error::msg(format!("The node {} is not a slave", addr)));
+		}
+		let master = self.master(shard);
+		let (_, slaves) = master.replication().await?;
+		if !slaves.contains(&addr) {
+			return Err(Error::msg(format!("The node {} is not a slave of the master {} of shard {}", addr, master.addr, shard)));
+		}
+		// 检查期间同一个节点可能已经被并发地添加
+		let mut slaves = self.slaves.write().unwrap();
+		if slaves.iter().flatten().any(|node| node.addr == addr) {
+			return Err(Error::msg(format!("The node {} is already in the cluster", addr)));
+		}
+		slaves[shard].push(node);
+		tracing::info!("Add slave {} to shard {}", addr, shard);
+		Ok("OK".into())
+	}
+
+	// 从分片中移除从节点
+	fn del_slave(&self, req: &GetItemRequest) -> Result<String, Error> {
+		let (shard, addr) = self.parse_admin_args(req)?;
+		let mut slaves = self.slaves.write().unwrap();
+		match slaves[shard].iter().position(|node| node.addr == addr) {
+			Some(index) => {
+				slaves[shard].remove(index);
+				tracing::info!("Remove slave {} from shard {}", addr, shard);
+				Ok("OK".into())
+			},
+			None => Err(Error::msg(format!("The node {} is not a slave of shard {}", addr, shard))),
+		}
+	}
+
+	// 替换分片的主节点，新的节点必须已经以主节点的身份启动（即启动时给出了从节点），否则写入会被它拒绝
+	// redis 节点的角色在启动时确定，代理无法提升从节点，因此先通过 INFO 检查节点的角色
+	async fn replace_master(&self, req: &GetItemRequest) -> Result<String, Error> {
+		let (shard, addr) = self.parse_admin_args(req)?;
+		if self.masters.read().unwrap().iter().any(|node| node.addr == addr) {
+			return Err(Error::msg(format!("The node {} is already a master", addr)));
+		}
+		let node = Node::new(addr, &self.config.client);
+		let (role, slaves) = node.replication().await?;
+		if role != "master" {
+			return Err(Error::msg(format!("The node {} is not a master, start it with its slaves before REPLACEMASTER", addr)));
+		}
+		let slaves = slaves.into_iter().map(|addr| Node::new(addr, &self.config.client)).collect();
+		let mut masters = self.masters.write().unwrap();
+		tracing::info!("Replace master of shard {}: {} -> {}", shard, masters[shard].addr, addr);
+		masters[shard] = node;
+		// 旧主节点的从节点仍然从旧主节点复制，继续从它们读取会读到过期的数据，因此换成新主节点列出的从节点
+		let old_slaves = std::mem::replace(&mut self.slaves.write().unwrap()[shard], slaves);
+		for slave in old_slaves {
+			tracing::info!("Remove slave {} of the old master from shard {}", slave.addr, shard);
+		}
+		Ok("OK".into())
+	}
+
+	// 获得 key 所在的分片
+	fn shard_of(&self, key: &str) -> Result<usize, Error> {
 		// 创建一个hash
 		let mut hash = DefaultHasher::new();
 
 		// 获得hash值
-		let hash_code = { 
-			_req.key_channal.clone().as_str().hash(&mut hash);
+		let hash_code = {
+			route_key(key).hash(&mut hash);
 			hash.finish()
 		};
 		// 获得主节点的个数
-		let master_num: usize =  { self.masters.read().unwrap().len() } ;
+		let master_num: usize = { self.masters.read().unwrap().len() };
+		if master_num == 0 {
+			return Err(Error::msg("No master in the cluster"));
+		}
 
 		// 获得将要访问的节点的id
-		let master_id = (hash_code as usize) % master_num; 
+		Ok((hash_code as usize) % master_num)
+	}
+
+	fn master(&self, shard: usize) -> Node {
+		self.masters.read().unwrap()[shard].clone()
+	}
 
-		// 过滤主节点同步操作
-		if _req.opcode == 100 || _req.opcode == 101 {
-			return Err(::volo_thrift::AnyhowError::from(anyhow::Error::msg("Can't not handle master operations.")));
+	// 随机选择分片中的一个节点（包括主节点），用于只读的请求
+	// 从节点可能被 DELSLAVE 并发地移除，因此在同一个快照中计数并选择节点
+	fn any_node(&self, shard: usize) -> Node {
+		let slaves = { self.slaves.read().unwrap()[shard].clone() };
+		// 生成随机数，并将随机数对节点数量（包括主节点）做模
+		let node_id: usize = rand::thread_rng().gen::<usize>() % (slaves.len() + 1);
+		match slaves.into_iter().nth(node_id) {
+			Some(slave) => {
+				// get从节点
+				log::info!("{}", format!("master {} slave {}", shard, node_id));
+				slave
+			},
+			None => {
+				// get主节点
+				log::info!("{}", format!("master {}", shard));
+				self.master(shard)
+			},
 		}
+	}
 
-		// 如果是ping操作，直接返回相关信息
-		if _req.opcode == 3 {
-			return Ok(volo_gen::volo::example::GetItemResponse {
-				opcode: 3,
-				key_channal: _req.key_channal.clone(),
-				value_message: _req.value_message.clone(),
-				success: true
-			});
+	// 在分片的主节点上发送事务相关的命令，返回主节点的 txn_id，watch 的 key 属于客户端选择的数据库
+	async fn txn_on_shard(&self, shard: usize, opcode: OPCode, key: &str, backend_txn_id: Option<String>, session_id: Option<String>, db: Option<i32>) -> Result<String, Error> {
+		let resp = self.master(shard).get_item(GetItemRequest {
+			opcode: opcode as i32,
+			key_channal: key.to_string().into(),
+			value_message: " ".into(),
+			txn_id: backend_txn_id.map(|id| id.into()),
+			session_id: session_id.map(|id| id.into()),
+			db,
+			auth: None,
+		}).await?;
+		match resp.success {
+			true => Ok(resp.key_channal.to_string()),
+			false => Err(Error::msg(resp.value_message.to_string())),
 		}
+	}
 
-		// 获得访问节点的客户端，若为get操作，则从
-		let rpc_cli = match  _req.opcode == 0 && _req.txn_id.is_none() {
+	// 查找属于该会话的事务并刷新其活跃时间，返回事务当前状态的副本
+	fn check_owner(&self, txn_id: &str, session_id: &Option<String>) -> Result<Txn, Error> {
+		match self.txns.write().unwrap().get_mut(txn_id) {
+			Some(txn) if &txn.session_id == session_id => {
+				txn.last_active = Instant::now();
+				Ok(txn.clone())
+			},
+			Some(_) => Err(Error::msg("ERR the transaction belongs to another session")),
+			None => Err(Error::msg("ERR no such transaction")),
+		}
+	}
+
+	// 修改事务的状态，事务已经不存在（例如空闲超时被丢弃）时返回错误
+	fn update_txn(&self, txn_id: &str, f: impl FnOnce(&mut Txn)) -> Result<(), Error> {
+		let mut txns = self.txns.write().unwrap();
+		let txn = txns.get_mut(txn_id).ok_or(Error::msg("ERR no such transaction"))?;
+		f(txn);
+		Ok(())
+	}
+
+	// 代理分配的 txn_id 为随机数，避免被其他客户端猜到
+	fn new_txn(&self, session_id: Option<String>, multi: bool, shard: Option<usize>, backend_txn_id: Option<String>) -> String {
+		let txn_id = format!("{:032x}", rand::random::<u128>());
+		self.txns.write().unwrap().insert(txn_id.clone(), Txn {
+			session_id,
+			multi,
+			shard,
+			backend_txn_id,
+			aborted: false,
+			last_active: Instant::now(),
+		});
+		txn_id
+	}
+
+	fn txn_resp(opcode: i32, txn_id: String) -> GetItemResponse {
+		GetItemResponse {
+			opcode,
+			key_channal: txn_id.into(),
+			value_message: "OK".into(),
+			success: true,
+		}
+	}
+
+	// 开启事务，若之前 watch 了某些 key，则事务固定到这些 key 所在的分片上
+	async fn multi(&self, req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		let session_id = req.session_id.as_ref().map(|id| id.to_string());
+		let Some(txn_id) = req.txn_id.as_ref().map(|id| id.to_string()) else {
+			return Ok(S::txn_resp(req.opcode, self.new_txn(session_id, true, None, None)));
+		};
+		let txn = self.check_owner(&txn_id, &session_id)?;
+		if txn.multi {
+			return Err(Error::msg("ERR MULTI calls can not be nested"));
+		}
+		if let (Some(shard), Some(backend_txn_id)) = (txn.shard, txn.backend_txn_id) {
+			self.txn_on_shard(shard, OPCode::MULTI, " ", Some(backend_txn_id), session_id, req.db).await?;
+		}
+		self.update_txn(&txn_id, |txn| txn.multi = true)?;
+		Ok(S::txn_resp(req.opcode, txn_id))
+	}
+
+	// 命令排队失败，EXEC 时放弃整个事务
+	fn abort(&self, txn_id: &str) {
+		let _ = self.update_txn(txn_id, |txn| txn.aborted = true);
+	}
+
+	// 将事务中的命令转发到事务固定的分片上排队
+	async fn queue(&self, txn_id: String, req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		let result = self.try_queue(&txn_id, req).await;
+		match &result {
+			::core::result::Result::Ok(resp) if resp.success => {},
+			_ => self.abort(&txn_id),
+		}
+		result
+	}
+
+	async fn try_queue(&self, txn_id: &str, mut req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		let session_id = req.session_id.as_ref().map(|id| id.to_string());
+		let txn = self.check_owner(txn_id, &session_id)?;
+		let shard = self.shard_of(&req.key_channal)?;
+		let backend_txn_id = match (txn.shard, txn.backend_txn_id) {
+			(Some(pinned), _) if pinned != shard => {
+				return Err(Error::msg("CROSSSLOT Keys in request don't hash to the same shard as the transaction"));
+			},
+			(Some(_), Some(backend_txn_id)) => backend_txn_id,
+			_ => {
+				// 第一个命令决定事务所在的分片
+				let backend_txn_id = self.txn_on_shard(shard, OPCode::MULTI, " ", None, session_id, req.db).await?;
+				self.update_txn(txn_id, |txn| {
+					txn.shard = Some(shard);
+					txn.backend_txn_id = Some(backend_txn_id.clone());
+				})?;
+				backend_txn_id
+			},
+		};
+		req.txn_id = Some(backend_txn_id.into());
+		self.master(shard).get_item(req).await
+	}
+
+	// 取出已经 MULTI 的事务，用于 EXEC 和 DISCARD
+	fn take_multi(&self, req: &GetItemRequest, command: &str) -> Result<Txn, Error> {
+		let session_id = req.session_id.as_ref().map(|id| id.to_string());
+		let txn_id = req.txn_id.as_ref().map(|id| id.to_string()).unwrap_or_default();
+		let mut txns = self.txns.write().unwrap();
+		match txns.get(&txn_id) {
+			Some(txn) if txn.session_id != session_id => Err(Error::msg("ERR the transaction belongs to another session")),
+			Some(txn) if txn.multi => txns.remove(&txn_id).ok_or(Error::msg("ERR no such transaction")),
+			_ => Err(Error::msg(format!("ERR {} without MULTI", command))),
+		}
+	}
+
+	// 在事务固定的分片上执行事务，排队时出过错的事务被整个放弃
+	async fn exec(&self, mut req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		let txn = self.take_multi(&req, "EXEC")?;
+		if txn.aborted {
+			if let (Some(shard), Some(backend_txn_id)) = (txn.shard, txn.backend_txn_id) {
+				let session_id = req.session_id.as_ref().map(|id| id.to_string());
+				let _ = self.txn_on_shard(shard, OPCode::DISCARD, " ", Some(backend_txn_id), session_id, req.db).await;
+			}
+			return Err(Error::msg("EXECABORT Transaction discarded because of previous errors."));
+		}
+		match (txn.shard, txn.backend_txn_id) {
+			(Some(shard), Some(backend_txn_id)) => {
+				req.txn_id = Some(backend_txn_id.into());
+				self.master(shard).get_item(req).await
+			},
+			// 事务中没有任何命令
+			_ => Ok(GetItemResponse {
+				opcode: req.opcode,
+				key_channal: req.key_channal,
+				value_message: "".into(),
+				success: true,
+			}),
+		}
+	}
+
+	// 放弃事务，主节点上的事务同样被放弃
+	async fn discard(&self, req: GetItemRequest) -> Result<String, Error> {
+		let txn = self.take_multi(&req, "DISCARD")?;
+		if let (Some(shard), Some(backend_txn_id)) = (txn.shard, txn.backend_txn_id) {
+			let session_id = req.session_id.as_ref().map(|id| id.to_string());
+			self.txn_on_shard(shard, OPCode::DISCARD, " ", Some(backend_txn_id), session_id, req.db).await?;
+		}
+		Ok("OK".to_string())
+	}
+
+	// watch 一个或多个 key，这些 key 必须位于同一个分片上，之后的事务固定到该分片上
+	// 第一次 watch 时创建事务，之后携带 txn_id 的 watch 向同一个事务添加 key
+	async fn watch(&self, req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		let session_id = req.session_id.as_ref().map(|id| id.to_string());
+		let keys: Vec<&str> = req.key_channal.split_whitespace().collect();
+		let Some(first) = keys.first() else {
+			return Err(Error::msg("ERR wrong number of arguments for WATCH"));
+		};
+		let shard = self.shard_of(first)?;
+		for key in &keys[1..] {
+			if self.shard_of(key)? != shard {
+				return Err(Error::msg("CROSSSLOT Keys in request don't hash to the same shard"));
+			}
+		}
+		let txn_id = req.txn_id.as_ref().map(|id| id.to_string());
+		let backend_txn_id = match &txn_id {
+			Some(txn_id) => {
+				let txn = self.check_owner(txn_id, &session_id)?;
+				if txn.multi {
+					return Err(Error::msg("ERR WATCH inside MULTI is not allowed"));
+				}
+				if txn.shard.is_some_and(|pinned| pinned != shard) {
+					return Err(Error::msg("CROSSSLOT Keys in request don't hash to the same shard as the transaction"));
+				}
+				txn.backend_txn_id
+			},
+			None => None,
+		};
+		let backend_txn_id = self.txn_on_shard(shard, OPCode::WATCH, &req.key_channal, backend_txn_id, session_id.clone(), req.db).await?;
+		let txn_id = match txn_id {
+			Some(txn_id) => {
+				self.update_txn(&txn_id, |txn| {
+					txn.shard = Some(shard);
+					txn.backend_txn_id = Some(backend_txn_id);
+				})?;
+				txn_id
+			},
+			None => self.new_txn(session_id, false, Some(shard), Some(backend_txn_id)),
+		};
+		Ok(S::txn_resp(req.opcode, txn_id))
+	}
+
+	// 取消 watch，尚未 MULTI 的事务会被删除，MULTI 之后与其他命令一样排队，执行时不做任何事
+	async fn unwatch(&self, req: GetItemRequest) -> Result<String, Error> {
+		let session_id = req.session_id.as_ref().map(|id| id.to_string());
+		let Some(txn_id) = req.txn_id.as_ref().map(|id| id.to_string()) else {
+			return Ok("OK".to_string());
+		};
+		if self.check_owner(&txn_id, &session_id)?.multi {
+			return Ok("QUEUED".to_string());
+		}
+		let txn = self.txns.write().unwrap().remove(&txn_id).ok_or(Error::msg("ERR no such transaction"))?;
+		if let (Some(shard), Some(backend_txn_id)) = (txn.shard, txn.backend_txn_id) {
+			self.txn_on_shard(shard, OPCode::UNWATCH, " ", Some(backend_txn_id), session_id, req.db).await?;
+		}
+		Ok("OK".to_string())
+	}
+
+	// 订阅或取消订阅，订阅属于客户端的会话，key_channal 为以空格分隔的频道（或模式），返回该会话的订阅数量
+	// 代理上新出现或者不再有订阅者的频道（或模式）会同步到所有分片的主节点
+	async fn subscription(&self, req: &GetItemRequest) -> Result<String, Error> {
+		let session_id = req.session_id.as_ref().ok_or(Error::msg("ERR the pub/sub commands need a session"))?.to_string();
+		let names: Vec<String> = req.key_channal.split_whitespace().map(|name| name.to_string()).collect();
+		let opcode = OPCode::from(req.opcode);
+		let pattern = opcode == OPCode::PSUBSCRIBE || opcode == OPCode::PUNSUBSCRIBE;
+		let subscribing = opcode == OPCode::SUBSCRIBE || opcode == OPCode::PSUBSCRIBE;
+		if subscribing && names.is_empty() {
+			return Err(Error::msg("ERR wrong number of arguments"));
+		}
+		let (count, changed) = {
+			let mut pubsub = self.pubsub.lock().unwrap();
+			match subscribing {
+				true => pubsub.subscribe(&session_id, &names, pattern),
+				false => pubsub.unsubscribe(&session_id, &names, pattern),
+			}
+		};
+		sync_subscription(&self.masters, &self.pubsub_session, opcode, changed).await;
+		Ok(count.to_string())
+	}
+
+	// PUBSUB 的子命令在 key_channal 中，参数以空格分隔放在 value_message 中
+	// 代理只统计通过代理订阅的客户端，CHANNELS 的频道以及 NUMSUB 的频道和订阅者数量以换行分隔
+	fn pubsub(&self, req: &GetItemRequest) -> Result<String, Error> {
+		let args: Vec<String> = req.value_message.split_whitespace().map(|arg| arg.to_string()).collect();
+		let pubsub = self.pubsub.lock().unwrap();
+		match req.key_channal.to_lowercase().as_str() {
+			"channels" if args.len() <= 1 => Ok(pubsub.channels(args.first().map(|pattern| pattern.as_str())).join("\n")),
+			"numsub" => Ok(
+				pubsub.numsub(&args)
+					.into_iter()
+					.map(|(channel, num)| format!("{}\n{}", channel, num))
+					.collect::<Vec<_>>()
+					.join("\n")
+			),
+			"numpat" if args.is_empty() => Ok(pubsub.numpat().to_string()),
+			// 代理上因为订阅者处理过慢而丢弃的消息，以及被断开的订阅者
+			"stats" if args.is_empty() => {
+				let stats = pubsub.stats();
+				Ok(format!(
+					"dropped_messages\n{}\ndisconnected_subscribers\n{}",
+					stats.dropped_messages, stats.disconnected_subscribers
+				))
+			},
+			_ => Err(Error::msg("ERR unknown subcommand or wrong number of arguments for PUBSUB")),
+		}
+	}
+
+	// 拉取会话的下一条消息，key_channal 为最近收到的消息的序号
+	async fn poll(&self, req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		let session_id = req.session_id.as_ref().ok_or(Error::msg("ERR the pub/sub commands need a session"))?.to_string();
+		let ack = req.key_channal.parse::<u64>().unwrap_or(0);
+		let mut resp = GetItemResponse {
+			opcode: req.opcode,
+			key_channal: req.key_channal,
+			value_message: " ".into(),
+			success: false,
+		};
+		if let Some((seq, message)) = pubsub::poll(&self.pubsub, &session_id, ack).await? {
+			resp.key_channal = seq.to_string().into();
+			resp.value_message = message.into();
+			resp.success = true;
+		}
+		Ok(resp)
+	}
+
+	// 将请求并发地发送给所有分片的主节点，任意一个分片失败则整个请求失败
+	async fn broadcast(&self, req: &GetItemRequest) -> Result<Vec<GetItemResponse>, Error> {
+		let masters = { self.masters.read().unwrap().clone() };
+		if masters.is_empty() {
+			return Err(Error::msg("No master in the cluster"));
+		}
+		let tasks = masters
+			.into_iter()
+			.map(|node| {
+				let req = req.clone();
+				tokio::spawn(async move { node.get_item(req).await })
+			})
+			.collect::<Vec<_>>();
+		let mut resps = Vec::new();
+		for task in tasks {
+			let resp = task.await??;
+			if !resp.success {
+				return Err(Error::msg(resp.value_message.to_string()));
+			}
+			resps.push(resp);
+		}
+		Ok(resps)
+	}
+
+	// 脚本与函数在一个分片上原子地执行，因此访问的 key 必须位于同一个分片上，没有 key 时在随机的分片上执行
+	// FCALL_RO 调用的函数不会写入数据，可以像 get 一样发送到分片中的任意节点，其余的发送到主节点
+	async fn eval(&self, req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		// value_message 为 `numkeys [key ...] [arg ...]`，格式错误时由主节点返回错误
+		let mut args = req.value_message.split_whitespace();
+		let numkeys = args.next().and_then(|numkeys| numkeys.parse::<usize>().ok()).unwrap_or(0);
+		let keys: Vec<&str> = args.take(numkeys).collect();
+		let shard = match keys.first() {
+			Some(first) => {
+				let shard = self.shard_of(first)?;
+				for key in &keys[1..] {
+					if self.shard_of(key)? != shard {
+						return Err(Error::msg("CROSSSLOT Keys in request don't hash to the same shard"));
+					}
+				}
+				shard
+			},
+			None => {
+				let master_num = { self.masters.read().unwrap().len() };
+				if master_num == 0 {
+					return Err(Error::msg("No master in the cluster"));
+				}
+				rand::thread_rng().gen::<usize>() % master_num
+			},
+		};
+		match OPCode::from(req.opcode) {
+			OPCode::FCALLRO => self.any_node(shard).get_item(req).await,
+			_ => self.master(shard).get_item(req).await,
+		}
+	}
+
+	// 每个分片的主节点各自缓存脚本，LOAD 与 FLUSH 发送到所有分片的主节点，EXISTS 只有脚本在所有分片上都存在时才返回 1
+	// KILL 同样发送到所有分片的主节点，任意一个分片上的脚本被终止即成功
+	// FUNCTION 与之类似，修改函数库的子命令发送到所有分片的主节点，LIST 与 DUMP 从第一个分片的主节点读取
+	async fn script(&self, req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		match req.key_channal.to_lowercase().as_str() {
+			"kill" => {
+				let masters = { self.masters.read().unwrap().clone() };
+				let mut result = Err(Error::msg("NOTBUSY No scripts in execution right now."));
+				for node in masters {
+					match node.get_item(req.clone()).await {
+						::core::result::Result::Ok(resp) if resp.success => return Ok(resp),
+						// 脚本已经写入过数据，无法终止
+						::core::result::Result::Ok(resp) if resp.value_message.starts_with("UNKILLABLE") => {
+							result = Err(Error::msg(resp.value_message.to_string()));
+						},
+						_ => {},
+					}
+				}
+				result
+			},
+			"exists" => {
+				let resps = self.broadcast(&req).await?;
+				let mut exists: Vec<bool> = resps[0].value_message.lines().map(|line| line == "1").collect();
+				for resp in &resps[1..] {
+					for (exists, line) in exists.iter_mut().zip(resp.value_message.lines()) {
+						*exists = *exists && line == "1";
+					}
+				}
+				let mut resp = resps[0].clone();
+				resp.value_message = exists.iter().map(|exists| (*exists as i32).to_string()).collect::<Vec<_>>().join("\n").into();
+				Ok(resp)
+			},
+			"list" | "dump" if req.opcode == OPCode::FUNCTION as i32 => {
+				if self.masters.read().unwrap().is_empty() {
+					return Err(Error::msg("No master in the cluster"));
+				}
+				self.master(0).get_item(req).await
+			},
+			_ => Ok(self.broadcast(&req).await?.remove(0)),
+		}
+	}
+
+	// 作用于整个 keyspace 的命令，需要访问所有分片，FLUSHDB 与 SWAPDB 同样发送到所有分片的主节点
+	async fn keyspace(&self, req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		let mut resp = GetItemResponse {
+			opcode: req.opcode,
+			key_channal: req.key_channal.clone(),
+			value_message: " ".into(),
+			success: true,
+		};
+		match OPCode::from(req.opcode) {
+			OPCode::SCAN => return self.scan(req).await,
+			OPCode::KEYS => {
+				let mut keys = Vec::new();
+				for shard_resp in self.broadcast(&req).await? {
+					keys.extend(shard_resp.value_message.lines().filter(|key| !key.is_empty()).map(|key| key.to_string()));
+				}
+				keys.sort();
+				resp.value_message = keys.join("\n").into();
+			},
+			OPCode::DBSIZE => {
+				resp.value_message = self.dbsize(&req).await?.iter().sum::<usize>().to_string().into();
+			},
+			OPCode::FLUSHALL | OPCode::FLUSHDB | OPCode::SWAPDB => {
+				self.broadcast(&req).await?;
+				resp.value_message = "OK".into();
+			},
+			_ => return self.random_key(req).await,
+		}
+		Ok(resp)
+	}
+
+	// MEMORY USAGE 发送到 key 所在分片的主节点，MEMORY STATS 汇总所有分片的主节点，数值为各分片之和，淘汰策略取第一个分片的
+	async fn memory(&self, req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		if !req.key_channal.eq_ignore_ascii_case("stats") {
+			let key = req.value_message.split_whitespace().next().unwrap_or_default().to_string();
+			return self.master(self.shard_of(&key)?).get_item(req).await;
+		}
+		let resps = self.broadcast(&req).await?;
+		// 各分片返回的统计项的名称与值逐行交替排列
+		let mut stats: Vec<(String, String)> = Vec::new();
+		for resp in &resps {
+			let lines: Vec<&str> = resp.value_message.lines().collect();
+			for (index, pair) in lines.chunks(2).enumerate() {
+				let [name, value] = pair else {
+					continue;
+				};
+				match stats.get_mut(index) {
+					Some((_, total)) => {
+						if let (::core::result::Result::Ok(sum), ::core::result::Result::Ok(value)) = (total.parse::<u64>(), value.parse::<u64>()) {
+							*total = (sum + value).to_string();
+						}
+					},
+					None => stats.push((name.to_string(), value.to_string())),
+				}
+			}
+		}
+		let mut resp = resps[0].clone();
+		resp.value_message = stats.iter().map(|(name, value)| format!("{}\n{}", name, value)).collect::<Vec<_>>().join("\n").into();
+		Ok(resp)
+	}
+
+	// INFO 的 Server、Clients 与 Replication 段由代理自身输出，其余的段汇总所有分片的主节点
+	// 汇总时数值为各分片之和，keyspace 中各数据库的 keys 与 expires 同样求和，其他值取第一个分片的
+	// Stats 段中的请求数与订阅相关的统计是代理自身的，客户端的订阅都在代理上
+	async fn info(&self, req: &GetItemRequest) -> Result<String, Error> {
+		let section = req.key_channal.trim().to_lowercase();
+		let sections: Vec<&str> = match section.as_str() {
+			"" | "all" | "default" | "everything" => INFO_SECTIONS.to_vec(),
+			section => INFO_SECTIONS.iter().copied().filter(|name| *name == section).collect(),
+		};
+		let mut backend: Vec<(String, Vec<(String, String)>)> = Vec::new();
+		if sections.iter().any(|section| BACKEND_SECTIONS.contains(section)) {
+			let mut req = req.clone();
+			req.txn_id = None;
+			for resp in self.broadcast(&req).await? {
+				merge_info(&mut backend, &resp.value_message);
+			}
+		}
+		let mut report = Vec::new();
+		for section in sections {
+			let mut fields: Vec<(String, String)> = backend
+				.iter()
+				.find(|(name, _)| name == section)
+				.map(|(_, fields)| fields.clone())
+				.unwrap_or_default();
+			let own: Vec<(&str, String)> = match section {
+				"server" => {
+					let uptime = self.metrics.uptime().as_secs();
+					vec![
+						("redis_proxy_version", env!("CARGO_PKG_VERSION").to_string()),
+						("process_id", std::process::id().to_string()),
+						("uptime_in_seconds", uptime.to_string()),
+						("uptime_in_days", (uptime / 86400).to_string()),
+					]
+				},
+				"clients" => vec![
+					("pubsub_clients", self.pubsub.lock().unwrap().num_subscribers().to_string()),
+					("open_transactions", self.txns.read().unwrap().len().to_string()),
+				],
+				"stats" => {
+					let pubsub = self.pubsub.lock().unwrap();
+					let stats = pubsub.stats();
+					vec![
+						("total_commands_processed", self.metrics.total_commands().to_string()),
+						("instantaneous_ops_per_sec", self.metrics.ops_per_sec().to_string()),
+						("total_error_replies", self.metrics.total_errors().to_string()),
+						("pubsub_channels", pubsub.num_channels().to_string()),
+						("pubsub_patterns", pubsub.numpat().to_string()),
+						("pubsub_dropped_messages", stats.dropped_messages.to_string()),
+						("pubsub_disconnected_subscribers", stats.disconnected_subscribers.to_string()),
+					]
+				},
+				_ => Vec::new(),
+			};
+			for (name, value) in own {
+				match fields.iter_mut().find(|(field, _)| field == name) {
+					Some((_, field)) => *field = value,
+					None => fields.push((name.to_string(), value)),
+				}
+			}
+			// 每个分片的主节点与从节点，以及它们最近一次访问的状态
+			if section == "replication" {
+				let masters = { self.masters.read().unwrap().clone() };
+				let slaves = { self.slaves.read().unwrap().clone() };
+				let status = |node: &Node| match node.health.is_down() {
+					true => "down",
+					false => "up",
+				};
+				fields.push(("role".to_string(), "proxy".to_string()));
+				fields.push(("shards".to_string(), masters.len().to_string()));
+				for (shard, master) in masters.iter().enumerate() {
+					fields.push((
+						format!("shard{}", shard),
+						format!("master={},status={},slaves={}", master.addr, status(master), slaves[shard].len()),
+					));
+					for (index, slave) in slaves[shard].iter().enumerate() {
+						fields.push((format!("shard{}_slave{}", shard, index), format!("addr={},status={}", slave.addr, status(slave))));
+					}
+				}
+			}
+			let mut lines = vec![format!("# {}{}", section[..1].to_uppercase(), &section[1..])];
+			lines.extend(fields.iter().map(|(name, value)| format!("{}:{}", name, value)));
+			report.push(lines.join("\n"));
+		}
+		Ok(report.join("\n\n"))
+	}
+
+	// 各分片的数据库数量相同，由第一个分片的主节点检查数据库的编号
+	async fn select(&self, mut req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		if self.masters.read().unwrap().is_empty() {
+			return Err(Error::msg("No master in the cluster"));
+		}
+		req.txn_id = None;
+		self.master(0).get_item(req).await
+	}
+
+	// 主节点的配置，CONFIG SET 发送到所有分片的主节点，CONFIG GET 从第一个分片的主节点读取
+	async fn config_command(&self, req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		match req.key_channal.to_lowercase() == "get" {
 			true => {
-				// 获得对应集群的节点数量
-				let mut node_num = { self.slaves.read().unwrap()[master_id].len() };
-				node_num = node_num + 1;
-				// 生成随机数
-				let mut rng = rand::thread_rng();
-				let mut node_id: usize = rng.gen();
-				// 将随机数对节点数量做模
-				node_id = node_id % node_num;
-				if node_id == node_num - 1 {
-					// get主节点
-					log::info!("{}", format!("master {}", master_id));
-					self.masters.read().unwrap()[master_id].clone()
-				} else {
-					// get从节点
-					log::info!("{}", format!("master {} slave {}", master_id, node_id));
-					self.slaves.read().unwrap()[master_id][node_id].clone()
+				if self.masters.read().unwrap().is_empty() {
+					return Err(Error::msg("No master in the cluster"));
 				}
+				self.master(0).get_item(req).await
+			},
+			false => Ok(self.broadcast(&req).await?.remove(0)),
+		}
+	}
+
+	// 获得每个分片的 key 的数量
+	async fn dbsize(&self, req: &GetItemRequest) -> Result<Vec<usize>, Error> {
+		let mut req = req.clone();
+		req.opcode = OPCode::DBSIZE as i32;
+		self.broadcast(&req)
+			.await?
+			.iter()
+			.map(|resp| resp.value_message.parse::<usize>().map_err(|_| Error::msg("Invalid response of DBSIZE")))
+			.collect()
+	}
+
+	// 按照各分片 key 的数量加权选择分片，使得每个 key 被选中的概率相同
+	async fn random_key(&self, req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		let sizes = self.dbsize(&req).await?;
+		let total: usize = sizes.iter().sum();
+		if total == 0 {
+			return Ok(GetItemResponse {
+				opcode: req.opcode,
+				key_channal: req.key_channal,
+				value_message: "(nil)".into(),
+				success: false,
+			});
+		}
+		let mut index = rand::thread_rng().gen_range(0..total);
+		let mut shard = 0;
+		while index >= sizes[shard] {
+			index -= sizes[shard];
+			shard += 1;
+		}
+		self.master(shard).get_item(req).await
+	}
 
+	// 代理上的游标由分片编号和该分片主节点的游标组成，形如 "shard:cursor"，"0" 表示开始或者结束
+	// 依次扫描每个分片，一个分片扫描结束后从下一个分片的游标 0 开始
+	async fn scan(&self, mut req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		let invalid = || Error::msg("ERR invalid cursor");
+		let (shard, cursor) = match req.key_channal.as_str() {
+			"0" => (0, "0".to_string()),
+			cursor => {
+				let (shard, cursor) = cursor.split_once(':').ok_or_else(invalid)?;
+				(shard.parse::<usize>().map_err(|_| invalid())?, cursor.to_string())
 			},
+		};
+		let shard_num = { self.masters.read().unwrap().len() };
+		if shard >= shard_num {
+			return Err(invalid());
+		}
+		req.key_channal = cursor.into();
+		let mut resp = self.master(shard).get_item(req).await?;
+		if resp.success {
+			let next = match resp.key_channal.as_str() {
+				"0" if shard + 1 == shard_num => "0".to_string(),
+				"0" => format!("{}:0", shard + 1),
+				next => format!("{}:{}", shard, next),
+			};
+			resp.key_channal = next.into();
+		}
+		Ok(resp)
+	}
+
+	// 根据 key 选择节点并转发请求
+	async fn forward(&self, _req: GetItemRequest) -> Result<GetItemResponse, Error> {
+		// 获得将要访问的节点的id
+		let master_id = self.shard_of(&_req.key_channal)?;
+
+		// 获得访问节点的客户端，若为get操作，则从
+		let node = match _req.opcode == OPCode::GET as i32 && _req.txn_id.is_none() {
+			true => self.any_node(master_id),
 			false => {
 				log::info!("{}", format!("master {}", master_id));
 				self.masters.read().unwrap()[master_id].clone()
 			},
 		};
-		match rpc_cli.get_item(_req).await {
-			::core::result::Result::Ok(resp) => {
-				Ok(resp)
+		node.get_item(_req).await
+	}
+}
+
+// 将一个分片的 INFO 合并到 sections 中，只保留由后端节点汇总的段
+// 同名的字段由 sum_info_value 相加，UNSUMMED_FIELDS 中的字段保留第一个分片的值，只有部分分片输出的段与字段同样会被保留
+fn merge_info(sections: &mut Vec<(String, Vec<(String, String)>)>, info: &str) {
+	let mut current: Option<usize> = None;
+	for line in info.lines() {
+		if let Some(name) = line.strip_prefix("# ") {
+			let name = name.trim().to_lowercase();
+			current = match BACKEND_SECTIONS.contains(&name.as_str()) {
+				true => Some(match sections.iter().position(|(section, _)| *section == name) {
+					Some(position) => position,
+					None => {
+						sections.push((name, Vec::new()));
+						sections.len() - 1
+					},
+				}),
+				false => None,
+			};
+			continue;
+		}
+		let (Some(position), Some((name, value))) = (current, line.split_once(':')) else {
+			continue;
+		};
+		let fields = &mut sections[position].1;
+		match fields.iter_mut().find(|(field, _)| field == name) {
+			Some((_, total)) if !UNSUMMED_FIELDS.contains(&name) => *total = sum_info_value(total, value),
+			Some(_) => {},
+			None => fields.push((name.to_string(), value.to_string())),
+		}
+	}
+}
+
+// 数值直接相加，keyspace 中 `keys=1,expires=0` 形式的值逐项相加，其他值保持不变
+fn sum_info_value(total: &str, value: &str) -> String {
+	if let (::core::result::Result::Ok(sum), ::core::result::Result::Ok(value)) = (total.parse::<i64>(), value.parse::<i64>()) {
+		return (sum + value).to_string();
+	}
+	let items: Vec<(&str, &str)> = total.split(',').filter_map(|item| item.split_once('=')).collect();
+	let values: Vec<(&str, &str)> = value.split(',').filter_map(|item| item.split_once('=')).collect();
+	if items.is_empty() || items.len() != values.len() {
+		return total.to_string();
+	}
+	items
+		.iter()
+		.zip(values.iter())
+		.map(|((name, sum), (_, value))| match (sum.parse::<i64>(), value.parse::<i64>()) {
+			(::core::result::Result::Ok(sum), ::core::result::Result::Ok(value)) => format!("{}={}", name, sum + value),
+			_ => format!("{}={}", name, sum),
+		})
+		.collect::<Vec<_>>()
+		.join(",")
+}
+
+// 将错误信息包装成响应
+fn error_resp(opcode: i32, e: Error) -> GetItemResponse {
+	GetItemResponse {
+		opcode,
+		key_channal: " ".into(),
+		value_message: e.to_string().into(),
+		success: false,
+	}
+}
+
+#[volo::async_trait]
+impl volo_gen::volo::example::ItemService for S {
+	async fn get_item(&self, mut _req: volo_gen::volo::example::GetItemRequest) -> ::core::result::Result<volo_gen::volo::example::GetItemResponse, ::volo_thrift::AnyhowError>{
+		let mut resp = GetItemResponse {
+			opcode: _req.opcode,
+			key_channal: _req.key_channal.clone(),
+			value_message: " ".into(),
+			success: false,
+		};
+		let opcode = _req.opcode;
+		// 只 watch 了 key 而尚未 MULTI 的事务不影响其他命令的执行，去掉 txn_id 以免主节点把它当作自己的事务
+		let watching = _req.txn_id.as_ref().is_some_and(|id| self.txns.read().unwrap().get(id.as_str()).is_some_and(|txn| !txn.multi));
+		if watching && !matches!(
+			OPCode::from(opcode),
+			OPCode::MULTI | OPCode::EXEC | OPCode::WATCH | OPCode::DISCARD | OPCode::UNWATCH
+		) {
+			_req.txn_id = None;
+		}
+		// 通过代理开启的事务中只能使用作用于单个 key 的命令，其他命令会使事务在 EXEC 时被放弃
+		let in_multi = _req.txn_id.as_ref().is_some_and(|id| self.txns.read().unwrap().get(id.as_str()).is_some_and(|txn| txn.multi));
+		if in_multi && !matches!(
+			OPCode::from(opcode),
+			OPCode::GET | OPCode::SET | OPCode::DEL | OPCode::PUBLISH | OPCode::PING | OPCode::EXPIRE | OPCode::TTL | OPCode::PERSIST
+				| OPCode::MOVE | OPCode::SELECT | OPCode::MULTI | OPCode::EXEC | OPCode::WATCH | OPCode::DISCARD | OPCode::UNWATCH
+		) {
+			if let Some(txn_id) = &_req.txn_id {
+				self.abort(txn_id);
+			}
+			return Ok(error_resp(opcode, Error::msg("ERR the command is not allowed in a transaction through the proxy")));
+		}
+		let result = match OPCode::from(_req.opcode) {
+			// 过滤主节点同步操作
+			OPCode::SETMASTER | OPCode::DELMASTER | OPCode::FLUSHMASTER | OPCode::EXECMASTER | OPCode::FUNCTIONMASTER | OPCode::EXPIREMASTER => {
+				return Err(Error::msg("Can't not handle master operations."));
+			},
+			OPCode::AUTH | OPCode::ACL => return Err(Error::msg("AUTH and ACL are handled by the AclLayer")),
+			OPCode::FILTER => return Err(Error::msg("FILTER is handled by the FilterLayer")),
+			OPCode::SLOWLOG => return Err(Error::msg("SLOWLOG is handled by the SlowLogLayer")),
+			// 如果是ping操作，直接返回相关信息
+			OPCode::PING => Ok(_req.value_message.to_string()),
+			// 集群拓扑的管理命令由代理自身处理
+			OPCode::TOPOLOGY => Ok(self.topology().await),
+			OPCode::ADDSLAVE => self.add_slave(&_req).await,
+			OPCode::DELSLAVE => self.del_slave(&_req),
+			OPCode::REPLACEMASTER => self.replace_master(&_req).await,
+			// 事务相关的命令需要固定到同一个分片上
+			OPCode::MULTI => return Ok(self.multi(_req).await.unwrap_or_else(|e| error_resp(opcode, e))),
+			OPCode::EXEC => return Ok(self.exec(_req).await.unwrap_or_else(|e| error_resp(opcode, e))),
+			OPCode::WATCH => return Ok(self.watch(_req).await.unwrap_or_else(|e| error_resp(opcode, e))),
+			OPCode::DISCARD => self.discard(_req).await,
+			OPCode::UNWATCH => self.unwatch(_req).await,
+			// 作用于整个 keyspace 的命令需要汇总所有分片的结果
+			// 数据库的编号由客户端在每个请求中携带，SELECT 只需检查编号是否有效，即使在事务中也不必排队
+			OPCode::SELECT => return Ok(self.select(_req).await.unwrap_or_else(|e| error_resp(opcode, e))),
+			OPCode::SCAN | OPCode::KEYS | OPCode::DBSIZE | OPCode::FLUSHALL | OPCode::FLUSHDB | OPCode::SWAPDB | OPCode::RANDOMKEY => {
+				return Ok(self.keyspace(_req).await.unwrap_or_else(|e| error_resp(opcode, e)));
+			},
+			OPCode::CONFIG => return Ok(self.config_command(_req).await.unwrap_or_else(|e| error_resp(opcode, e))),
+			OPCode::MEMORY => return Ok(self.memory(_req).await.unwrap_or_else(|e| error_resp(opcode, e))),
+			OPCode::INFO => self.info(&_req).await,
+			// 脚本与函数转发到其访问的 key 所在的分片，脚本缓存与函数库的管理命令发送到所有分片
+			OPCode::EVAL | OPCode::EVALSHA | OPCode::FCALL | OPCode::FCALLRO => {
+				return Ok(self.eval(_req).await.unwrap_or_else(|e| error_resp(opcode, e)));
+			},
+			OPCode::SCRIPT | OPCode::FUNCTION => return Ok(self.script(_req).await.unwrap_or_else(|e| error_resp(opcode, e))),
+			// 订阅由代理负责，代理向所有分片订阅后再把消息转发给客户端
+			OPCode::SUBSCRIBE | OPCode::PSUBSCRIBE | OPCode::UNSUBSCRIBE | OPCode::PUNSUBSCRIBE => self.subscription(&_req).await,
+			OPCode::PUBSUB => self.pubsub(&_req),
+			OPCode::POLL => return Ok(self.poll(_req).await.unwrap_or_else(|e| error_resp(opcode, e))),
+			_ => {
+				let txn_id = _req.txn_id.as_ref().map(|id| id.to_string());
+				let in_txn = txn_id.as_ref().is_some_and(|id| self.txns.read().unwrap().contains_key(id));
+				return match (txn_id, in_txn) {
+					(Some(txn_id), true) => Ok(self.queue(txn_id, _req).await.unwrap_or_else(|e| error_resp(opcode, e))),
+					// 客户端只会拿到代理分配的 txn_id，未找到说明事务不存在或者已经空闲超时
+					(Some(_), false) => Ok(error_resp(opcode, Error::msg("ERR no such transaction"))),
+					_ => self.forward(_req).await,
+				};
+			},
+		};
+		match result {
+			::core::result::Result::Ok(message) => {
+				resp.value_message = message.into();
+				resp.success = true;
 			},
 			::core::result::Result::Err(e) => {
-				Err(::volo_thrift::AnyhowError::from(anyhow::Error::msg(e)))
-			}
+				resp.value_message = e.to_string().into();
+			},
 		}
-		
+		Ok(resp)
 	}
 }
 
@@ -142,23 +1587,70 @@ where
     async fn call(&self, cx: &mut Cx, req: Req) -> Result<S::Response, S::Error> {
         let now = std::time::Instant::now();
         tracing::debug!("Received request {:?}", &req);
-		// 中间件希望过滤掉kv对中涉及的不文明词汇，由于不文明词汇的判断比较复杂，此处演示过滤词汇"傻逼"
-		let info: Vec<char> = format!("{req:?}").chars().collect();
-		let mut can_send: bool = true;
-		for i in 0..(info.len() - 1) {
-			if info[i] == '傻' && info[i + 1] == '逼' {
-				can_send = false;
-				break;
-			}
-		}
-		if can_send {
-			let resp = self.0.call(cx, req).await;
-			tracing::debug!("Sent response {:?}", &resp);
-			tracing::info!("Request took {}ms", now.elapsed().as_millis());	
-			return resp;
-		}
-		// panic!("命令中有敏感词“傻逼");
-		Err(S::Error::from(Error::msg("命令中有敏感词'傻逼'")))
+		let resp = self.0.call(cx, req).await;
+		tracing::debug!("Sent response {:?}", &resp);
+		tracing::info!("Request took {}ms", now.elapsed().as_millis());
+		resp
     }
 }
 
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn merged(infos: &[&str]) -> Vec<(String, Vec<(String, String)>)> {
+		let mut sections = Vec::new();
+		for info in infos {
+			merge_info(&mut sections, info);
+		}
+		sections
+	}
+
+	fn field<'a>(sections: &'a [(String, Vec<(String, String)>)], section: &str, name: &str) -> Option<&'a str> {
+		let (_, fields) = sections.iter().find(|(current, _)| current == section)?;
+		fields.iter().find(|(current, _)| current == name).map(|(_, value)| value.as_str())
+	}
+
+	#[test]
+	fn merge_info_sums_the_stats() {
+		let sections = merged(&[
+			"# Memory\nused_memory:100\nmaxmemory_policy:noeviction\n# Stats\ntotal_commands_processed:5\n# Keyspace\ndb0:keys=1,expires=0\n",
+			"# Memory\nused_memory:50\nmaxmemory_policy:noeviction\n# Stats\ntotal_commands_processed:7\n# Keyspace\ndb0:keys=2,expires=1\n",
+		]);
+		assert_eq!(field(&sections, "memory", "used_memory"), Some("150"));
+		assert_eq!(field(&sections, "memory", "maxmemory_policy"), Some("noeviction"));
+		assert_eq!(field(&sections, "stats", "total_commands_processed"), Some("12"));
+		assert_eq!(field(&sections, "keyspace", "db0"), Some("keys=3,expires=1"));
+	}
+
+	#[test]
+	fn merge_info_keeps_the_flags() {
+		let sections = merged(&[
+			"# Persistence\naof_enabled:1\naof_last_rewrite_time_sec:3\n",
+			"# Persistence\naof_enabled:1\naof_last_rewrite_time_sec:4\n",
+		]);
+		assert_eq!(field(&sections, "persistence", "aof_enabled"), Some("1"));
+		assert_eq!(field(&sections, "persistence", "aof_last_rewrite_time_sec"), Some("3"));
+	}
+
+	#[test]
+	fn merge_info_keeps_what_only_one_node_reports() {
+		let sections = merged(&[
+			"# Server\nprocess_id:1\n# Keyspace\ndb0:keys=1,expires=0\n",
+			"# Persistence\naof_enabled:1\n# Keyspace\ndb0:keys=2,expires=0\ndb1:keys=4,expires=2\n",
+		]);
+		assert_eq!(field(&sections, "keyspace", "db0"), Some("keys=3,expires=0"));
+		assert_eq!(field(&sections, "keyspace", "db1"), Some("keys=4,expires=2"));
+		assert_eq!(field(&sections, "persistence", "aof_enabled"), Some("1"));
+		// server 由代理自身输出，不从后端节点汇总
+		assert!(sections.iter().all(|(section, _)| section != "server"));
+		assert_eq!(sections.iter().map(|(section, _)| section.as_str()).collect::<Vec<_>>(), vec!["keyspace", "persistence"]);
+	}
+
+	#[test]
+	fn sum_info_value_keeps_mismatched_values() {
+		assert_eq!(sum_info_value("3", "4"), "7");
+		assert_eq!(sum_info_value("keys=1,expires=0", "keys=2"), "keys=1,expires=0");
+		assert_eq!(sum_info_value("noeviction", "allkeys-lru"), "noeviction");
+	}
+}
@@ -0,0 +1,287 @@
+use std::{
+	collections::HashMap,
+	fmt::Display,
+	io,
+	net::SocketAddr,
+	sync::{Arc, Weak, atomic::{AtomicU64, Ordering}},
+	time::{Duration, Instant},
+};
+use anyhow::Error;
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::{TcpListener, TcpStream},
+};
+use volo_gen::volo::example::{ItemServiceGetItemResultSend, ItemServiceRequestRecv, ItemServiceResponseSend};
+
+use crate::acl::COMMANDS;
+
+// 延迟直方图各个桶的上界，单位为秒
+const BUCKETS: &[f64] = &[0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+// 没有在这段时间内发送完整请求的抓取会被断开
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+// 抓取请求的最大长度，只需要其中的请求行
+const MAX_SCRAPE_REQUEST: usize = 8192;
+// 统计每秒请求数的间隔
+const OPS_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+// 延迟直方图，输出时对各个桶累加
+pub struct Histogram {
+	buckets: Vec<AtomicU64>,	// 落在该上界与前一个上界之间的次数
+	sum_us: AtomicU64,
+	count: AtomicU64,
+}
+
+impl Default for Histogram {
+	fn default() -> Histogram {
+		Histogram {
+			buckets: BUCKETS.iter().map(|_| AtomicU64::new(0)).collect(),
+			sum_us: AtomicU64::new(0),
+			count: AtomicU64::new(0),
+		}
+	}
+}
+
+impl Histogram {
+	pub fn observe(&self, elapsed: Duration) {
+		let seconds = elapsed.as_secs_f64();
+		if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+			self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+		}
+		self.sum_us.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+		self.count.fetch_add(1, Ordering::Relaxed);
+	}
+
+	fn render(&self, out: &mut Exposition, name: &str, labels: &[(&str, &str)]) {
+		let mut cumulated = 0;
+		for (bound, bucket) in BUCKETS.iter().zip(self.buckets.iter()) {
+			cumulated += bucket.load(Ordering::Relaxed);
+			let le = bound.to_string();
+			let mut bucket_labels = labels.to_vec();
+			bucket_labels.push(("le", le.as_str()));
+			out.sample(&format!("{}_bucket", name), &bucket_labels, cumulated);
+		}
+		let count = self.count.load(Ordering::Relaxed);
+		let mut bucket_labels = labels.to_vec();
+		bucket_labels.push(("le", "+Inf"));
+		out.sample(&format!("{}_bucket", name), &bucket_labels, count);
+		out.sample(&format!("{}_sum", name), labels, self.sum_us.load(Ordering::Relaxed) as f64 / 1e6);
+		out.sample(&format!("{}_count", name), labels, count);
+	}
+}
+
+// prometheus 的文本格式
+#[derive(Default)]
+pub struct Exposition(String);
+
+impl Exposition {
+	pub fn header(&mut self, name: &str, kind: &str, help: &str) {
+		self.0.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+	}
+
+	pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+		self.0.push_str(name);
+		if !labels.is_empty() {
+			let labels: Vec<String> = labels
+				.iter()
+				.map(|(label, value)| format!("{}=\"{}\"", label, value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+				.collect();
+			self.0.push_str(&format!("{{{}}}", labels.join(",")));
+		}
+		self.0.push_str(&format!(" {}\n", value));
+	}
+
+	pub fn into_string(self) -> String {
+		self.0
+	}
+}
+
+#[derive(Default)]
+struct CommandStats {
+	calls: AtomicU64,
+	errors: AtomicU64,
+	latency: Histogram,
+}
+
+// Metrics 保存代理运行时更新的计数器，后端节点的健康状态等指标在每次抓取时读取
+// 命令的统计保存在创建时就确定的表中，因此记录请求时不需要加锁
+pub struct Metrics {
+	started: Instant,
+	commands: Vec<(&'static str, CommandStats)>,
+	index: HashMap<i32, usize>,		// 操作码到其在 commands 中的位置
+	ops_per_sec: AtomicU64,			// 最近一个 OPS_SAMPLE_INTERVAL 内的请求数
+}
+
+impl Default for Metrics {
+	fn default() -> Metrics {
+		let mut commands: Vec<(&'static str, CommandStats)> = COMMANDS.iter().map(|(_, name, _)| (*name, CommandStats::default())).collect();
+		let index = COMMANDS.iter().enumerate().map(|(position, (opcode, _, _))| (*opcode, position)).collect();
+		commands.push(("unknown", CommandStats::default()));
+		Metrics {
+			started: Instant::now(),
+			commands,
+			index,
+			ops_per_sec: AtomicU64::new(0),
+		}
+	}
+}
+
+impl Metrics {
+	pub fn record(&self, opcode: i32, elapsed: Duration, error: bool) {
+		let position = self.index.get(&opcode).copied().unwrap_or(self.commands.len() - 1);
+		let stats = &self.commands[position].1;
+		stats.calls.fetch_add(1, Ordering::Relaxed);
+		if error {
+			stats.errors.fetch_add(1, Ordering::Relaxed);
+		}
+		stats.latency.observe(elapsed);
+	}
+
+	pub fn uptime(&self) -> Duration {
+		self.started.elapsed()
+	}
+
+	pub fn total_commands(&self) -> u64 {
+		self.commands.iter().map(|(_, stats)| stats.calls.load(Ordering::Relaxed)).sum()
+	}
+
+	pub fn total_errors(&self) -> u64 {
+		self.commands.iter().map(|(_, stats)| stats.errors.load(Ordering::Relaxed)).sum()
+	}
+
+	pub fn ops_per_sec(&self) -> u64 {
+		self.ops_per_sec.load(Ordering::Relaxed)
+	}
+
+	pub fn render(&self, out: &mut Exposition) {
+		out.header("redis_proxy_commands_total", "counter", "The requests of each command");
+		for (name, stats) in self.commands.iter() {
+			out.sample("redis_proxy_commands_total", &[("command", *name)], stats.calls.load(Ordering::Relaxed));
+		}
+		out.header("redis_proxy_command_errors_total", "counter", "The requests of each command answered with an error");
+		for (name, stats) in self.commands.iter() {
+			out.sample("redis_proxy_command_errors_total", &[("command", *name)], stats.errors.load(Ordering::Relaxed));
+		}
+		out.header("redis_proxy_command_duration_seconds", "histogram", "The latency of the requests of each command");
+		for (name, stats) in self.commands.iter().filter(|(_, stats)| stats.calls.load(Ordering::Relaxed) > 0) {
+			stats.latency.render(out, "redis_proxy_command_duration_seconds", &[("command", *name)]);
+		}
+	}
+}
+
+// 统计每个间隔内的请求数，即 INFO 输出的每秒请求数
+pub fn start_ops_sampler(metrics: Weak<Metrics>) {
+	tokio::spawn(async move {
+		let mut last = 0;
+		loop {
+			tokio::time::sleep(OPS_SAMPLE_INTERVAL).await;
+			let Some(metrics) = metrics.upgrade() else {
+				break;
+			};
+			let total = metrics.total_commands();
+			let ops = (total - last) as f64 / OPS_SAMPLE_INTERVAL.as_secs_f64();
+			metrics.ops_per_sec.store(ops as u64, Ordering::Relaxed);
+			last = total;
+		}
+	});
+}
+
+// MetricsLayer 统计每个命令的请求数、错误数以及延迟
+// 它位于 AclLayer 之前，因此被拒绝的请求也会被统计
+pub struct MetricsLayer(pub Arc<Metrics>);
+
+impl<S> volo::Layer<S> for MetricsLayer {
+	type Service = MetricsService<S>;
+
+	fn layer(self, inner: S) -> Self::Service {
+		MetricsService { inner, metrics: self.0 }
+	}
+}
+
+#[derive(Clone)]
+pub struct MetricsService<S> {
+	inner: S,
+	metrics: Arc<Metrics>,
+}
+
+#[volo::service]
+impl<S> volo::Service<volo_thrift::context::ServerContext, ItemServiceRequestRecv> for MetricsService<S>
+where
+	S: volo::Service<volo_thrift::context::ServerContext, ItemServiceRequestRecv, Response = ItemServiceResponseSend, Error = Error>
+		+ Send
+		+ Sync
+		+ 'static,
+{
+	async fn call(
+		&self,
+		cx: &mut volo_thrift::context::ServerContext,
+		req: ItemServiceRequestRecv,
+	) -> Result<ItemServiceResponseSend, Error> {
+		let ItemServiceRequestRecv::GetItem(args) = &req;
+		let opcode = args.req.opcode;
+		let now = Instant::now();
+		let result = self.inner.call(cx, req).await;
+		// (nil) 表示 key 不存在，不是错误
+		let error = match &result {
+			Ok(ItemServiceResponseSend::GetItem(ItemServiceGetItemResultSend::Ok(resp))) => !resp.success && resp.value_message != "(nil)",
+			_ => true,
+		};
+		self.metrics.record(opcode, now.elapsed(), error);
+		result
+	}
+}
+
+// 通过 http 的 GET /metrics 提供 render 输出的指标
+pub async fn serve_metrics<F>(addr: SocketAddr, render: F) -> io::Result<()>
+where
+	F: Fn() -> String + Send + Sync + 'static,
+{
+	let listener = TcpListener::bind(addr).await?;
+	tracing::info!("Serving metrics on http://{}/metrics", addr);
+	let render = Arc::new(render);
+	loop {
+		let (mut stream, peer) = match listener.accept().await {
+			Ok(accepted) => accepted,
+			Err(e) => {
+				tracing::warn!("Failed to accept a scrape on {}: {}", addr, e);
+				tokio::time::sleep(Duration::from_millis(10)).await;
+				continue;
+			},
+		};
+		let render = render.clone();
+		tokio::spawn(async move {
+			if let Err(e) = respond(&mut stream, &*render).await {
+				tracing::debug!("Failed to serve the scrape of {}: {}", peer, e);
+			}
+		});
+	}
+}
+
+// 只检查请求行，回复后关闭连接
+async fn respond(stream: &mut TcpStream, render: &(dyn Fn() -> String + Send + Sync)) -> io::Result<()> {
+	let mut request = Vec::new();
+	let mut chunk = [0u8; 1024];
+	while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < MAX_SCRAPE_REQUEST {
+		let read = tokio::time::timeout(SCRAPE_TIMEOUT, stream.read(&mut chunk))
+			.await
+			.map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "the scrape timed out"))??;
+		if read == 0 {
+			return Ok(());
+		}
+		request.extend_from_slice(&chunk[..read]);
+	}
+	let request = String::from_utf8_lossy(&request);
+	let mut parts = request.split_whitespace();
+	let (status, body) = match (parts.next(), parts.next().and_then(|target| target.split('?').next())) {
+		(Some("GET"), Some("/metrics")) => ("200 OK", render()),
+		(Some("GET"), _) => ("404 Not Found", "Not Found\n".to_string()),
+		_ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+	};
+	let response = format!(
+		"HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+		status,
+		body.len(),
+		body
+	);
+	stream.write_all(response.as_bytes()).await?;
+	stream.shutdown().await
+}
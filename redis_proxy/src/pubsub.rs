@@ -0,0 +1,346 @@
+use std::{
+	collections::{HashMap, HashSet, VecDeque},
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+use anyhow::Error;
+use tokio::sync::watch;
+
+use crate::glob_match;
+
+// 超过该时间没有拉取消息的订阅者被认为已经断开，其订阅会被删除
+const SUBSCRIBER_IDLE: Duration = Duration::from_secs(60);
+// 清理断开的订阅者的间隔
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+// 拉取消息时最长的等待时间，没有消息时客户端会重新拉取
+pub const POLL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// 订阅者未确认的消息的大小限制，单位为字节，0 表示不限制
+// 超过硬限制的消息会被丢弃，并告知订阅者丢弃的数量；超过软限制的时间长于 soft_duration 的订阅者会被断开
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BufferLimits {
+	pub hard: usize,
+	pub soft: usize,
+	pub soft_duration: Duration,
+}
+
+impl Default for BufferLimits {
+	fn default() -> BufferLimits {
+		BufferLimits {
+			hard: 32 * 1024 * 1024,
+			soft: 8 * 1024 * 1024,
+			soft_duration: Duration::from_secs(60),
+		}
+	}
+}
+
+// 代理启动以来处理过慢的订阅者的统计
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PubSubStats {
+	pub dropped_messages: u64,
+	pub disconnected_subscribers: u64,
+}
+
+// 订阅者队列中的一项
+enum Pending {
+	Message(String),
+	Dropped(u64),		// 在此处丢弃的消息数量，以 "dropped\n<count>" 的形式发送
+}
+
+// 一个会话的订阅以及尚未确认的消息
+struct Subscriber {
+	channels: HashSet<String>,
+	patterns: HashSet<String>,
+	queue: VecDeque<(u64, Pending)>,	// 尚未确认的消息及其序号
+	bytes: usize,						// 队列中消息的大小
+	over_soft_since: Option<Instant>,	// 队列开始超过软限制的时间
+	delivered: u64,						// 最近一次拉取返回的消息的序号
+	seq_tx: watch::Sender<u64>,			// 最后一条消息的序号，用于唤醒正在等待的拉取
+	last_poll: Instant,
+}
+
+impl Subscriber {
+	fn new() -> Subscriber {
+		Subscriber {
+			channels: HashSet::new(),
+			patterns: HashSet::new(),
+			queue: VecDeque::new(),
+			bytes: 0,
+			over_soft_since: None,
+			delivered: 0,
+			seq_tx: watch::channel(0).0,
+			last_poll: Instant::now(),
+		}
+	}
+
+	fn count(&self) -> usize {
+		self.channels.len() + self.patterns.len()
+	}
+
+	fn enqueue(&mut self, seq: u64, pending: Pending) {
+		if let Pending::Message(message) = &pending {
+			self.bytes += message.len();
+		}
+		self.queue.push_back((seq, pending));
+		self.seq_tx.send_replace(seq);
+	}
+
+	// 将丢弃的消息计入队尾的通知，队尾没有通知或者通知已经被客户端拉取过时放入新的通知
+	fn drop_messages(&mut self, next_seq: &mut u64, count: u64) {
+		if let Some((seq, Pending::Dropped(num))) = self.queue.back_mut() {
+			if *seq > self.delivered {
+				*num += count;
+				return;
+			}
+		}
+		*next_seq += 1;
+		self.enqueue(*next_seq, Pending::Dropped(count));
+	}
+
+	fn update_soft_limit(&mut self, limits: &BufferLimits) {
+		match limits.soft > 0 && self.bytes > limits.soft {
+			true => {
+				self.over_soft_since.get_or_insert_with(Instant::now);
+			},
+			false => self.over_soft_since = None,
+		}
+	}
+
+	fn is_slow(&self, limits: &BufferLimits) -> bool {
+		self.over_soft_since.is_some_and(|since| since.elapsed() > limits.soft_duration)
+	}
+}
+
+// 拉取消息的结果
+pub enum Poll {
+	Message(u64, String),
+	Wait(watch::Receiver<u64>),		// 暂时没有消息，receiver 变化后再次拉取
+	NotSubscribed,
+	Disconnected,					// 会话因为超过缓冲区限制而被断开
+}
+
+// 代理上所有会话的订阅，消息按会话排队直到会话确认，因此两次拉取之间的消息不会丢失
+// 消息的格式与 mini-redis 相同，为 "message\n<channel>\n<payload>" 或 "pmessage\n<pattern>\n<channel>\n<payload>"，
+// 丢弃消息的通知为 "dropped\n<count>"
+#[derive(Default)]
+pub struct PubSub {
+	channels: HashMap<String, HashSet<String>>,		// 频道到订阅的会话
+	patterns: HashMap<String, HashSet<String>>,		// 模式到订阅的会话
+	subscribers: HashMap<String, Subscriber>,
+	next_seq: u64,									// 所有会话共用，保证过期的确认不会覆盖新的消息
+	limits: BufferLimits,
+	disconnected: HashMap<String, Instant>,			// 最近因为处理过慢被断开的会话，在下一次拉取时告知
+	stats: PubSubStats,
+}
+
+impl PubSub {
+	pub fn new(limits: BufferLimits) -> PubSub {
+		PubSub {
+			limits,
+			..PubSub::default()
+		}
+	}
+
+	pub fn stats(&self) -> PubSubStats {
+		self.stats
+	}
+
+	// 为会话订阅频道（或模式），返回该会话的订阅数量，以及之前没有订阅者的频道（或模式）
+	pub fn subscribe(&mut self, session: &str, names: &[String], pattern: bool) -> (usize, Vec<String>) {
+		self.disconnected.remove(session);
+		let subscriber = self.subscribers.entry(session.to_string()).or_insert_with(Subscriber::new);
+		let (mine, all) = match pattern {
+			true => (&mut subscriber.patterns, &mut self.patterns),
+			false => (&mut subscriber.channels, &mut self.channels),
+		};
+		let mut created = Vec::new();
+		for name in names {
+			mine.insert(name.clone());
+			all.entry(name.clone())
+				.or_insert_with(|| {
+					created.push(name.clone());
+					HashSet::new()
+				})
+				.insert(session.to_string());
+		}
+		(subscriber.count(), created)
+	}
+
+	// 取消会话对频道（或模式）的订阅，未指定时取消所有的订阅，
+	// 返回该会话剩余的订阅数量，以及不再有订阅者的频道（或模式）
+	// 会话没有任何订阅时，连同未确认的消息一起删除
+	pub fn unsubscribe(&mut self, session: &str, names: &[String], pattern: bool) -> (usize, Vec<String>) {
+		let Some(subscriber) = self.subscribers.get_mut(session) else {
+			return (0, Vec::new());
+		};
+		let (mine, all) = match pattern {
+			true => (&mut subscriber.patterns, &mut self.patterns),
+			false => (&mut subscriber.channels, &mut self.channels),
+		};
+		let names: Vec<String> = match names.is_empty() {
+			true => mine.iter().cloned().collect(),
+			false => names.to_vec(),
+		};
+		let mut removed = Vec::new();
+		for name in names {
+			if !mine.remove(&name) {
+				continue;
+			}
+			if let Some(sessions) = all.get_mut(&name) {
+				sessions.remove(session);
+				if sessions.is_empty() {
+					all.remove(&name);
+					removed.push(name);
+				}
+			}
+		}
+		let count = subscriber.count();
+		if count == 0 {
+			// 删除 sender 会唤醒该会话正在等待的拉取
+			self.subscribers.remove(session);
+		}
+		(count, removed)
+	}
+
+	// 删除已经不再拉取消息的会话以及持续超过软限制的会话，返回不再有订阅者的频道和模式
+	pub fn sweep(&mut self) -> (Vec<String>, Vec<String>) {
+		let (mut channels, mut patterns) = (Vec::new(), Vec::new());
+		let mut dropped = Vec::new();
+		for (session, subscriber) in self.subscribers.iter() {
+			if subscriber.last_poll.elapsed() > SUBSCRIBER_IDLE {
+				tracing::info!("Drop the idle subscriber {}", session);
+				dropped.push(session.clone());
+			} else if subscriber.is_slow(&self.limits) {
+				tracing::warn!("Disconnect the slow subscriber {} with {} bytes pending", session, subscriber.bytes);
+				self.stats.disconnected_subscribers += 1;
+				self.disconnected.insert(session.clone(), Instant::now());
+				dropped.push(session.clone());
+			}
+		}
+		for session in dropped {
+			channels.extend(self.unsubscribe(&session, &[], false).1);
+			patterns.extend(self.unsubscribe(&session, &[], true).1);
+		}
+		// 被断开后不再拉取的会话与空闲的会话一样被遗忘
+		self.disconnected.retain(|_, since| since.elapsed() <= SUBSCRIBER_IDLE);
+		(channels, patterns)
+	}
+
+	// 代理上订阅的所有频道和模式，用于向主节点重新订阅
+	pub fn names(&self) -> (Vec<String>, Vec<String>) {
+		(self.channels.keys().cloned().collect(), self.patterns.keys().cloned().collect())
+	}
+
+	// 将主节点发来的消息放入订阅了该频道（或模式）的会话的队列中
+	pub fn deliver(&mut self, message: &str) {
+		let mut parts = message.splitn(3, '\n');
+		let sessions = match (parts.next(), parts.next()) {
+			(Some("message"), Some(channel)) => self.channels.get(channel),
+			(Some("pmessage"), Some(pattern)) => self.patterns.get(pattern),
+			// 主节点丢弃了发给代理的消息，无法知道丢弃的是哪些频道的消息，因此通知所有的会话
+			(Some("dropped"), Some(count)) => {
+				let count = count.parse().unwrap_or(0);
+				for subscriber in self.subscribers.values_mut() {
+					subscriber.drop_messages(&mut self.next_seq, count);
+				}
+				return;
+			},
+			_ => None,
+		};
+		let sessions: Vec<String> = sessions.map(|sessions| sessions.iter().cloned().collect()).unwrap_or_default();
+		for session in sessions {
+			self.push(&session, message.to_string());
+		}
+	}
+
+	// 至少有一个订阅者的频道，指定模式时只返回与模式匹配的频道
+	pub fn channels(&self, pattern: Option<&str>) -> Vec<String> {
+		let mut channels: Vec<String> = self.channels
+			.keys()
+			.filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+			.cloned()
+			.collect();
+		channels.sort();
+		channels
+	}
+
+	// 每个频道的订阅者数量，不包括模式订阅者
+	pub fn numsub(&self, channels: &[String]) -> Vec<(String, usize)> {
+		channels
+			.iter()
+			.map(|channel| (channel.clone(), self.channels.get(channel).map_or(0, |sessions| sessions.len())))
+			.collect()
+	}
+
+	// 至少有一个订阅者的模式的数量
+	pub fn numpat(&self) -> usize {
+		self.patterns.len()
+	}
+
+	// 至少有一个订阅者的频道的数量
+	pub fn num_channels(&self) -> usize {
+		self.channels.len()
+	}
+
+	// 订阅了频道或模式的会话的数量
+	pub fn num_subscribers(&self) -> usize {
+		self.subscribers.len()
+	}
+
+	// 将消息放入会话的队列，超过硬限制时丢弃该消息
+	fn push(&mut self, session: &str, message: String) {
+		let Some(subscriber) = self.subscribers.get_mut(session) else {
+			return;
+		};
+		if self.limits.hard > 0 && subscriber.bytes + message.len() > self.limits.hard {
+			self.stats.dropped_messages += 1;
+			subscriber.drop_messages(&mut self.next_seq, 1);
+			return;
+		}
+		self.next_seq += 1;
+		subscriber.enqueue(self.next_seq, Pending::Message(message));
+		subscriber.update_soft_limit(&self.limits);
+	}
+
+	// 确认会话序号不超过 ack 的消息，并返回之后的第一条消息
+	pub fn poll(&mut self, session: &str, ack: u64) -> Poll {
+		let Some(subscriber) = self.subscribers.get_mut(session) else {
+			return match self.disconnected.remove(session) {
+				Some(_) => Poll::Disconnected,
+				None => Poll::NotSubscribed,
+			};
+		};
+		subscriber.last_poll = Instant::now();
+		while subscriber.queue.front().is_some_and(|(seq, _)| *seq <= ack) {
+			if let Some((_, Pending::Message(message))) = subscriber.queue.pop_front() {
+				subscriber.bytes -= message.len();
+			}
+		}
+		subscriber.update_soft_limit(&self.limits);
+		let (seq, message) = match subscriber.queue.front() {
+			Some((seq, Pending::Message(message))) => (*seq, message.clone()),
+			Some((seq, Pending::Dropped(num))) => (*seq, format!("dropped\n{}", num)),
+			None => return Poll::Wait(subscriber.seq_tx.subscribe()),
+		};
+		subscriber.delivered = seq;
+		Poll::Message(seq, message)
+	}
+}
+
+// 等待会话在 ack 之后的下一条消息，POLL_TIMEOUT 内没有消息时返回 None
+pub async fn poll(pubsub: &Mutex<PubSub>, session: &str, ack: u64) -> Result<Option<(u64, String)>, Error> {
+	let deadline = tokio::time::Instant::now() + POLL_TIMEOUT;
+	loop {
+		let poll = pubsub.lock().unwrap().poll(session, ack);
+		let mut rx = match poll {
+			Poll::Message(seq, message) => return Ok(Some((seq, message))),
+			Poll::Wait(rx) => rx,
+			Poll::NotSubscribed => return Err(Error::msg("ERR not subscribed")),
+			Poll::Disconnected => return Err(Error::msg("ERR disconnected for staying over the pub/sub output buffer limit")),
+		};
+		// 会话被删除时 receiver 也会返回，由下一次拉取判断
+		if tokio::time::timeout_at(deadline, rx.changed()).await.is_err() {
+			return Ok(None);
+		}
+	}
+}